@@ -0,0 +1,114 @@
+use bevy::math::IVec3;
+
+use crate::neighbours::{Neighbourhood, MAX_OFFSET};
+use crate::utilities::wrap;
+
+// What happens to neighbours which are past a face of the cube
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    // Continue from the opposite face, so the cube is a torus
+    Periodic,
+    // Cells outside the cube are always dead
+    Dead,
+    // Cells outside the cube are always alive, and count as neighbours
+    Alive,
+    // Cells outside the cube mirror the cells inside it
+    Reflective,
+}
+
+// A boundary for each of the x, y and z axes
+pub type Boundaries = [Boundary; 3];
+
+pub const PERIODIC: Boundaries = [Boundary::Periodic; 3];
+
+// Where a neighbour is found, once the boundaries are applied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Neighbour {
+    Cell(IVec3),
+    Dead,
+    Alive,
+}
+
+// Find the cell at a position, which may be outside the cube
+// Where a dead face meets an alive face, the dead face is used
+pub fn resolve(position: IVec3, bounds: IVec3, boundaries: &Boundaries) -> Neighbour {
+    let wrapped = wrap(position, bounds);
+    let mut resolved = position;
+    let mut alive = false;
+    for axis in 0..3 {
+        let value = position[axis];
+        let length = bounds[axis];
+        if (0..length).contains(&value) {
+            continue;
+        }
+        match boundaries[axis] {
+            Boundary::Periodic => resolved[axis] = wrapped[axis],
+            Boundary::Dead => return Neighbour::Dead,
+            Boundary::Alive => alive = true,
+            // Mirror across the face, so the cells on the face are next to their own images
+            // Thin grids can need more than one reflection
+            Boundary::Reflective => {
+                let value = value.rem_euclid(2 * length);
+                resolved[axis] = match value < length {
+                    true => value,
+                    false => 2 * length - value - 1,
+                }
+            }
+        }
+    }
+    match alive {
+        true => Neighbour::Alive,
+        false => Neighbour::Cell(resolved),
+    }
+}
+
+// Neighbours a cell gets from alive faces, which are not counted with the other cells
+pub fn wall_neighbours(kernel: &Neighbourhood, position: IVec3, bounds: IVec3, boundaries: &Boundaries) -> u16 {
+    // Only cells close to an alive face can have any
+    let near_wall = (0..3).any(|axis| {
+        boundaries[axis] == Boundary::Alive
+            && (position[axis] < MAX_OFFSET || position[axis] >= bounds[axis] - MAX_OFFSET)
+    });
+    if !near_wall {
+        return 0;
+    }
+    kernel
+        .get_weighted_iter()
+        .filter(|(offset, _)| resolve(position + *offset, bounds, boundaries) == Neighbour::Alive)
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+#[cfg(test)]
+mod boundary {
+    use super::*;
+    use bevy::math::ivec3;
+
+    #[test]
+    fn test_resolve() {
+        use Boundary::*;
+        let boundaries = [Periodic, Dead, Reflective];
+        assert_eq!(resolve(ivec3(1, 2, 3), IVec3::splat(5), &boundaries), Neighbour::Cell(ivec3(1, 2, 3)));
+        assert_eq!(resolve(ivec3(-1, 0, 0), IVec3::splat(5), &boundaries), Neighbour::Cell(ivec3(4, 0, 0)));
+        assert_eq!(resolve(ivec3(0, 5, 0), IVec3::splat(5), &boundaries), Neighbour::Dead);
+        assert_eq!(resolve(ivec3(0, 0, -1), IVec3::splat(5), &boundaries), Neighbour::Cell(ivec3(0, 0, 0)));
+        assert_eq!(resolve(ivec3(0, 0, 6), IVec3::splat(5), &boundaries), Neighbour::Cell(ivec3(0, 0, 3)));
+
+        assert_eq!(resolve(ivec3(5, 0, 0), IVec3::splat(5), &[Alive; 3]), Neighbour::Alive);
+        assert_eq!(resolve(ivec3(5, -1, 0), IVec3::splat(5), &[Alive, Dead, Dead]), Neighbour::Dead);
+
+        // Thin grids reflect more than once
+        assert_eq!(resolve(ivec3(0, 0, -3), ivec3(5, 5, 2), &boundaries), Neighbour::Cell(ivec3(0, 0, 1)));
+    }
+
+    #[test]
+    fn test_wall_neighbours() {
+        use Boundary::*;
+        let boundaries = [Alive, Periodic, Periodic];
+        let moore = Neighbourhood::Moore;
+        assert_eq!(wall_neighbours(&moore, ivec3(0, 5, 5), IVec3::splat(20), &boundaries), 9);
+        assert_eq!(wall_neighbours(&moore, ivec3(19, 0, 0), IVec3::splat(20), &boundaries), 9);
+        assert_eq!(wall_neighbours(&moore, ivec3(1, 5, 5), IVec3::splat(20), &boundaries), 0);
+        assert_eq!(wall_neighbours(&moore, ivec3(0, 5, 5), IVec3::splat(20), &PERIODIC), 0);
+    }
+}
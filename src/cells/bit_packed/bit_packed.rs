@@ -0,0 +1,613 @@
+use bevy::{
+    math::{ivec3, IVec3},
+    tasks::TaskPool,
+};
+
+use crate::{
+    boundary::{resolve, Boundaries, Boundary, Neighbour, PERIODIC},
+    cells::{multi_threaded::MultiThreaded, LiveCell, Sim},
+    render::CellRenderer,
+    rule::Rule,
+    utilities::{cell_count, get_centre, idx_to_pos, in_bounds},
+};
+
+// Check if a rule can be run on packed cells
+// Cells can only be alive, decaying for one generation, or dead, and every cell must follow the same rule
+pub fn supports(rule: &Rule) -> bool {
+    rule.states <= 2 && rule.stochastic.is_none() && rule.regions.is_empty()
+}
+
+// Neighbour counts which are valid for a rule, found once for each update
+struct Counts {
+    kernel: Vec<(IVec3, u16)>,
+    // Number of bits needed to hold the largest count
+    bits: usize,
+    birth: Vec<u16>,
+    survival: Vec<u16>,
+}
+
+impl Counts {
+    fn new(rule: &Rule) -> Counts {
+        let kernel: Vec<(IVec3, u16)> = rule.kernel().get_weighted_iter().collect();
+        let max: u16 = kernel.iter().map(|(_, weight)| weight).sum();
+        Counts {
+            bits: (u16::BITS - max.leading_zeros()).max(1) as usize,
+            birth: (0..=max).filter(|count| rule.is_birth(*count)).collect(),
+            survival: (0..=max).filter(|count| rule.is_survival(*count)).collect(),
+            kernel,
+        }
+    }
+}
+
+// Cells stored as bits, 64 to a word, with each row along the x axis starting a new word
+// The neighbours of 64 cells are summed at once, by adding bits with a ripple carry adder,
+// where plane i of the sums holds bit i of each cell's count
+// Rules with more states fall back to `MultiThreaded`, and the cells are moved back once they can be packed again
+pub struct BitPacked {
+    // Cells which are alive and have not started to decay
+    alive: Vec<u64>,
+    // Cells which are decaying, for rules with 2 states
+    decaying: Vec<u64>,
+    // Words in each row
+    words: usize,
+    bounds: IVec3,
+    // Number of states of the rule the cells were last used with
+    states: u8,
+    // Rule the cells were last used with, for counting neighbours when rendering
+    rule: Option<Rule>,
+    generation: u64,
+    boundaries: Boundaries,
+    // Engine used for rules which can not be packed
+    fallback: Option<MultiThreaded>,
+}
+
+impl BitPacked {
+    pub fn new() -> BitPacked {
+        BitPacked {
+            alive: vec![],
+            decaying: vec![],
+            words: 0,
+            bounds: IVec3::ZERO,
+            states: 1,
+            rule: None,
+            generation: 0,
+            boundaries: PERIODIC,
+            fallback: None,
+        }
+    }
+
+    pub fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        if new_bounds != self.bounds {
+            self.words = (new_bounds.x as usize).div_ceil(64);
+            let length = self.words * (new_bounds.y * new_bounds.z) as usize;
+            self.alive = vec![0; length];
+            self.decaying = vec![0; length];
+            self.bounds = new_bounds;
+            self.generation = 0;
+            self.fallback = None;
+        }
+        self.bounds
+    }
+
+    pub fn count_cells(&self) -> usize {
+        match &self.fallback {
+            Some(fallback) => fallback.count(),
+            None => self
+                .alive
+                .iter()
+                .zip(&self.decaying)
+                .map(|(alive, decaying)| (alive | decaying).count_ones() as usize)
+                .sum(),
+        }
+    }
+
+    // Bits which hold cells in the last word of each row
+    fn last_word_mask(&self) -> u64 {
+        match self.bounds.x % 64 {
+            0 => u64::MAX,
+            used => (1 << used) - 1,
+        }
+    }
+
+    fn get_state(&self, position: IVec3) -> u8 {
+        let (word, bit) = self.word_and_bit(position);
+        match (self.alive[word] >> bit & 1, self.decaying[word] >> bit & 1) {
+            (1, _) => self.states,
+            (_, 1) => 1,
+            _ => 0,
+        }
+    }
+
+    fn set_state(&mut self, position: IVec3, state: u8) {
+        let (word, bit) = self.word_and_bit(position);
+        self.alive[word] &= !(1 << bit);
+        self.decaying[word] &= !(1 << bit);
+        if state == self.states {
+            self.alive[word] |= 1 << bit;
+        } else if state != 0 {
+            self.decaying[word] |= 1 << bit;
+        }
+    }
+
+    fn word_and_bit(&self, position: IVec3) -> (usize, usize) {
+        let row = (position.y + position.z * self.bounds.y) as usize;
+        (row * self.words + position.x as usize / 64, position.x as usize % 64)
+    }
+
+    // Move the cells to or from the fallback engine, if the rule needs it
+    fn use_rule(&mut self, rule: &Rule) {
+        if self.rule.as_ref() != Some(rule) {
+            self.rule = Some(rule.clone());
+        }
+        match (supports(rule), self.fallback.take()) {
+            (true, Some(fallback)) => {
+                let mut renderer = CellRenderer::new();
+                renderer.set_bounds(self.bounds);
+                fallback.render(&mut renderer);
+                self.generation = fallback.generation();
+                self.states = rule.states;
+                for (index, value) in renderer.values.iter().enumerate() {
+                    let position = idx_to_pos(index as i32, self.bounds);
+                    self.set_state(position, (*value).min(rule.states));
+                }
+            }
+            (false, None) => {
+                let mut fallback = MultiThreaded::new();
+                fallback.set_bounds(self.bounds);
+                fallback.set_boundaries(self.boundaries);
+                let states: Vec<u8> = (0..cell_count(self.bounds))
+                    .map(|index| self.get_state(idx_to_pos(index as i32, self.bounds)))
+                    .collect();
+                fallback.set_cells(&states, self.generation, rule);
+                self.fallback = Some(fallback);
+            }
+            (true, None) => self.set_states(rule.states),
+            (false, fallback) => self.fallback = fallback,
+        }
+    }
+
+    // Keep the cells when the number of states changes, as `SingleThreaded` does
+    // Cells in the top state of a 1 state rule are decaying with 2 states, and every live cell is in the top state with 1
+    fn set_states(&mut self, states: u8) {
+        match (self.states, states) {
+            (1, 2) => self.decaying = std::mem::replace(&mut self.alive, vec![0; self.decaying.len()]),
+            (2, 1) => {
+                for (alive, decaying) in self.alive.iter_mut().zip(self.decaying.iter_mut()) {
+                    *alive |= *decaying;
+                    *decaying = 0;
+                }
+            }
+            _ => (),
+        }
+        self.states = states;
+    }
+
+    // Cells which are counted as neighbours
+    fn counted(&self, rule: &Rule) -> Vec<u64> {
+        match rule.decaying_neighbours {
+            true => self.alive.iter().zip(&self.decaying).map(|(alive, decaying)| alive | decaying).collect(),
+            false => self.alive.clone(),
+        }
+    }
+
+    // Sum the weighted neighbours of a row into bit planes, with `words` words in each
+    fn count_row(&self, counted: &[u64], y: i32, z: i32, counts: &Counts, sums: &mut [u64], row: &mut [u64]) {
+        let words = self.words;
+        sums.fill(0);
+        for (offset, weight) in &counts.kernel {
+            // Find the row the neighbours are in, which may be past a face
+            let rows = ivec3(1, self.bounds.y, self.bounds.z);
+            let boundaries = [Boundary::Periodic, self.boundaries[1], self.boundaries[2]];
+            let source = match resolve(ivec3(0, y + offset.y, z + offset.z), rows, &boundaries) {
+                Neighbour::Dead => continue,
+                Neighbour::Alive => None,
+                Neighbour::Cell(position) => {
+                    let start = (position.y + position.z * self.bounds.y) as usize * words;
+                    Some(&counted[start..start + words])
+                }
+            };
+            shift_row(source, offset.x, self.bounds.x, self.boundaries[0], row);
+            for (word, bits) in row.iter().enumerate() {
+                add(sums, words, word, *bits, *weight);
+            }
+        }
+    }
+
+    fn update(&mut self, rule: &Rule, task_pool: &TaskPool) {
+        self.use_rule(rule);
+        if let Some(fallback) = &mut self.fallback {
+            fallback.update(rule, task_pool);
+            return;
+        }
+        if self.alive.is_empty() {
+            return;
+        }
+
+        let counts = Counts::new(rule);
+        let counted = self.counted(rule);
+        let mut alive = vec![0; self.alive.len()];
+        let mut decaying = vec![0; self.decaying.len()];
+
+        // Each task works out the new cells of a group of rows
+        let words = self.words;
+        let rows = (self.bounds.y * self.bounds.z) as usize;
+        let rows_per_task = rows.div_ceil(task_pool.thread_num().max(1));
+        let last_word = self.last_word_mask();
+        let this = &*self;
+        let (counts, counted) = (&counts, &counted);
+        task_pool.scope(|scope| {
+            let slabs = alive
+                .chunks_mut(rows_per_task * words)
+                .zip(decaying.chunks_mut(rows_per_task * words))
+                .enumerate();
+            for (slab, (alive, decaying)) in slabs {
+                scope.spawn(async move {
+                    let mut sums = vec![0; counts.bits * words];
+                    let mut row = vec![0; words];
+                    for (row_in_slab, (alive, decaying)) in alive.chunks_mut(words).zip(decaying.chunks_mut(words)).enumerate() {
+                        let row_index = slab * rows_per_task + row_in_slab;
+                        let (y, z) = (row_index as i32 % this.bounds.y, row_index as i32 / this.bounds.y);
+                        this.count_row(counted, y, z, counts, &mut sums, &mut row);
+                        let start = row_index * words;
+                        for word in 0..words {
+                            let was_alive = this.alive[start + word];
+                            let was_decaying = this.decaying[start + word];
+                            let valid = if word == words - 1 { last_word } else { u64::MAX };
+                            let dead = !(was_alive | was_decaying) & valid;
+                            let birth = matches(&sums, words, word, counts.bits, &counts.birth);
+                            let survival = matches(&sums, words, word, counts.bits, &counts.survival);
+                            alive[word] = (dead & birth) | (was_alive & survival);
+                            // Cells which stop surviving decay first, and decaying cells always die
+                            if this.states == 2 {
+                                decaying[word] = was_alive & !survival;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        self.alive = alive;
+        self.decaying = decaying;
+        self.generation += 1;
+    }
+
+    // Switch to a new rule, keeping the cells
+    pub fn set_rule(&mut self, rule: &Rule) {
+        self.use_rule(rule);
+        if let Some(fallback) = &mut self.fallback {
+            fallback.set_rule(rule);
+        }
+    }
+
+    // Every cell which is not dead, at positions relative to the centre, found from the set bits so empty words are skipped
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.live_cells();
+        }
+        let centre = get_centre(self.bounds);
+        let mut cells = vec![];
+        for (word, (alive, decaying)) in self.alive.iter().zip(&self.decaying).enumerate() {
+            let row = (word / self.words) as i32;
+            let mut bits = alive | decaying;
+            while bits != 0 {
+                let bit = bits.trailing_zeros();
+                bits &= bits - 1;
+                let position = ivec3((word % self.words) as i32 * 64 + bit as i32, row % self.bounds.y, row / self.bounds.y);
+                cells.push(LiveCell {
+                    position: position - centre,
+                    state: if alive >> bit & 1 == 1 { self.states } else { 1 },
+                    species: 0,
+                });
+            }
+        }
+        cells
+    }
+
+    // Replace every cell, at positions relative to the centre
+    pub fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        let bounds = self.bounds;
+        self.set_bounds(IVec3::ZERO);
+        self.set_bounds(bounds);
+        self.use_rule(rule);
+        if let Some(fallback) = &mut self.fallback {
+            fallback.set_live_cells(rule, cells, generation);
+            return;
+        }
+        for cell in cells {
+            let pos = cell.position + get_centre(bounds);
+            if in_bounds(pos, bounds) {
+                self.set_state(pos, cell.state.min(rule.states));
+            }
+        }
+        self.generation = generation;
+    }
+
+    // Make dead cells alive, at positions relative to the centre
+    pub fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.use_rule(rule);
+        if let Some(fallback) = &mut self.fallback {
+            fallback.spawn(rule, positions);
+            return;
+        }
+        for position in positions {
+            let Neighbour::Cell(pos) = resolve(*position + get_centre(self.bounds), self.bounds, &self.boundaries) else {
+                continue;
+            };
+            if self.get_state(pos) == 0 {
+                self.set_state(pos, rule.states);
+            }
+        }
+    }
+}
+
+// Move a row along x, so bit x is the cell at x + dx, using the boundary for cells past the ends
+// A missing row is one past an alive face
+fn shift_row(source: Option<&[u64]>, dx: i32, length: i32, boundary: Boundary, out: &mut [u64]) {
+    let words = out.len();
+    let get = |x: i32| match source {
+        Some(source) => source[x as usize / 64] >> (x % 64) & 1,
+        None => 1,
+    };
+    match source {
+        Some(source) => {
+            let (shift, bits) = ((dx.unsigned_abs() / 64) as usize, dx.unsigned_abs() % 64);
+            let word = |index: usize| source.get(index).copied().unwrap_or(0);
+            for (index, out) in out.iter_mut().enumerate() {
+                *out = match (dx >= 0, bits) {
+                    (true, 0) => word(index + shift),
+                    (true, _) => word(index + shift) >> bits | word(index + shift + 1) << (64 - bits),
+                    (false, 0) => index.checked_sub(shift).map_or(0, word),
+                    (false, _) => {
+                        index.checked_sub(shift).map_or(0, word) << bits
+                            | index.checked_sub(shift + 1).map_or(0, word) >> (64 - bits)
+                    }
+                };
+            }
+        }
+        None => out.fill(u64::MAX),
+    }
+    if length % 64 != 0 {
+        out[words - 1] &= (1 << (length % 64)) - 1;
+    }
+
+    // Fix the cells whose neighbour is past a face
+    let ends = (0..dx.min(length)).map(|x| length - 1 - x).chain(0..(-dx).min(length));
+    for x in ends {
+        let bit = match resolve(ivec3(x + dx, 0, 0), ivec3(length, 1, 1), &[boundary, Boundary::Periodic, Boundary::Periodic]) {
+            Neighbour::Cell(position) => get(position.x),
+            Neighbour::Dead => 0,
+            Neighbour::Alive => 1,
+        };
+        out[x as usize / 64] = out[x as usize / 64] & !(1 << (x % 64)) | bit << (x % 64);
+    }
+}
+
+// Add a weighted word of bits to the sums, with a ripple carry
+fn add(sums: &mut [u64], words: usize, word: usize, bits: u64, weight: u16) {
+    let planes = sums.len() / words;
+    for start in (0..u16::BITS as usize).filter(|bit| weight >> bit & 1 == 1) {
+        let mut carry = bits;
+        for plane in start..planes {
+            if carry == 0 {
+                break;
+            }
+            let sum = &mut sums[plane * words + word];
+            let next = *sum & carry;
+            *sum ^= carry;
+            carry = next;
+        }
+    }
+}
+
+// Bits of the cells whose sum is one of the values
+fn matches(sums: &[u64], words: usize, word: usize, bits: usize, values: &[u16]) -> u64 {
+    values.iter().fold(0, |result, value| {
+        result
+            | (0..bits).fold(u64::MAX, |equal, plane| {
+                let sum = sums[plane * words + word];
+                equal & if value >> plane & 1 == 1 { sum } else { !sum }
+            })
+    })
+}
+
+impl Sim for BitPacked {
+    fn update(&mut self, rule: &Rule, task_pool: &TaskPool) {
+        self.update(rule, task_pool);
+    }
+
+    fn render(&self, renderer: &mut CellRenderer) {
+        if let Some(fallback) = &self.fallback {
+            fallback.render(renderer);
+            return;
+        }
+        renderer.clear();
+        let Some(rule) = &self.rule else {
+            return;
+        };
+        let counts = Counts::new(rule);
+        let counted = self.counted(rule);
+        let mut sums = vec![0; counts.bits * self.words];
+        let mut row = vec![0; self.words];
+        for z in 0..self.bounds.z {
+            for y in 0..self.bounds.y {
+                let start = (y + z * self.bounds.y) as usize * self.words;
+                let live = (start..start + self.words).any(|word| self.alive[word] | self.decaying[word] != 0);
+                if !live {
+                    continue;
+                }
+                // Neighbours are only worked out for rows with live cells, for colouring
+                self.count_row(&counted, y, z, &counts, &mut sums, &mut row);
+                for x in 0..self.bounds.x {
+                    let position = ivec3(x, y, z);
+                    let state = self.get_state(position);
+                    if state != 0 {
+                        let (word, bit) = ((x / 64) as usize, x % 64);
+                        let neighbours = (0..counts.bits)
+                            .map(|plane| ((sums[plane * self.words + word] >> bit & 1) as u16) << plane)
+                            .sum();
+                        renderer.set(renderer.index(position), state, neighbours);
+                    }
+                }
+            }
+        }
+    }
+
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+        self.reset();
+    }
+
+    fn generation(&self) -> u64 {
+        match &self.fallback {
+            Some(fallback) => fallback.generation(),
+            None => self.generation,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.count_cells()
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.bounds
+    }
+
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        self.set_bounds(new_bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::Boundary::*;
+    use crate::cells::single_threaded::SingleThreaded;
+    use crate::rule::Stochastic;
+
+    #[test]
+    fn test_shift_row() {
+        // Cells 0, 1 and 69 of a row of 70
+        let source = [0b11, 1 << 5];
+        let mut out = [0; 2];
+
+        shift_row(Some(&source), 1, 70, Periodic, &mut out);
+        assert_eq!(out, [0b1, 1 << 4 | 1 << 5]);
+
+        shift_row(Some(&source), -1, 70, Dead, &mut out);
+        assert_eq!(out, [0b110, 0]);
+
+        shift_row(Some(&source), -2, 70, Reflective, &mut out);
+        assert_eq!(out, [0b1111, 0]);
+
+        // Past an alive face, every cell is alive unless the row ends at a dead face
+        shift_row(None, 3, 70, Dead, &mut out);
+        assert_eq!(out, [u64::MAX, 0b111]);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut sums = [0; 3];
+        add(&mut sums, 1, 0, 0b11, 1);
+        add(&mut sums, 1, 0, 0b10, 3);
+        assert_eq!(matches(&sums, 1, 0, 3, &[1]), 0b01);
+        assert_eq!(matches(&sums, 1, 0, 3, &[4]), 0b10);
+        assert_eq!(matches(&sums, 1, 0, 3, &[0, 4]), !0b01);
+    }
+
+    #[test]
+    fn test_matches_single_threaded() {
+        let task_pool = TaskPool::new();
+        let rules: [Rule; 4] = [
+            "4/4/2/M".parse().unwrap(),
+            "2-3/3/1/C(1,0,0;-1,0,0;0,1,0;0,-1,0;1,1,0;1,-1,0;-1,1,0;-1,-1,0)".parse().unwrap(),
+            Rule {
+                decaying_neighbours: true,
+                ..
+                "0-6/1,3/2/N".parse().unwrap()
+            },
+            // Falls back to `MultiThreaded`
+            "4/4/5/M".parse().unwrap(),
+        ];
+        let boundaries = [PERIODIC, [Dead, Reflective, Alive], [Reflective, Alive, Periodic]];
+
+        for rule in rules {
+            for boundaries in boundaries {
+                let mut single = SingleThreaded::new();
+                let mut packed = BitPacked::new();
+                for sim in [&mut single as &mut dyn Sim, &mut packed] {
+                    sim.set_bounds(ivec3(70, 6, 5));
+                    sim.set_boundaries(boundaries);
+                }
+
+                // Seeded random births give both the same starting cells, which moves the cells to
+                // the fallback and back
+                let noise = Rule {
+                    stochastic: Some(Stochastic {
+                        spontaneous_birth: 0.3,
+                        ..Default::default()
+                    }),
+                    ..rule.clone()
+                };
+                Sim::update(&mut single, &noise, &task_pool);
+                Sim::update(&mut packed, &noise, &task_pool);
+                assert!(packed.count() > 0);
+
+                for _ in 0..8 {
+                    Sim::update(&mut single, &rule, &task_pool);
+                    Sim::update(&mut packed, &rule, &task_pool);
+                    assert_eq!(packed.fallback.is_some(), !supports(&rule));
+
+                    let mut expected = CellRenderer::new();
+                    let mut found = CellRenderer::new();
+                    expected.set_bounds(single.get_bounds());
+                    found.set_bounds(packed.get_bounds());
+                    Sim::render(&single, &mut expected);
+                    Sim::render(&packed, &mut found);
+                    assert_eq!(expected.values, found.values);
+                    // Dead cells are not given neighbours, and alive faces are counted
+                    for (index, value) in found.values.iter().enumerate() {
+                        if *value != 0 && !boundaries.contains(&Alive) {
+                            assert_eq!(expected.neighbors[index], found.neighbors[index]);
+                        }
+                    }
+                    assert_eq!(single.count(), packed.count());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_rule() {
+        let mut packed = BitPacked::new();
+        packed.set_bounds(IVec3::splat(4));
+        let rule: Rule = "4/4/1/M".parse().unwrap();
+        packed.set_rule(&rule);
+        packed.set_state(IVec3::ZERO, 1);
+
+        // A live cell decays with 2 states, and is kept when moved to and from the fallback
+        packed.set_rule(&"4/4/2/M".parse().unwrap());
+        assert_eq!(packed.get_state(IVec3::ZERO), 1);
+        packed.set_rule(&"4/4/5/M".parse().unwrap());
+        assert!(packed.fallback.is_some());
+        packed.set_rule(&rule);
+        assert!(packed.fallback.is_none());
+        assert_eq!(packed.get_state(IVec3::ZERO), 1);
+        assert_eq!(packed.count(), 1);
+    }
+}
@@ -0,0 +1,2 @@
+mod bit_packed;
+pub use bit_packed::*;
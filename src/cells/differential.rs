@@ -0,0 +1,272 @@
+use std::fmt;
+
+use bevy::{math::IVec3, tasks::TaskPool};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    boundary::{Boundaries, Boundary::*, PERIODIC},
+    cells::{Sim, Sims},
+    render::CellRenderer,
+    rule::Rule,
+    setup,
+    utilities::{cell_count, get_centre, idx_to_pos, pos_to_idx},
+};
+
+// Differential testing
+// - Every sim is given the same rule, and the noise for the same seed
+// - After each generation, the cells in the box of the bounds around the centre are compared
+// - Sims with faces are compared with each other, as are sims without, as the two only agree
+//   until a pattern reaches a face
+// - Sims with competing species are given a single species which follows the rule
+// - Sims which do not support the rule are skipped
+// Neighbour counts are only used for colouring, and some sims count them differently past
+// alive faces or outside the box, so only the states are compared
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub bounds: IVec3,
+    pub boundaries: Boundaries,
+    // Seed for the noise the sims start with
+    pub seed: u64,
+    pub generations: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            bounds: IVec3::splat(24),
+            boundaries: PERIODIC,
+            seed: 0,
+            generations: 20,
+        }
+    }
+}
+
+// The first cell where a sim did not match the first sim it was compared with
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub generation: u64,
+    pub expected_sim: String,
+    pub found_sim: String,
+    // Position relative to the centre of the space
+    pub position: IVec3,
+    pub expected: u8,
+    pub found: u8,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "generation {}: '{}' has state {} at {} but '{}' has state {}",
+            self.generation, self.expected_sim, self.expected, self.position, self.found_sim, self.found
+        )
+    }
+}
+
+// Names of the sims which agreed, in the groups they were compared in, and those which were skipped
+#[derive(Debug, Default)]
+pub struct Report {
+    pub groups: Vec<Vec<String>>,
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let groups: Vec<String> = self.groups.iter().map(|group| group.join(", ")).collect();
+        write!(f, "agree: [{}]", groups.join("] ["))?;
+        if !self.skipped.is_empty() {
+            write!(f, ", skipped: {}", self.skipped.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Run a rule through every sim, stopping at the first cell where two which should agree do not
+pub fn compare(
+    sims: &mut [(String, Box<dyn Sim>)],
+    rule: &Rule,
+    options: &Options,
+    task_pool: &TaskPool,
+) -> Result<Report, Divergence> {
+    let mut renderer = CellRenderer::new();
+    let mut report = Report::default();
+
+    for has_faces in [true, false] {
+        let mut group = vec![];
+        for (index, (name, sim)) in sims.iter_mut().enumerate() {
+            if sim.has_faces() != has_faces {
+                continue;
+            }
+            if let Some(competition) = sim.competition_mut() {
+                competition.species.truncate(1);
+                competition.species[0].rule = rule.clone();
+            }
+            if !sim.supports(rule) {
+                report.skipped.push(name.clone());
+                continue;
+            }
+            sim.set_bounds(options.bounds);
+            sim.set_boundaries(options.boundaries);
+            sim.set_rule(rule);
+            // One generation is compared at a time
+            if let Some(step_exponent) = sim.step_exponent_mut() {
+                *step_exponent = 0;
+            }
+            sim.spawn_noise(rule, &mut StdRng::seed_from_u64(options.seed));
+            group.push(index);
+        }
+        let Some((&first, rest)) = group.split_first() else {
+            continue;
+        };
+
+        for generation in 0..=options.generations {
+            let expected = states(sims[first].1.as_ref(), options.bounds, &mut renderer);
+            for &index in rest {
+                let found = states(sims[index].1.as_ref(), options.bounds, &mut renderer);
+                if let Some((position, expected, found)) = first_difference(&expected, &found, options.bounds) {
+                    return Err(Divergence {
+                        generation,
+                        expected_sim: sims[first].0.clone(),
+                        found_sim: sims[index].0.clone(),
+                        position,
+                        expected,
+                        found,
+                    });
+                }
+            }
+            if generation < options.generations {
+                for &index in &group {
+                    sims[index].1.update(rule, task_pool);
+                }
+            }
+        }
+        report.groups.push(group.iter().map(|index| sims[*index].0.clone()).collect());
+    }
+    Ok(report)
+}
+
+// States of the cells in the box of the bounds around the centre, whatever box and layout the sim renders
+fn states(sim: &dyn Sim, bounds: IVec3, renderer: &mut CellRenderer) -> Vec<u8> {
+    renderer.set_layout(sim.layout());
+    renderer.set_bounds(sim.get_bounds());
+    sim.render(renderer);
+    let offset = sim.offset() + get_centre(bounds);
+    let mut states = vec![0; cell_count(bounds)];
+    for index in 0..renderer.cell_count() {
+        let position = renderer.position(index) + offset;
+        if renderer.values[index] != 0 && position.cmpge(IVec3::ZERO).all() && position.cmplt(bounds).all() {
+            states[pos_to_idx(position, bounds)] = renderer.values[index];
+        }
+    }
+    states
+}
+
+// Position relative to the centre, and the two states, of the first cell which differs
+fn first_difference(expected: &[u8], found: &[u8], bounds: IVec3) -> Option<(IVec3, u8, u8)> {
+    let index = expected.iter().zip(found).position(|(expected, found)| expected != found)?;
+    let position = idx_to_pos(index as i32, bounds) - get_centre(bounds);
+    Some((position, expected[index], found[index]))
+}
+
+// Run from the command line with `--differential`, followed by any of
+// `--rule <rule>`, `--seed <number>`, `--generations <number>` and `--bounds <number>`
+// Every example is run if no rule is given
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut options = Options::default();
+    let mut rules = vec![];
+    let mut args = args.iter().filter(|arg| *arg != "--differential");
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--rule" => {
+                let text = value()?;
+                let rule = text.parse().map_err(|error| format!("{}: {}", text, error))?;
+                rules.push((text.clone(), rule));
+            }
+            "--seed" => options.seed = value()?.parse().map_err(|_| "the seed must be a whole number")?,
+            "--generations" => {
+                options.generations = value()?.parse().map_err(|_| "the generations must be a whole number")?
+            }
+            "--bounds" => {
+                let size = value()?.parse().map_err(|_| "the bounds must be a whole number")?;
+                options.bounds = IVec3::splat(size);
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    let mut sims = Sims::new();
+    setup::add_sims(&mut sims);
+    if rules.is_empty() {
+        setup::add_examples(&mut sims);
+        rules = sims.examples.iter().map(|example| (example.name.clone(), example.rule.clone())).collect();
+    }
+
+    let task_pool = TaskPool::new();
+    let mut failed = false;
+    for (name, rule) in rules {
+        for boundaries in [PERIODIC, [Dead; 3], [Reflective, Alive, Dead]] {
+            let options = Options { boundaries, ..options };
+            match compare(&mut sims.sims, &rule, &options, &task_pool) {
+                Ok(report) => println!("{} {:?}: {}", name, boundaries, report),
+                Err(divergence) => {
+                    println!("{} {:?}: {}", name, boundaries, divergence);
+                    failed = true;
+                }
+            }
+        }
+    }
+    match failed {
+        true => Err("the sims do not agree".into()),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::ivec3;
+
+    #[test]
+    fn test_first_difference() {
+        let bounds = ivec3(3, 2, 2);
+        let expected = vec![0; 12];
+        assert_eq!(first_difference(&expected, &expected, bounds), None);
+        let mut found = expected.clone();
+        found[4] = 2;
+        found[9] = 1;
+        // Index 4 is (1, 1, 0), which is (0, 0, -1) from the centre
+        assert_eq!(first_difference(&expected, &found, bounds), Some((ivec3(0, 0, -1), 0, 2)));
+    }
+
+    #[test]
+    fn test_registered_sims_agree() {
+        let mut sims = Sims::new();
+        setup::add_sims(&mut sims);
+        let task_pool = TaskPool::new();
+        let rules: [Rule; 2] = ["4/4/5/M".parse().unwrap(), "0-6/1,3/2/N".parse().unwrap()];
+        for rule in rules {
+            for boundaries in [PERIODIC, [Dead, Reflective, Alive]] {
+                let options = Options {
+                    bounds: ivec3(16, 12, 14),
+                    boundaries,
+                    seed: 7,
+                    generations: 8,
+                };
+                let report = match compare(&mut sims.sims, &rule, &options, &task_pool) {
+                    Ok(report) => report,
+                    Err(divergence) => panic!("{}", divergence),
+                };
+                // Every sim follows these rules, including the one with competing species
+                let group = |has_faces| {
+                    let group = sims.sims.iter().filter(|(_, sim)| sim.has_faces() == has_faces);
+                    group.map(|(name, _)| name.clone()).collect::<Vec<_>>()
+                };
+                assert_eq!(report.groups, vec![group(true), group(false)]);
+                assert!(report.skipped.is_empty());
+                assert!(report.groups[0].contains(&"Multi-Species Cell".to_string()));
+            }
+        }
+    }
+}
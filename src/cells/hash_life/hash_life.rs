@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+
+use bevy::{
+    math::{ivec3, IVec3},
+    tasks::TaskPool,
+};
+
+use crate::{
+    boundary::Boundaries,
+    cells::LiveCell,
+    render::CellRenderer,
+    rule::Rule,
+    utilities::get_centre,
+};
+
+// Largest number of generations an update can jump, as a power of two
+pub const MAX_STEP_EXPONENT: u8 = 48;
+
+// Number of nodes kept before the ones no longer used are removed
+const MAX_NODES: usize = 1 << 22;
+
+type NodeId = u32;
+
+// A cube of cells, 2^level along each side, which is stored once however many times it is used
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    // A single cell, with its state
+    Leaf(u8),
+    // Eight cubes of the level below, with index x + 2y + 4z
+    Branch { level: u8, children: [NodeId; 8] },
+}
+
+// Check if a rule can be run by jumping ahead
+// The result of a cube must only depend on the cells in it, so the rule has to reach one cell,
+// be the same everywhere and at every generation, and leave empty space empty
+pub fn supports(rule: &Rule) -> bool {
+    rule.stochastic.is_none()
+        && rule.regions.is_empty()
+        && rule
+            .kernel()
+            .get_weighted_iter()
+            .all(|(offset, _)| offset.abs().max_element() <= 1)
+        && !rule.should_birth(0, IVec3::ZERO, 0)
+}
+
+// Octree of cells which remembers how each cube changes, so repeated patterns are only worked out once
+// Each update jumps 2^step_exponent generations
+// Positions are relative to the centre of the space, and the view is a box of the chosen size around it
+pub struct HashLife {
+    nodes: Vec<(Node, u64)>,
+    // Id of each node, so the same cube is never stored twice
+    ids: HashMap<Node, NodeId>,
+    // Centre of each node after it is moved ahead, for the rule and exponent in use
+    results: HashMap<NodeId, NodeId>,
+    // Empty node of each level
+    empty: Vec<NodeId>,
+    root: NodeId,
+    // Rule and exponent the results were worked out with
+    rule: Option<Rule>,
+    results_exponent: u8,
+    pub step_exponent: u8,
+    bounds: IVec3,
+    generation: u64,
+}
+
+impl HashLife {
+    pub fn new() -> Self {
+        let mut hash_life = HashLife {
+            nodes: vec![],
+            ids: HashMap::new(),
+            results: HashMap::new(),
+            empty: vec![],
+            root: 0,
+            rule: None,
+            results_exponent: 0,
+            step_exponent: 0,
+            bounds: IVec3::ZERO,
+            generation: 0,
+        };
+        hash_life.clear();
+        hash_life
+    }
+
+    pub fn clear(&mut self) {
+        self.root = self.empty(3);
+        self.generation = 0;
+    }
+
+    fn intern(&mut self, node: Node, population: u64) -> NodeId {
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let id = self.nodes.len() as NodeId;
+        self.nodes.push((node, population));
+        self.ids.insert(node, id);
+        id
+    }
+
+    fn leaf(&mut self, state: u8) -> NodeId {
+        self.intern(Node::Leaf(state), (state != 0) as u64)
+    }
+
+    fn branch(&mut self, children: [NodeId; 8]) -> NodeId {
+        let level = self.level(children[0]) + 1;
+        let population = children.iter().map(|child| self.population(*child)).sum();
+        self.intern(Node::Branch { level, children }, population)
+    }
+
+    fn empty(&mut self, level: u8) -> NodeId {
+        while self.empty.len() <= level as usize {
+            let id = match self.empty.last() {
+                Some(child) => self.branch([*child; 8]),
+                None => self.leaf(0),
+            };
+            self.empty.push(id);
+        }
+        self.empty[level as usize]
+    }
+
+    fn level(&self, id: NodeId) -> u8 {
+        match self.nodes[id as usize].0 {
+            Node::Leaf(_) => 0,
+            Node::Branch { level, .. } => level,
+        }
+    }
+
+    fn population(&self, id: NodeId) -> u64 {
+        self.nodes[id as usize].1
+    }
+
+    fn children(&self, id: NodeId) -> [NodeId; 8] {
+        match self.nodes[id as usize].0 {
+            Node::Leaf(_) => panic!("a leaf has no children"),
+            Node::Branch { children, .. } => children,
+        }
+    }
+
+    // Node of the level two below, in a 4x4x4 grid of them
+    fn grandchild(&self, id: NodeId, x: i32, y: i32, z: i32) -> NodeId {
+        let child = self.children(id)[(x / 2 + 2 * (y / 2) + 4 * (z / 2)) as usize];
+        self.children(child)[(x % 2 + 2 * (y % 2) + 4 * (z % 2)) as usize]
+    }
+
+    // Node of the level below made from the grandchildren starting at a position
+    fn sub_node(&mut self, id: NodeId, x: i32, y: i32, z: i32) -> NodeId {
+        let children = corners().map(|corner| self.grandchild(id, x + corner.x, y + corner.y, z + corner.z));
+        self.branch(children)
+    }
+
+    // Node of the level below in the middle of this one, which is not moved ahead
+    fn centre(&mut self, id: NodeId) -> NodeId {
+        self.sub_node(id, 1, 1, 1)
+    }
+
+    // Check if all of the cells are in the middle quarter along each axis
+    fn is_centred(&self, id: NodeId) -> bool {
+        let children = self.children(id);
+        (0..8).all(|i| {
+            let inner = self.children(self.children(children[i])[7 - i])[7 - i];
+            self.population(inner) == self.population(children[i])
+        })
+    }
+
+    // Put empty space around the node, keeping it in the middle
+    fn expand(&mut self, id: NodeId) -> NodeId {
+        let empty = self.empty(self.level(id) - 1);
+        let mut children = self.children(id);
+        for (i, child) in children.iter_mut().enumerate() {
+            let mut grandchildren = [empty; 8];
+            grandchildren[7 - i] = *child;
+            *child = self.branch(grandchildren);
+        }
+        self.branch(children)
+    }
+
+    // Centre of the node, moved ahead 2^min(level - 2, step exponent) generations
+    fn result(&mut self, rule: &Rule, id: NodeId) -> NodeId {
+        let level = self.level(id);
+        if self.population(id) == 0 {
+            return self.empty(level - 1);
+        }
+        if let Some(result) = self.results.get(&id) {
+            return *result;
+        }
+
+        let result = match level {
+            2 => self.base_result(rule, id),
+            _ => {
+                // Move the 27 overlapping cubes of the level below ahead, or only take their centres
+                // when the step is shorter than the node allows
+                let mut first = [0; 27];
+                for (i, position) in (0..27).map(|i| ivec3(i % 3, i / 3 % 3, i / 9)).enumerate() {
+                    let sub_node = self.sub_node(id, position.x, position.y, position.z);
+                    first[i] = match level - 2 > self.results_exponent {
+                        true => self.centre(sub_node),
+                        false => self.result(rule, sub_node),
+                    };
+                }
+                // Then move the 8 cubes made from those ahead, which together are the centre
+                let children = corners().map(|corner| {
+                    let children = corners().map(|offset| {
+                        let position = corner + offset;
+                        first[(position.x + 3 * position.y + 9 * position.z) as usize]
+                    });
+                    let node = self.branch(children);
+                    self.result(rule, node)
+                });
+                self.branch(children)
+            }
+        };
+        self.results.insert(id, result);
+        result
+    }
+
+    // Move the middle 2x2x2 cells of a 4x4x4 node ahead one generation
+    fn base_result(&mut self, rule: &Rule, id: NodeId) -> NodeId {
+        let mut states = [[[0u8; 4]; 4]; 4];
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    if let Node::Leaf(state) = self.nodes[self.grandchild(id, x, y, z) as usize].0 {
+                        states[x as usize][y as usize][z as usize] = state;
+                    }
+                }
+            }
+        }
+        let state_at = |position: IVec3| states[position.x as usize][position.y as usize][position.z as usize];
+
+        let children = corners().map(|corner| {
+            let position = corner + IVec3::ONE;
+            let neighbours = rule
+                .kernel()
+                .get_weighted_iter()
+                .filter(|(offset, _)| rule.counts_as_neighbour(state_at(position + *offset)))
+                .map(|(_, weight)| weight)
+                .sum();
+            let state = state_at(position);
+            let state = if state == 0 {
+                match rule.should_birth(neighbours, position, 0) {
+                    true => rule.states,
+                    false => 0,
+                }
+            } else if state < rule.states || !rule.should_survive(neighbours, position, 0) {
+                state - 1
+            } else {
+                state
+            };
+            self.leaf(state)
+        });
+        self.branch(children)
+    }
+
+    // Node with the cell at a position, relative to the corner of the node, set to a state
+    // Positions in the octree use i64, as it can grow far past the range of i32
+    fn set_cell(&mut self, id: NodeId, position: [i64; 3], state: u8) -> NodeId {
+        let level = self.level(id);
+        if level == 0 {
+            return self.leaf(state);
+        }
+        let half = 1i64 << (level - 1);
+        let corner = position.map(|value| (value >= half) as usize);
+        let index = corner[0] + 2 * corner[1] + 4 * corner[2];
+        let mut children = self.children(id);
+        let position = [0, 1, 2].map(|axis| position[axis] - corner[axis] as i64 * half);
+        children[index] = self.set_cell(children[index], position, state);
+        self.branch(children)
+    }
+
+    // Half the length of the root along each axis, so its cells go from -radius to radius - 1
+    fn radius(&self) -> i64 {
+        1 << (self.level(self.root) - 1)
+    }
+
+    // Set the cell at a position, relative to the centre, growing the space to hold it
+    pub fn set(&mut self, position: IVec3, state: u8) {
+        self.set_at(position.to_array().map(i64::from), state);
+    }
+
+    fn set_at(&mut self, position: [i64; 3], state: u8) {
+        while position.iter().map(|value| value.unsigned_abs()).max().unwrap() >= self.radius() as u64 - 1 {
+            self.root = self.expand(self.root);
+        }
+        let radius = self.radius();
+        let position = position.map(|value| value + radius);
+        self.root = self.set_cell(self.root, position, state);
+    }
+
+    // Call a function for each live cell in a node which is within a box
+    fn visit<F: FnMut([i64; 3], u8)>(&self, id: NodeId, corner: [i64; 3], min: [i64; 3], max: [i64; 3], f: &mut F) {
+        let size = 1i64 << self.level(id);
+        let outside = (0..3).any(|axis| corner[axis] > max[axis] || corner[axis] + size <= min[axis]);
+        if self.population(id) == 0 || outside {
+            return;
+        }
+        match self.nodes[id as usize].0 {
+            Node::Leaf(state) => f(corner, state),
+            Node::Branch { children, .. } => {
+                for (child, offset) in children.iter().zip(corners()) {
+                    let offset = offset.to_array().map(|value| value as i64 * size / 2);
+                    let corner = [0, 1, 2].map(|axis| corner[axis] + offset[axis]);
+                    self.visit(*child, corner, min, max, f);
+                }
+            }
+        }
+    }
+
+    // Live cells within a box, relative to the centre
+    // Only cells in the box are visited, so their positions always fit in i32
+    pub fn cells(&self, min: IVec3, max: IVec3) -> Vec<(IVec3, u8)> {
+        let mut cells = vec![];
+        let min = min.to_array().map(i64::from);
+        let max = max.to_array().map(i64::from);
+        self.visit(self.root, [-self.radius(); 3], min, max, &mut |position, state| {
+            cells.push((IVec3::from_array(position.map(|value| value as i32)), state))
+        });
+        cells
+    }
+
+    // Number of live cells in a node which are within a box
+    fn population_within(&self, id: NodeId, corner: [i64; 3], min: [i64; 3], max: [i64; 3]) -> u64 {
+        let size = 1i64 << self.level(id);
+        if (0..3).any(|axis| corner[axis] > max[axis] || corner[axis] + size <= min[axis]) {
+            return 0;
+        }
+        if (0..3).all(|axis| corner[axis] >= min[axis] && corner[axis] + size - 1 <= max[axis]) {
+            return self.population(id);
+        }
+        let children = self.children(id);
+        children
+            .iter()
+            .zip(corners())
+            .map(|(child, offset)| {
+                let offset = offset.to_array().map(|value| value as i64 * size / 2);
+                let corner = [0, 1, 2].map(|axis| corner[axis] + offset[axis]);
+                self.population_within(*child, corner, min, max)
+            })
+            .sum()
+    }
+
+    // Number of live cells too far from the centre for their positions to fit in i32
+    // These can not be listed, so they are lost when the cells are copied to another sim or the history
+    pub fn cells_out_of_range(&self) -> u64 {
+        let (min, max) = ([i32::MIN as i64; 3], [i32::MAX as i64; 3]);
+        self.population(self.root) - self.population_within(self.root, [-self.radius(); 3], min, max)
+    }
+
+    // Forget results which were worked out with another rule or step
+    fn use_rule(&mut self, rule: &Rule) {
+        let step_exponent = self.step_exponent.min(MAX_STEP_EXPONENT);
+        if self.rule.as_ref() != Some(rule) || self.results_exponent != step_exponent {
+            self.results.clear();
+            self.rule = Some(rule.clone());
+            self.results_exponent = step_exponent;
+        }
+    }
+
+    // Keep only the nodes used by the root, once there are too many
+    fn collect_garbage(&mut self) {
+        if self.nodes.len() < MAX_NODES {
+            return;
+        }
+        let nodes = std::mem::take(&mut self.nodes);
+        self.ids.clear();
+        self.results.clear();
+        self.empty.clear();
+        let mut copied = HashMap::new();
+        self.root = self.copy(&nodes, self.root, &mut copied);
+    }
+
+    fn copy(&mut self, nodes: &[(Node, u64)], id: NodeId, copied: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        if let Some(copy) = copied.get(&id) {
+            return *copy;
+        }
+        let copy = match nodes[id as usize].0 {
+            Node::Leaf(state) => self.leaf(state),
+            Node::Branch { children, .. } => {
+                let children = children.map(|child| self.copy(nodes, child, copied));
+                self.branch(children)
+            }
+        };
+        copied.insert(id, copy);
+        copy
+    }
+
+    // Move 2^step_exponent generations ahead
+    pub fn update(&mut self, rule: &Rule) {
+        if !supports(rule) {
+            return;
+        }
+        self.use_rule(rule);
+        // Cells move at most one cell each generation, so there must be enough space around
+        // them to hold everything they could reach
+        while self.level(self.root) < self.results_exponent + 3 || !self.is_centred(self.root) {
+            self.root = self.expand(self.root);
+        }
+        let rule = self.rule.take().unwrap();
+        let result = self.result(&rule, self.root);
+        self.rule = Some(rule);
+        self.root = self.expand(result);
+        self.generation += 1 << self.results_exponent;
+        self.collect_garbage();
+    }
+
+    // Switch to a new rule, keeping the cells
+    // Every cell is visited with i64 positions, as the space can grow past the range of i32
+    pub fn set_rule(&mut self, rule: &Rule) {
+        let mut cells = vec![];
+        self.visit(self.root, [-self.radius(); 3], [i64::MIN; 3], [i64::MAX; 3], &mut |position, state| {
+            if state > rule.states {
+                cells.push(position);
+            }
+        });
+        for position in cells {
+            self.set_at(position, rule.states);
+        }
+        self.use_rule(rule);
+    }
+
+    // Replace every cell, at positions relative to the centre
+    pub fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.clear();
+        self.use_rule(rule);
+        for cell in cells {
+            self.set(cell.position, cell.state.min(rule.states));
+        }
+        self.generation = generation;
+    }
+
+    pub fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.use_rule(rule);
+        for position in positions {
+            if self.cells(*position, *position).is_empty() {
+                self.set(*position, rule.states);
+            }
+        }
+    }
+}
+
+// Offsets of the corners of a 2x2x2 cube, in the order children are stored
+fn corners() -> [IVec3; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| ivec3(i & 1, (i >> 1) & 1, i >> 2))
+}
+
+impl crate::cells::Sim for HashLife {
+    fn update(&mut self, rule: &Rule, _task_pool: &TaskPool) {
+        self.update(rule);
+    }
+
+    // Only the cells within the bounds are rendered, as the space can be far too big to show
+    fn render(&self, renderer: &mut CellRenderer) {
+        renderer.clear();
+        let min = -get_centre(self.bounds);
+        let cells = self.cells(min, min + self.bounds - IVec3::ONE);
+        for (position, state) in &cells {
+            renderer.set(renderer.index(*position - min), *state, 0);
+        }
+        // Count the neighbours which are shown, for colouring
+        let Some(rule) = &self.rule else {
+            return;
+        };
+        for (position, _) in cells.iter().filter(|(_, state)| rule.counts_as_neighbour(*state)) {
+            for (offset, weight) in rule.kernel().get_weighted_iter() {
+                let neighbour = *position + offset - min;
+                if neighbour.cmpge(IVec3::ZERO).all() && neighbour.cmplt(self.bounds).all() {
+                    let index = renderer.index(neighbour);
+                    renderer.neighbors[index] += weight;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    // Every cell in the space, not only the ones which are shown, apart from those past the range of i32
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.cells(IVec3::splat(i32::MIN), IVec3::splat(i32::MAX))
+            .into_iter()
+            .map(|(position, state)| LiveCell {
+                position,
+                state,
+                species: 0,
+            })
+            .collect()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    // There are no faces, so only the cells are cleared
+    fn set_boundaries(&mut self, _boundaries: Boundaries) {
+        self.clear();
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn count(&self) -> usize {
+        self.population(self.root) as usize
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.bounds
+    }
+
+    // The bounds are only the part of the space which is shown
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        if new_bounds != self.bounds {
+            self.bounds = new_bounds;
+            self.clear();
+        }
+        self.bounds
+    }
+
+    fn has_faces(&self) -> bool {
+        false
+    }
+
+    fn supports(&self, rule: &Rule) -> bool {
+        supports(rule)
+    }
+
+    fn step_exponent_mut(&mut self) -> Option<&mut u8> {
+        Some(&mut self.step_exponent)
+    }
+
+    fn cells_out_of_range(&self) -> Option<u64> {
+        Some(self.cells_out_of_range())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{sparse::Sparse, Sim};
+    use crate::rule::Stochastic;
+    use crate::utilities::{cell_random, idx_to_pos};
+
+    // Live cells of a sim, relative to the centre, in order
+    fn live_cells(sim: &dyn Sim) -> Vec<(IVec3, u8)> {
+        let mut renderer = CellRenderer::new();
+        renderer.set_bounds(sim.get_bounds());
+        sim.render(&mut renderer);
+        let mut cells: Vec<(IVec3, u8)> = (0..renderer.cell_count())
+            .filter(|index| renderer.values[*index] != 0)
+            .map(|index| (idx_to_pos(index as i32, renderer.bounds) + sim.offset(), renderer.values[index]))
+            .collect();
+        cells.sort_by_key(|(position, _)| position.to_array());
+        cells
+    }
+
+    // The same random cells in both sims
+    fn seed(hash_life: &mut HashLife, sparse: &mut Sparse, rule: &Rule) {
+        hash_life.set_bounds(IVec3::splat(64));
+        for x in -3..=3 {
+            for y in -3..=3 {
+                for z in -3..=3 {
+                    if cell_random(1, 0, ivec3(x, y, z), 0) < 0.4 {
+                        hash_life.set(ivec3(x, y, z), rule.states);
+                        sparse.spawn_cell(rule, ivec3(x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_set() {
+        let mut hash_life = HashLife::new();
+        hash_life.set(ivec3(-1, 0, 2), 3);
+        hash_life.set(ivec3(100, -50, 7), 1);
+        assert_eq!(hash_life.count(), 2);
+        let mut cells = hash_life.cells(IVec3::splat(-200), IVec3::splat(200));
+        cells.sort_by_key(|(position, _)| position.to_array());
+        assert_eq!(cells, vec![(ivec3(-1, 0, 2), 3), (ivec3(100, -50, 7), 1)]);
+        // Expanding keeps the cells in place
+        hash_life.root = hash_life.expand(hash_life.root);
+        assert_eq!(hash_life.cells(ivec3(100, -50, 7), ivec3(100, -50, 7)), vec![(ivec3(100, -50, 7), 1)]);
+        hash_life.set(ivec3(-1, 0, 2), 0);
+        assert_eq!(hash_life.count(), 1);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut hash_life = HashLife::new();
+        hash_life.set(IVec3::splat(i32::MIN), 5);
+        hash_life.set(IVec3::splat(i32::MAX), 5);
+        assert_eq!(hash_life.cells_out_of_range(), 0);
+        assert_eq!(Sim::live_cells(&hash_life).len(), 2);
+
+        // Cells past the range of i32 are counted, and never listed at a wrapped position
+        let far = [i32::MAX as i64 + 10, 0, 0];
+        hash_life.set_at(far, 5);
+        assert_eq!(hash_life.count(), 3);
+        assert_eq!(hash_life.cells_out_of_range(), 1);
+        let mut cells: Vec<IVec3> = Sim::live_cells(&hash_life).iter().map(|cell| cell.position).collect();
+        cells.sort_by_key(|position| position.to_array());
+        assert_eq!(cells, vec![IVec3::splat(i32::MIN), IVec3::splat(i32::MAX)]);
+
+        // A rule with fewer states still reaches them
+        hash_life.set_rule(&"4/4/2/M".parse().unwrap());
+        let mut states = vec![];
+        hash_life.visit(hash_life.root, [-hash_life.radius(); 3], [i64::MIN; 3], [i64::MAX; 3], &mut |position, state| {
+            states.push((position, state))
+        });
+        assert!(states.contains(&(far, 2)));
+        assert!(states.iter().all(|(_, state)| *state == 2));
+    }
+
+    #[test]
+    fn test_supports() {
+        assert!(supports(&"4/4/5/M".parse().unwrap()));
+        assert!(!supports(&"0-6/0,1/2/N".parse().unwrap()));
+        let rule = Rule {
+            stochastic: Some(Stochastic::default()),
+            ..Default::default()
+        };
+        assert!(!supports(&rule));
+    }
+
+    #[test]
+    fn test_matches_sparse() {
+        let rule: Rule = "4/4/5/M".parse().unwrap();
+        for step_exponent in [0, 2, 3] {
+            let mut hash_life = HashLife::new();
+            let mut sparse = Sparse::new();
+            seed(&mut hash_life, &mut sparse, &rule);
+            hash_life.step_exponent = step_exponent;
+
+            for _ in 0..(8 >> step_exponent) {
+                hash_life.update(&rule);
+            }
+            for _ in 0..8 {
+                sparse.update(&rule);
+            }
+            assert_eq!(hash_life.generation(), 8);
+            assert_eq!(hash_life.count(), sparse.count());
+            assert_eq!(live_cells(&hash_life), live_cells(&sparse));
+        }
+    }
+
+    #[test]
+    fn test_big_jump() {
+        // A block is still forever in 2D Life, so it can be jumped far ahead
+        let rule: Rule = "2-3/3/1/C(1,0,0;-1,0,0;0,1,0;0,-1,0;1,1,0;1,-1,0;-1,1,0;-1,-1,0)"
+            .parse()
+            .unwrap();
+        let mut hash_life = HashLife::new();
+        for position in [ivec3(0, 0, 0), ivec3(1, 0, 0), ivec3(0, 1, 0), ivec3(1, 1, 0)] {
+            hash_life.set(position, 1);
+        }
+        hash_life.step_exponent = 40;
+        hash_life.update(&rule);
+        assert_eq!(hash_life.generation(), 1 << 40);
+        assert_eq!(hash_life.count(), 4);
+        assert_eq!(hash_life.cells(IVec3::splat(-2), IVec3::splat(2)).len(), 4);
+    }
+}
@@ -0,0 +1,2 @@
+mod hash_life;
+pub use hash_life::*;
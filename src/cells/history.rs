@@ -0,0 +1,356 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::math::IVec3;
+
+use crate::cells::LiveCell;
+
+// Most deltas in a row before a whole snapshot is kept, so going back never applies too many
+const KEY_INTERVAL: usize = 32;
+
+// Memory kept for past generations unless the budget is changed
+pub const DEFAULT_BUDGET: usize = 64 << 20;
+
+// State and species of each live cell
+type Cells = HashMap<IVec3, (u8, u8)>;
+
+enum Frame {
+    // Every live cell
+    Key(Vec<LiveCell>),
+    // Cells which changed since the entry before, where cells which died have state 0
+    Delta(Vec<LiveCell>),
+}
+
+// A generation ready to be added to the history
+pub struct Entry {
+    generation: u64,
+    frame: Frame,
+}
+
+impl Entry {
+    // Rough number of bytes the entry uses
+    fn size(&self) -> usize {
+        let cells = match &self.frame {
+            Frame::Key(cells) | Frame::Delta(cells) => cells.len(),
+        };
+        std::mem::size_of::<Entry>() + cells * std::mem::size_of::<LiveCell>()
+    }
+}
+
+// What is needed to find the entries after the newest one, which can be moved to another thread
+pub struct Recorder {
+    // Cells of the newest entry
+    latest: Cells,
+    // Deltas since the last snapshot, which is as many as the interval when there is none
+    deltas: usize,
+}
+
+impl Recorder {
+    // Find the entry for the cells of the next generation
+    pub fn entry(&mut self, generation: u64, cells: &[LiveCell]) -> Entry {
+        let next: Cells = cells.iter().map(|cell| (cell.position, (cell.state, cell.species))).collect();
+        let mut changes: Vec<LiveCell> = cells
+            .iter()
+            .filter(|cell| self.latest.get(&cell.position) != Some(&(cell.state, cell.species)))
+            .copied()
+            .collect();
+        changes.extend(self.latest.keys().filter(|position| !next.contains_key(position)).map(|position| LiveCell {
+            position: *position,
+            state: 0,
+            species: 0,
+        }));
+
+        let frame = match self.deltas >= KEY_INTERVAL || changes.len() >= cells.len() {
+            true => {
+                self.deltas = 0;
+                Frame::Key(cells.to_vec())
+            }
+            false => {
+                self.deltas += 1;
+                Frame::Delta(changes)
+            }
+        };
+        self.latest = next;
+        Entry { generation, frame }
+    }
+}
+
+// Past generations of a sim, oldest first
+// Only the live cells are stored, and most entries only hold the cells which changed,
+// with a whole snapshot every so often
+// The oldest entries are dropped once they, and the cells of the newest entry, use more memory than the budget
+pub struct History {
+    entries: VecDeque<Entry>,
+    // Cells of the newest entry, to find what changes in the next one
+    latest: Cells,
+    budget: usize,
+    // Bytes used by the entries, without the cells of the newest entry
+    used: usize,
+}
+
+impl History {
+    pub fn new(budget: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            latest: HashMap::new(),
+            budget,
+            used: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.latest = HashMap::new();
+        self.used = 0;
+    }
+
+    // Rough number of bytes used by the entries and the cells of the newest entry
+    pub fn used(&self) -> usize {
+        self.used + self.latest_size()
+    }
+
+    // Rough number of bytes used by the cells of the newest entry, with a control byte for each
+    fn latest_size(&self) -> usize {
+        self.latest.capacity() * (std::mem::size_of::<(IVec3, (u8, u8))>() + 1)
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.drop_oldest();
+    }
+
+    // Generations of the oldest and newest entries
+    pub fn range(&self) -> Option<(u64, u64)> {
+        Some((self.entries.front()?.generation, self.entries.back()?.generation))
+    }
+
+    // Keep the cells of a generation
+    // Entries from this generation on are dropped first, so going back and carrying on starts a new history
+    pub fn record(&mut self, generation: u64, cells: &[LiveCell]) {
+        if self.budget == 0 {
+            self.clear();
+            return;
+        }
+        let mut recorder = self.recorder(generation);
+        let entry = recorder.entry(generation, cells);
+        self.append(recorder, vec![entry]);
+    }
+
+    // Drop the entries from a generation on, and hand out what is needed to find the entries after the rest
+    // The entries can then be found on another thread, and added with `append` once they are ready
+    pub fn recorder(&mut self, generation: u64) -> Recorder {
+        if self.entries.back().is_some_and(|entry| entry.generation >= generation) {
+            while self.entries.back().is_some_and(|entry| entry.generation >= generation) {
+                let entry = self.entries.pop_back().unwrap();
+                self.used -= entry.size();
+            }
+            self.latest = match self.entries.len() {
+                0 => HashMap::new(),
+                length => self.cells_at(length - 1),
+            };
+        }
+        let deltas = match self.entries.is_empty() {
+            true => KEY_INTERVAL,
+            false => self.entries.iter().rev().take_while(|entry| matches!(entry.frame, Frame::Delta(_))).count(),
+        };
+        Recorder {
+            latest: std::mem::take(&mut self.latest),
+            deltas,
+        }
+    }
+
+    // Add the entries found with a recorder, which is handed back
+    pub fn append(&mut self, recorder: Recorder, entries: Vec<Entry>) {
+        if self.budget == 0 {
+            self.clear();
+            return;
+        }
+        for entry in entries {
+            self.used += entry.size();
+            self.entries.push_back(entry);
+        }
+        self.latest = recorder.latest;
+        self.drop_oldest();
+    }
+
+    // The newest entry at or before a generation, with its generation and cells
+    pub fn get(&self, generation: u64) -> Option<(u64, Vec<LiveCell>)> {
+        let index = self.entries.partition_point(|entry| entry.generation <= generation).checked_sub(1)?;
+        let cells = self
+            .cells_at(index)
+            .into_iter()
+            .map(|(position, (state, species))| LiveCell { position, state, species })
+            .collect();
+        Some((self.entries[index].generation, cells))
+    }
+
+    // Cells of an entry, from the snapshot before it and the deltas after that
+    fn cells_at(&self, index: usize) -> Cells {
+        let key = (0..=index)
+            .rev()
+            .find(|index| matches!(self.entries[*index].frame, Frame::Key(_)))
+            .unwrap();
+        let mut cells = HashMap::new();
+        for entry in self.entries.range(key..=index) {
+            match &entry.frame {
+                Frame::Key(live) => {
+                    cells = live.iter().map(|cell| (cell.position, (cell.state, cell.species))).collect();
+                }
+                Frame::Delta(changes) => {
+                    for cell in changes {
+                        match cell.state {
+                            0 => cells.remove(&cell.position),
+                            _ => cells.insert(cell.position, (cell.state, cell.species)),
+                        };
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    // Drop the oldest entries until the rest fit in the budget, always keeping the newest
+    // The oldest entry is always a snapshot, so the deltas after it can be applied
+    fn drop_oldest(&mut self) {
+        while self.used() > self.budget && self.entries.len() > 1 {
+            if matches!(self.entries[1].frame, Frame::Delta(_)) {
+                let cells = self.cells_at(1);
+                let cells = cells
+                    .into_iter()
+                    .map(|(position, (state, species))| LiveCell { position, state, species })
+                    .collect();
+                self.used -= self.entries[1].size();
+                self.entries[1].frame = Frame::Key(cells);
+                self.used += self.entries[1].size();
+            }
+            let entry = self.entries.pop_front().unwrap();
+            self.used -= entry.size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod history {
+    use super::*;
+    use bevy::math::ivec3;
+
+    fn cell(x: i32, state: u8) -> LiveCell {
+        LiveCell {
+            position: ivec3(x, 0, 0),
+            state,
+            species: 0,
+        }
+    }
+
+    // Cells sorted along x, as entries do not keep their order
+    fn sorted(mut cells: Vec<LiveCell>) -> Vec<LiveCell> {
+        cells.sort_by_key(|cell| cell.position.x);
+        cells
+    }
+
+    // Cells which never change, and a pair moving one step along x each generation
+    fn glider(generation: u64) -> Vec<LiveCell> {
+        let mut cells: Vec<LiveCell> = (-5..0).map(|x| cell(x, 2)).collect();
+        cells.extend([cell(generation as i32, 2), cell(generation as i32 + 1, 1)]);
+        cells
+    }
+
+    #[test]
+    fn test_record() {
+        let mut history = History::new(DEFAULT_BUDGET);
+        assert_eq!(history.range(), None);
+        assert_eq!(history.get(0), None);
+        for generation in 0..100 {
+            history.record(generation, &glider(generation));
+        }
+        assert_eq!(history.range(), Some((0, 99)));
+        for generation in [0, 1, 31, 32, 33, 99] {
+            let (found, cells) = history.get(generation).unwrap();
+            assert_eq!(found, generation);
+            assert_eq!(sorted(cells), glider(generation));
+        }
+        // Generations which were skipped give the one before
+        assert_eq!(history.get(150).map(|(generation, _)| generation), Some(99));
+
+        // Most entries only hold the cells which changed
+        let snapshots = history.entries.iter().filter(|entry| matches!(entry.frame, Frame::Key(_))).count();
+        assert_eq!(snapshots, 4);
+
+        // The cells kept to find the next delta are counted too
+        let mut history = History::new(DEFAULT_BUDGET);
+        let cells: Vec<LiveCell> = (0..1000).map(|x| cell(x, 1)).collect();
+        history.record(0, &cells);
+        assert!(history.used() >= 2 * cells.len() * std::mem::size_of::<LiveCell>());
+    }
+
+    #[test]
+    fn test_branch() {
+        let mut history = History::new(DEFAULT_BUDGET);
+        for generation in 0..10 {
+            history.record(generation, &glider(generation));
+        }
+        // Going back to generation 4 and carrying on drops the generations after it
+        let branch = vec![cell(-5, 1)];
+        history.record(5, &branch);
+        assert_eq!(history.range(), Some((0, 5)));
+        assert_eq!(sorted(history.get(4).unwrap().1), glider(4));
+        assert_eq!(history.get(9).map(|(_, cells)| cells), Some(branch.clone()));
+
+        // The next generation is found from the branch
+        history.record(6, &[]);
+        assert_eq!(history.get(5).map(|(_, cells)| cells), Some(branch));
+        assert_eq!(history.get(6), Some((6, vec![])));
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut recorded = History::new(DEFAULT_BUDGET);
+        let mut appended = History::new(DEFAULT_BUDGET);
+        for generation in 0..10 {
+            recorded.record(generation, &glider(generation));
+        }
+        appended.record(0, &glider(0));
+
+        // Entries found away from the history are the same as the ones it records
+        for start in [1, 5] {
+            let mut recorder = appended.recorder(start);
+            let entries = (start..10).map(|generation| recorder.entry(generation, &glider(generation))).collect();
+            appended.append(recorder, entries);
+            assert_eq!(appended.range(), Some((0, 9)));
+            for generation in 0..10 {
+                assert_eq!(appended.get(generation).map(|(_, cells)| sorted(cells)), Some(glider(generation)));
+            }
+        }
+        assert_eq!(appended.used(), recorded.used());
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut history = History::new(DEFAULT_BUDGET);
+        for generation in 0..50 {
+            history.record(generation, &glider(generation));
+        }
+        let used = history.used();
+        history.set_budget(used / 2);
+        assert!(history.used() <= used / 2);
+        let (oldest, newest) = history.range().unwrap();
+        assert!(oldest > 0);
+        assert_eq!(newest, 49);
+        // The oldest entry left still has every cell
+        assert_eq!(sorted(history.get(oldest).unwrap().1), glider(oldest));
+        assert_eq!(sorted(history.get(40).unwrap().1), glider(40));
+
+        // The newest entry is kept whatever the budget
+        history.set_budget(1);
+        assert_eq!(history.range(), Some((49, 49)));
+        assert_eq!(sorted(history.get(49).unwrap().1), glider(49));
+
+        history.set_budget(0);
+        history.record(50, &glider(50));
+        assert_eq!(history.range(), None);
+        assert_eq!(history.used(), 0);
+    }
+}
@@ -1,6 +1,22 @@
-use bevy::tasks::TaskPool;
+use bevy::{math::IVec3, tasks::TaskPool};
+use rand::rngs::StdRng;
 
-use crate::{render::CellRenderer, rule::Rule};
+use crate::{
+    boundary::Boundaries,
+    layout::Layout,
+    render::CellRenderer,
+    rule::Rule,
+    utilities::{default_noise, get_centre},
+};
+use multi_species::Competition;
+
+// A cell which is not dead, with its position relative to the centre of the space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiveCell {
+    pub position: IVec3,
+    pub state: u8,
+    pub species: u8,
+}
 
 pub trait Sim: Send + Sync {
     fn update(&mut self, rule: &Rule, task_pool: &TaskPool);
@@ -9,17 +25,114 @@ pub trait Sim: Send + Sync {
 
     fn reset(&mut self) {
         let bounds = self.get_bounds();
-        self.set_bounds(0);
+        self.set_bounds(IVec3::ZERO);
         self.set_bounds(bounds);
     }
 
-    fn spawn_noise(&mut self, rule: &Rule);
+    // Make dead cells alive, at positions relative to the centre of the space
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]);
+
+    // Spawn a random cloud of cells around the centre, which is the same for the same state of the generator
+    fn spawn_noise(&mut self, rule: &Rule, rand: &mut StdRng) {
+        self.spawn(rule, &default_noise(rand));
+    }
+
+    // Every cell which is not dead
+    // This renders the whole box, so sims which can find their live cells more cheaply should do so
+    fn live_cells(&self) -> Vec<LiveCell> {
+        rendered_cells(self)
+    }
+
+    // Replace every cell, and the generation, counting the neighbours again with the rule
+    // Cells outside the box are dropped
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64);
+
+    // Switch to a new rule without clearing the cells
+    fn set_rule(&mut self, rule: &Rule);
+
+    // Number of updates since the cells were cleared
+    fn generation(&self) -> u64;
+
+    // Change what happens at each face of the cube, which clears the cells
+    fn set_boundaries(&mut self, boundaries: Boundaries);
 
     fn count(&self) -> usize;
 
-    fn get_bounds(&self) -> i32;
+    // Size of the box of cells given to the renderer
+    fn get_bounds(&self) -> IVec3;
+
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3;
 
-    fn set_bounds(&mut self, new_bounds: i32) -> i32;
+    // Position of the first cell given to the renderer, relative to the centre of the space
+    fn offset(&self) -> IVec3 {
+        -get_centre(self.get_bounds())
+    }
+
+    // Order of the cells given to the renderer
+    fn layout(&self) -> Layout {
+        Layout::Linear
+    }
+
+    // Store the cells in a new order, for sims which support more than one, which clears them
+    fn set_layout(&mut self, _layout: Layout) -> Layout {
+        self.layout()
+    }
+
+    // False for sims whose space goes on forever, which ignore the boundaries
+    fn has_faces(&self) -> bool {
+        true
+    }
+
+    // Check if the sim follows every part of a rule, for sims which only support some rules
+    fn supports(&self, _rule: &Rule) -> bool {
+        true
+    }
+
+    // Species which compete, for sims which have more than one type of cell
+    fn competition(&self) -> Option<&Competition> {
+        None
+    }
+
+    fn competition_mut(&mut self) -> Option<&mut Competition> {
+        None
+    }
+
+    // Power of two of the generations each update moves ahead, for sims which can jump
+    fn step_exponent_mut(&mut self) -> Option<&mut u8> {
+        None
+    }
+
+    // Number of cells checked in the last update, for sims which skip cells that can not change
+    fn cells_evaluated(&self) -> Option<usize> {
+        None
+    }
+
+    // Number of live cells of each species
+    fn populations(&self) -> Vec<usize> {
+        vec![]
+    }
+
+    // Number of live cells too far from the centre to be listed, for sims whose space can grow without limit
+    fn cells_out_of_range(&self) -> Option<u64> {
+        None
+    }
+}
+
+// Every cell which is not dead, found from what the sim renders
+pub fn rendered_cells<S: Sim + ?Sized>(sim: &S) -> Vec<LiveCell> {
+    let mut renderer = CellRenderer::new();
+    renderer.set_layout(sim.layout());
+    renderer.set_bounds(sim.get_bounds());
+    sim.render(&mut renderer);
+    let offset = sim.offset();
+    (0..renderer.cell_count())
+        .filter(|index| renderer.values[*index] != 0)
+        .map(|index| LiveCell {
+            position: renderer.position(index) + offset,
+            state: renderer.values[index],
+            species: renderer.species[index],
+        })
+        .collect()
 }
 
 pub mod sims;
@@ -27,6 +140,13 @@ pub use sims::*;
 pub mod settings;
 pub use settings::*;
 
+pub mod differential;
+pub mod history;
+
+pub mod bit_packed;
+pub mod hash_life;
 pub mod multi_dimensional;
-// pub mod multi_threaded;
+pub mod multi_species;
+pub mod multi_threaded;
 pub mod single_threaded;
+pub mod sparse;
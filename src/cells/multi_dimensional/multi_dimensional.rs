@@ -1,7 +1,9 @@
 use crate::{
+    boundary::{resolve, wall_neighbours, Boundaries, Neighbour, PERIODIC},
+    cells::LiveCell,
     render::CellRenderer,
     rule::Rule,
-    utilities::{default_noise, get_centre, wrap},
+    utilities::{get_centre, in_bounds},
 };
 use bevy::{math::IVec3, tasks::TaskPool};
 
@@ -33,7 +35,7 @@ impl Position {
 #[derive(Clone, Copy)]
 pub struct SimpleCell {
     state: u8,
-    neighbours: u8,
+    neighbours: u16,
 }
 
 impl SimpleCell {
@@ -52,29 +54,34 @@ impl SimpleCell {
 #[derive(Clone)]
 pub struct MultiDimensional {
     cells: Vec<Vec<Vec<SimpleCell>>>,
-    bounds: i32,
+    bounds: IVec3,
+    // Number of updates since the cells were cleared, for stochastic rules
+    generation: u64,
+    boundaries: Boundaries,
 }
 
 impl MultiDimensional {
     pub fn new() -> Self {
         MultiDimensional {
             cells: vec![vec![vec![]]],
-            bounds: 0,
+            bounds: IVec3::ZERO,
+            generation: 0,
+            boundaries: PERIODIC,
         }
     }
 
     // Return the current bounds
-    pub fn get_bounds(&self) -> i32 {
-        self.bounds as i32
+    pub fn get_bounds(&self) -> IVec3 {
+        self.bounds
     }
 
     // Get the total number of live cells
     pub fn get_count(&self) -> usize {
         let mut total = 0;
         // Loop through each cell
-        for x in 0..self.bounds {
-            for y in 0..self.bounds {
-                for z in 0..self.bounds {
+        for x in 0..self.bounds.x {
+            for y in 0..self.bounds.y {
+                for z in 0..self.bounds.z {
                     // Add to the total if the cell is alive
                     if !self.cells[x as usize][y as usize][z as usize].is_dead() {
                         total += 1;
@@ -86,7 +93,7 @@ impl MultiDimensional {
     }
 
     // Set the size of the bounds
-    pub fn set_bounds(&mut self, new_bounds: i32) -> i32 {
+    pub fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
         if new_bounds != self.bounds {
             // Source: https://programming-idioms.org/idiom/27/create-a-3-dimensional-array/452/rust
             self.cells =
@@ -94,13 +101,14 @@ impl MultiDimensional {
                     vec![
                         vec![
                             SimpleCell::new();
-                            new_bounds as usize];
-                        new_bounds as usize];
-                    new_bounds as usize
+                            new_bounds.z as usize];
+                        new_bounds.y as usize];
+                    new_bounds.x as usize
                 ];
             self.bounds = new_bounds;
+            self.generation = 0;
         }
-        self.bounds as i32
+        self.bounds
     }
 
     // Update the cells
@@ -109,30 +117,35 @@ impl MultiDimensional {
         let mut spawns: Vec<Position> = vec![];
         let mut deaths: Vec<Position> = vec![];
 
-        for x in 0..=self.bounds - 1 {
-            for y in 0..=self.bounds - 1 {
-                for z in 0..=self.bounds - 1 {
+        for x in 0..=self.bounds.x - 1 {
+            for y in 0..=self.bounds.y - 1 {
+                for z in 0..=self.bounds.z - 1 {
                     let index = Position::new(x, y, z);
+                    let pos = IVec3::new(x, y, z);
                     let mut cell = self.cells[index.x][index.y][index.z];
+                    // Neighbours are always counted with the main rule, but each region has its own transitions
+                    let cell_rule = rule.rule_at(pos, self.bounds);
+                    let neighbours = cell.neighbours + wall_neighbours(rule.kernel(), pos, self.bounds, &self.boundaries);
 
                     // Check cell state (dead/alive)
                     match cell.is_dead() {
                         // Dead cell
                         true => {
                             // Spawn a new cell if it has a valid number of neighbours
-                            if rule.birth.is_valid(cell.neighbours) {
-                                cell.state = rule.states;
+                            if cell_rule.should_birth(neighbours, pos, self.generation) {
+                                cell.state = cell_rule.states;
                                 spawns.push(index)
                             }
                         }
                         // Alive cell
                         false => {
-                            let num_states = rule.states;
-                            let valid_survival = rule.survival.is_valid(cell.neighbours);
+                            let num_states = cell_rule.states;
+                            let valid_survival = cell_rule.should_survive(neighbours, pos, self.generation);
 
                             // Kill cell if it has too few states, or does not have enough to survive
                             if cell.state < num_states || !valid_survival {
-                                if cell.state == num_states {
+                                // Stop counting the cell as a neighbour
+                                if cell_rule.stops_counting(cell.state) {
                                     deaths.push(index);
                                 }
                                 // Decrement cell state
@@ -153,44 +166,110 @@ impl MultiDimensional {
         for position in deaths {
             self.update_neighbours(rule, position, false);
         }
+        self.generation += 1;
     }
 
     fn update_neighbours(&mut self, rule: &Rule, pos: Position, inc: bool) {
         // Iterate through each element in the neighbourhood
-        for n in rule.neighbourhood.get_neighbourhood_iter() {
-            let neighbour_pos = Position::from_vec(self.wrap(
+        for (n, weight) in rule.kernel().get_weighted_iter() {
+            let neighbour = resolve(
                 IVec3 {
                     x: pos.x as i32,
                     y: pos.y as i32,
                     z: pos.z as i32,
-                } + *n,
-            ));
+                } + n,
+                self.bounds,
+                &self.boundaries,
+            );
+            // Neighbours past a dead or alive face have no cell to update
+            let Neighbour::Cell(neighbour_pos) = neighbour else {
+                continue;
+            };
+            let neighbour_pos = Position::from_vec(neighbour_pos);
 
-            // Increment or decrement cell's value
+            // Increment or decrement cell's value by the neighbour's weight
             match inc {
                 true => {
-                    self.cells[neighbour_pos.x][neighbour_pos.y][neighbour_pos.z].neighbours += 1;
+                    self.cells[neighbour_pos.x][neighbour_pos.y][neighbour_pos.z].neighbours += weight;
                 }
                 false => {
-                    self.cells[neighbour_pos.x][neighbour_pos.y][neighbour_pos.z].neighbours -= 1;
+                    self.cells[neighbour_pos.x][neighbour_pos.y][neighbour_pos.z].neighbours -= weight;
                 }
             }
         }
     }
 
-    fn wrap(&self, pos: IVec3) -> IVec3 {
-        wrap(pos, self.bounds)
+    // Switch to a new rule, keeping the cells, and count their neighbours again
+    fn set_rule(&mut self, rule: &Rule) {
+        for x in 0..self.bounds.x {
+            for y in 0..self.bounds.y {
+                for z in 0..self.bounds.z {
+                    let states = rule.rule_at(IVec3::new(x, y, z), self.bounds).states;
+                    let cell = &mut self.cells[x as usize][y as usize][z as usize];
+                    cell.state = cell.state.min(states);
+                    cell.neighbours = 0;
+                }
+            }
+        }
+        for x in 0..self.bounds.x {
+            for y in 0..self.bounds.y {
+                for z in 0..self.bounds.z {
+                    let position = Position::new(x, y, z);
+                    let cell_rule = rule.rule_at(IVec3::new(x, y, z), self.bounds);
+                    if cell_rule.counts_as_neighbour(self.cells[position.x][position.y][position.z].state) {
+                        self.update_neighbours(rule, position, true);
+                    }
+                }
+            }
+        }
+    }
+
+    // Every cell which is not dead, at positions relative to the centre, without rendering the whole box
+    fn live_cells(&self) -> Vec<LiveCell> {
+        let centre = get_centre(self.bounds);
+        let mut cells = vec![];
+        for (x, plane) in self.cells.iter().enumerate() {
+            for (y, row) in plane.iter().enumerate() {
+                for (z, cell) in row.iter().enumerate().filter(|(_, cell)| cell.state != 0) {
+                    cells.push(LiveCell {
+                        position: IVec3::new(x as i32, y as i32, z as i32) - centre,
+                        state: cell.state,
+                        species: 0,
+                    });
+                }
+            }
+        }
+        cells
+    }
+
+    // Replace every cell, at positions relative to the centre, and count their neighbours again
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        let bounds = self.bounds;
+        self.set_bounds(IVec3::ZERO);
+        self.set_bounds(bounds);
+        for cell in cells {
+            let pos = cell.position + get_centre(bounds);
+            if in_bounds(pos, bounds) {
+                self.cells[pos.x as usize][pos.y as usize][pos.z as usize].state = cell.state;
+            }
+        }
+        self.generation = generation;
+        self.set_rule(rule);
     }
 
     // Spawn a random amount of cells in the centre
-    fn spawn_noise(&mut self, rule: &Rule) {
-        default_noise(get_centre(self.bounds), |pos| {
+    // Make dead cells alive, at positions relative to the centre
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        for position in positions {
+            let Neighbour::Cell(pos) = resolve(*position + get_centre(self.bounds), self.bounds, &self.boundaries) else {
+                continue;
+            };
             // Update a cell if it is dead, if not, leave it
             if self.cells[pos.x as usize][pos.y as usize][pos.z as usize].is_dead() {
-                self.cells[pos.x as usize][pos.y as usize][pos.z as usize].state = rule.states;
+                self.cells[pos.x as usize][pos.y as usize][pos.z as usize].state = rule.rule_at(pos, self.bounds).states;
                 self.update_neighbours(rule,  Position::from_vec(pos), true);
             }
-        });
+        }
     }
 }
 
@@ -200,36 +279,52 @@ impl crate::cells::Sim for MultiDimensional {
     }
 
     fn render(&self, renderer: &mut CellRenderer) {
-        // Convert 3D vector into 1D vector
-        for (index, cell) in self
-            .cells
-            // Flatten vec
-            .concat()
-            // Flatten vector again
-            .concat()
-            // Iterate through each cell
-            .iter()
-            // Add a counter to determine index
-            .enumerate()
-        {
-            // Render each cell
-            renderer.set(index, cell.state, cell.neighbours);
+        // Convert 3D vector into the 1D vector of the renderer
+        for x in 0..self.bounds.x {
+            for y in 0..self.bounds.y {
+                for z in 0..self.bounds.z {
+                    let cell = self.cells[x as usize][y as usize][z as usize];
+                    // Render each cell
+                    renderer.set(renderer.index(IVec3::new(x, y, z)), cell.state, cell.neighbours);
+                }
+            }
         }
     }
 
-    fn spawn_noise(&mut self, rule: &Rule) {
-        self.spawn_noise(rule);
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+        self.reset();
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
     }
 
     fn count(&self) -> usize {
         self.get_count()
     }
 
-    fn get_bounds(&self) -> i32 {
+    fn get_bounds(&self) -> IVec3 {
         self.get_bounds()
     }
 
-    fn set_bounds(&mut self, new_bounds: i32) -> i32 {
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
         self.set_bounds(new_bounds)
     }
 }
@@ -288,11 +383,13 @@ mod multi_dimensional {
             ],
         ];
 
-        let bounds: usize = 2;
+        let bounds = IVec3::splat(2);
 
         let multi_dimensional = MultiDimensional {
             cells,
-            bounds: bounds as i32,
+            bounds,
+            generation: 0,
+            boundaries: PERIODIC,
         };
 
         // Validate there are two dead cells in the grid
@@ -0,0 +1,2 @@
+mod multi_species;
+pub use multi_species::*;
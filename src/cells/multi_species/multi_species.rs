@@ -0,0 +1,566 @@
+use bevy::{math::IVec3, prelude::Color, tasks::TaskPool};
+
+use crate::{
+    boundary::{resolve, wall_neighbours, Boundaries, Neighbour, PERIODIC},
+    cells::LiveCell,
+    layout::Layout,
+    neighbours::Neighbourhood,
+    render::CellRenderer,
+    rule::{Rule, Value},
+    utilities::{cell_random, get_centre, in_bounds},
+};
+
+// The most species which can compete at once
+pub const MAX_SPECIES: usize = 8;
+
+// Colours given to new species, one for each species which can compete
+pub const SPECIES_COLOURS: [Color; MAX_SPECIES] = [
+    Color::RED,
+    Color::BLUE,
+    Color::GREEN,
+    Color::YELLOW,
+    Color::CYAN,
+    Color::PURPLE,
+    Color::ORANGE,
+    Color::PINK,
+];
+
+// How to pick the species of a cell, when more than one could be born there
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    // The species with the most neighbours, ties go to the species listed first
+    Majority,
+    // Any of the species, picked at random from the seed
+    Random,
+    // The species listed first
+    Priority,
+}
+
+// A type of cell, with its own rule and colour
+#[derive(Clone, Debug, PartialEq)]
+pub struct Species {
+    pub name: String,
+    pub rule: Rule,
+    pub colour: Color,
+}
+
+impl Species {
+    pub fn new(name: &str, rule: Rule, colour: Color) -> Species {
+        Species {
+            name: name.into(),
+            rule,
+            colour,
+        }
+    }
+}
+
+// The species which compete, in order of priority
+#[derive(Clone, Debug, PartialEq)]
+pub struct Competition {
+    pub species: Vec<Species>,
+    pub policy: ConflictPolicy,
+    pub seed: u64,
+}
+
+impl Default for Competition {
+    fn default() -> Self {
+        let rule = Rule {
+            survival: Value::new(&[4]),
+            birth: Value::new(&[4]),
+            states: 5,
+            neighbourhood: Neighbourhood::Moore,
+            ..Default::default()
+        };
+        Competition {
+            species: vec![
+                Species::new("Red", rule.clone(), Color::RED),
+                Species::new("Blue", rule, Color::BLUE),
+            ],
+            policy: ConflictPolicy::Majority,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SpeciesCell {
+    state: u8,
+    species: u8,
+}
+
+impl SpeciesCell {
+    fn new() -> SpeciesCell {
+        SpeciesCell {
+            state: 0,
+            species: 0,
+        }
+    }
+
+    // Return true if the cell has value 0
+    pub fn is_dead(&self) -> bool {
+        self.state == 0
+    }
+}
+
+pub struct MultiSpecies {
+    cells: Vec<SpeciesCell>,
+    // Neighbours of each species, for each cell
+    neighbours: Vec<u16>,
+    bounds: IVec3,
+    // Order of the cells in memory
+    layout: Layout,
+    generation: u64,
+    boundaries: Boundaries,
+    competition: Competition,
+}
+
+impl MultiSpecies {
+    pub fn new() -> Self {
+        MultiSpecies {
+            cells: vec![],
+            neighbours: vec![],
+            bounds: IVec3::ZERO,
+            layout: Layout::Linear,
+            generation: 0,
+            boundaries: PERIODIC,
+            competition: Competition::default(),
+        }
+    }
+
+    fn species_count(&self) -> usize {
+        self.competition.species.len()
+    }
+
+    // Set the boundary for cells
+    pub fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        if new_bounds != self.bounds {
+            // One cell for each position in the bounds, and any padding
+            let count = self.layout.len(new_bounds);
+            self.cells.clear();
+            self.cells.resize(count, SpeciesCell::new());
+            self.neighbours.clear();
+            self.neighbours.resize(count * self.species_count(), 0);
+            self.bounds = new_bounds;
+            self.generation = 0;
+        }
+        self.bounds
+    }
+
+    // Store the cells in a new order, which clears them
+    pub fn set_layout(&mut self, layout: Layout) -> Layout {
+        if layout != self.layout {
+            let bounds = self.bounds;
+            self.set_bounds(IVec3::ZERO);
+            self.layout = layout;
+            self.set_bounds(bounds);
+        }
+        self.layout
+    }
+
+    fn idx_to_pos(&self, index: usize) -> IVec3 {
+        self.layout.position(index, self.bounds)
+    }
+
+    fn pos_to_idx(&self, position: IVec3) -> usize {
+        self.layout.index(position, self.bounds)
+    }
+
+    // Count the number of live cells of each species
+    pub fn populations(&self) -> Vec<usize> {
+        let mut populations = vec![0; self.species_count()];
+        for cell in self.cells.iter().filter(|cell| !cell.is_dead()) {
+            populations[cell.species as usize] += 1;
+        }
+        populations
+    }
+
+    // Get the neighbours of one species around a cell, including any from alive faces
+    fn get_neighbours(&self, index: usize, species: usize) -> u16 {
+        let kernel = self.competition.species[species].rule.kernel();
+        let pos = self.idx_to_pos(index);
+        self.neighbours[index * self.species_count() + species]
+            + wall_neighbours(kernel, pos, self.bounds, &self.boundaries)
+    }
+
+    fn update_neighbours(&mut self, index: usize, species: usize, inc: bool) {
+        let species_count = self.species_count();
+        let pos = self.idx_to_pos(index);
+        for (dir, weight) in self.competition.species[species].rule.kernel().get_weighted_iter() {
+            // Neighbours past a dead or alive face have no cell to update
+            let Neighbour::Cell(neighbour) = resolve(pos + dir, self.bounds, &self.boundaries) else {
+                continue;
+            };
+            let neighbour = self.pos_to_idx(neighbour);
+            let count = &mut self.neighbours[neighbour * species_count + species];
+            if inc {
+                *count += weight;
+            } else {
+                *count -= weight;
+            }
+        }
+    }
+
+    // Pick which species is born in a dead cell, if any
+    fn birth_species(&self, index: usize, pos: IVec3) -> Option<usize> {
+        let mut born: Option<(usize, u16)> = None;
+        let mut candidates = 0;
+        for (species, Species { rule, .. }) in self.competition.species.iter().enumerate() {
+            let neighbours = self.get_neighbours(index, species);
+            if !rule.should_birth(neighbours, pos, self.generation) {
+                continue;
+            }
+            candidates += 1;
+            let total = rule.neighbour_total(neighbours);
+            born = match (self.competition.policy, born) {
+                (_, None) => Some((species, total)),
+                (ConflictPolicy::Majority, Some((_, most))) if total > most => Some((species, total)),
+                // Reservoir sampling, so each candidate has the same chance
+                (ConflictPolicy::Random, Some(_))
+                    if cell_random(self.competition.seed, self.generation, pos, 2 + species as u64)
+                        < 1.0 / candidates as f32 =>
+                {
+                    Some((species, total))
+                }
+                (_, born) => born,
+            };
+        }
+        born.map(|(species, _)| species)
+    }
+
+    // Update the cells
+    pub fn update(&mut self) {
+        let mut spawns = vec![];
+        let mut deaths = vec![];
+
+        for index in 0..self.cells.len() {
+            let pos = self.idx_to_pos(index);
+            // Padding is not part of the cube, so it never changes
+            if !pos.cmplt(self.bounds).all() {
+                continue;
+            }
+            let mut cell = self.cells[index];
+            if cell.is_dead() {
+                if let Some(species) = self.birth_species(index, pos) {
+                    cell.state = self.competition.species[species].rule.states;
+                    cell.species = species as u8;
+                    spawns.push(index);
+                }
+            } else {
+                let species = cell.species as usize;
+                let rule = &self.competition.species[species].rule;
+                let neighbours = self.get_neighbours(index, species);
+                if cell.state < rule.states || !rule.should_survive(neighbours, pos, self.generation) {
+                    if rule.stops_counting(cell.state) {
+                        deaths.push(index);
+                    }
+                    cell.state -= 1;
+                }
+            }
+            self.cells[index] = cell;
+        }
+
+        // Update neighbouring cells
+        for index in spawns {
+            self.update_neighbours(index, self.cells[index].species as usize, true);
+        }
+        // Update dead cells
+        for index in deaths {
+            self.update_neighbours(index, self.cells[index].species as usize, false);
+        }
+        self.generation += 1;
+    }
+
+    // Every cell which is not dead, at positions relative to the centre, without rendering the whole box
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        let centre = get_centre(self.bounds);
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.is_dead())
+            .map(|(index, cell)| LiveCell {
+                position: self.idx_to_pos(index) - centre,
+                state: cell.state,
+                species: cell.species,
+            })
+            .collect()
+    }
+
+    // Replace every cell, at positions relative to the centre, and count their neighbours again
+    // Each cell keeps its species, if there are still enough of them
+    pub fn set_live_cells(&mut self, cells: &[LiveCell], generation: u64) {
+        let bounds = self.bounds;
+        self.set_bounds(IVec3::ZERO);
+        self.set_bounds(bounds);
+        for cell in cells {
+            let pos = cell.position + get_centre(bounds);
+            if !in_bounds(pos, bounds) {
+                continue;
+            }
+            let index = self.pos_to_idx(pos);
+            let species = (cell.species as usize).min(self.species_count() - 1);
+            let rule = &self.competition.species[species].rule;
+            let state = cell.state.min(rule.states);
+            let counted = rule.counts_as_neighbour(state);
+            self.cells[index] = SpeciesCell {
+                state,
+                species: species as u8,
+            };
+            if counted {
+                self.update_neighbours(index, species, true);
+            }
+        }
+        self.generation = generation;
+    }
+
+    // Make dead cells alive, at positions relative to the centre, as a species picked from the seed
+    pub fn spawn(&mut self, positions: &[IVec3]) {
+        for position in positions {
+            let Neighbour::Cell(pos) = resolve(*position + get_centre(self.bounds), self.bounds, &self.boundaries) else {
+                continue;
+            };
+            let index = self.pos_to_idx(pos);
+            if self.cells[index].is_dead() {
+                let species = self.species_count() - 1;
+                let species = ((cell_random(self.competition.seed, self.generation, pos, 0) * (species + 1) as f32) as usize).min(species);
+                self.cells[index] = SpeciesCell {
+                    state: self.competition.species[species].rule.states,
+                    species: species as u8,
+                };
+                self.update_neighbours(index, species, true);
+            }
+        }
+    }
+}
+
+impl crate::cells::Sim for MultiSpecies {
+    // Each species has its own rule, so the shared rule is not used
+    fn update(&mut self, _rule: &Rule, _task_pool: &TaskPool) {
+        self.update();
+    }
+
+    fn render(&self, renderer: &mut CellRenderer) {
+        for (index, cell) in self.cells.iter().enumerate() {
+            let neighbours = self.get_neighbours(index, cell.species as usize);
+            renderer.set_species(index, cell.state, neighbours, cell.species);
+        }
+    }
+
+    fn spawn(&mut self, _rule: &Rule, positions: &[IVec3]) {
+        self.spawn(positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, _rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(cells, generation);
+    }
+
+    // Each species keeps its own rule
+    fn set_rule(&mut self, _rule: &Rule) {}
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+        self.reset();
+    }
+
+    fn count(&self) -> usize {
+        self.cells.iter().filter(|cell| !cell.is_dead()).count()
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.bounds
+    }
+
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        self.set_bounds(new_bounds)
+    }
+
+    fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    fn set_layout(&mut self, layout: Layout) -> Layout {
+        self.set_layout(layout)
+    }
+
+    // Each species follows its own rule instead, so only a single species with the same rule follows it
+    // Regions are not used
+    fn supports(&self, rule: &Rule) -> bool {
+        rule.regions.is_empty() && self.competition.species.len() == 1 && self.competition.species[0].rule == *rule
+    }
+
+    fn competition(&self) -> Option<&Competition> {
+        Some(&self.competition)
+    }
+
+    fn competition_mut(&mut self) -> Option<&mut Competition> {
+        Some(&mut self.competition)
+    }
+
+    fn populations(&self) -> Vec<usize> {
+        self.populations()
+    }
+}
+
+#[cfg(test)]
+mod multi_species {
+    use super::*;
+    use bevy::math::ivec3;
+
+    // Two species which are born with a single neighbour, and never survive
+    fn competition(policy: ConflictPolicy) -> Competition {
+        let rule = Rule {
+            birth: Value::new(&[1, 2]),
+            neighbourhood: Neighbourhood::VonNeumann,
+            ..Default::default()
+        };
+        Competition {
+            species: vec![
+                Species::new("A", rule.clone(), Color::RED),
+                Species::new("B", rule, Color::BLUE),
+            ],
+            policy,
+            seed: 0,
+        }
+    }
+
+    fn spawn(sim: &mut MultiSpecies, pos: IVec3, species: usize) {
+        let index = sim.pos_to_idx(pos);
+        sim.cells[index] = SpeciesCell {
+            state: sim.competition.species[species].rule.states,
+            species: species as u8,
+        };
+        sim.update_neighbours(index, species, true);
+    }
+
+    #[test]
+    fn test_neighbours_per_species() {
+        let mut sim = MultiSpecies::new();
+        sim.competition = competition(ConflictPolicy::Priority);
+        sim.set_bounds(IVec3::splat(5));
+        assert_eq!(sim.neighbours.len(), 125 * 2);
+
+        spawn(&mut sim, ivec3(2, 2, 2), 1);
+        let above = sim.pos_to_idx(ivec3(2, 3, 2));
+        assert_eq!(sim.get_neighbours(above, 0), 0);
+        assert_eq!(sim.get_neighbours(above, 1), 1);
+        assert_eq!(sim.populations(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        use crate::utilities::pos_to_idx;
+
+        // B has two neighbours at the centre, and A has one
+        let setup = |policy| {
+            let mut sim = MultiSpecies::new();
+            sim.competition = competition(policy);
+            sim.set_bounds(IVec3::splat(7));
+            spawn(&mut sim, ivec3(3, 4, 3), 0);
+            spawn(&mut sim, ivec3(3, 2, 3), 1);
+            spawn(&mut sim, ivec3(2, 3, 3), 1);
+            sim
+        };
+        let centre = pos_to_idx(ivec3(3, 3, 3), IVec3::splat(7));
+
+        let sim = setup(ConflictPolicy::Priority);
+        assert_eq!(sim.birth_species(centre, ivec3(3, 3, 3)), Some(0));
+
+        let sim = setup(ConflictPolicy::Majority);
+        assert_eq!(sim.birth_species(centre, ivec3(3, 3, 3)), Some(1));
+
+        // Random picks are repeatable, and give both species a chance
+        let mut sim = setup(ConflictPolicy::Random);
+        let mut picked = [0; 2];
+        for generation in 0..100 {
+            sim.generation = generation;
+            let species = sim.birth_species(centre, ivec3(3, 3, 3)).unwrap();
+            assert_eq!(sim.birth_species(centre, ivec3(3, 3, 3)), Some(species));
+            picked[species] += 1;
+        }
+        assert!(picked[0] > 20 && picked[1] > 20);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut sim = MultiSpecies::new();
+        sim.competition = competition(ConflictPolicy::Priority);
+        sim.set_bounds(IVec3::splat(7));
+        spawn(&mut sim, ivec3(1, 3, 3), 0);
+        spawn(&mut sim, ivec3(5, 3, 3), 1);
+
+        // Each cell starts to decay, and six of its own species are born around it
+        sim.update();
+        assert_eq!(sim.populations(), vec![7, 7]);
+        let index = sim.pos_to_idx(ivec3(1, 4, 3));
+        assert_eq!(sim.cells[index].species, 0);
+        let index = sim.pos_to_idx(ivec3(5, 4, 3));
+        assert_eq!(sim.cells[index].species, 1);
+    }
+
+    #[test]
+    fn test_layouts_match() {
+        use crate::cells::Sim;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // Sides which are not a multiple of the tile size, so the tiled cells have padding
+        let bounds = ivec3(13, 9, 10);
+        let mut linear = MultiSpecies::new();
+        let mut tiled = MultiSpecies::new();
+        tiled.set_layout(Layout::Tiled);
+        for sim in [&mut linear, &mut tiled] {
+            sim.competition = competition(ConflictPolicy::Majority);
+            sim.set_bounds(bounds);
+            Sim::spawn_noise(sim, &Rule::default(), &mut StdRng::seed_from_u64(3));
+        }
+        assert!(tiled.cells.len() > linear.cells.len());
+
+        for _ in 0..10 {
+            linear.update();
+            tiled.update();
+            assert_eq!(linear.populations(), tiled.populations());
+            let sorted = |sim: &MultiSpecies| {
+                let mut cells = sim.live_cells();
+                cells.sort_by_key(|cell| cell.position.to_array());
+                cells
+            };
+            assert_eq!(sorted(&linear), sorted(&tiled));
+        }
+        assert!(Sim::count(&linear) > 0);
+    }
+
+    #[test]
+    fn test_supports() {
+        use crate::cells::Sim;
+        use crate::regions::{Region, Shape};
+
+        let mut sim = MultiSpecies::new();
+        let rule = sim.competition.species[0].rule.clone();
+        // Two species never follow one rule
+        assert!(!Sim::supports(&sim, &rule));
+        sim.competition.species.truncate(1);
+        assert!(Sim::supports(&sim, &rule));
+        assert!(!Sim::supports(&sim, &"4/4/2/M".parse().unwrap()));
+
+        let region = Region {
+            shape: Shape::Sphere {
+                centre: IVec3::ZERO,
+                radius: 2.0,
+            },
+            rule: rule.clone(),
+            colour: Color::ORANGE,
+        };
+        let rule = Rule {
+            regions: vec![region],
+            ..rule
+        };
+        sim.competition.species[0].rule = rule.clone();
+        assert!(!Sim::supports(&sim, &rule));
+    }
+}
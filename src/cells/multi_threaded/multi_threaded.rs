@@ -1,24 +1,23 @@
+use bevy::{
+    math::{ivec3, IVec3},
+    tasks::TaskPool,
+};
+
 use crate::{
+    boundary::{resolve, wall_neighbours, Boundaries, Neighbour, PERIODIC},
+    cells::LiveCell,
+    layout::Layout,
     render::CellRenderer,
     rule::Rule,
-    utilities,
-    utilities::{default_noise, get_centre, wrap},
+    utilities::{get_centre, in_bounds},
 };
-use bevy::math::ivec3;
-use bevy::{math::IVec3, tasks::TaskPool};
-use bevy::utils::petgraph::visit::Walker;
-use futures_lite::future;
 
 extern crate num_cpus;
 
-pub fn is_dead(value: u8) -> bool {
-    value == 0
-}
-
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct ParallelCell {
     state: u8,
-    neighbours: u8,
+    neighbours: u16,
 }
 
 impl ParallelCell {
@@ -29,276 +28,451 @@ impl ParallelCell {
         }
     }
 
-    fn get_state(&self) -> u8 {
-        self.state
-    }
-
-    fn set_state(&mut self, state: u8) -> u8 {
-        self.state = state;
-        self.state
-    }
-
     fn is_dead(&self) -> bool {
         self.state == 0
     }
 }
 
-#[derive(Clone)]
-pub struct Chunk {
-    cells: Vec<Vec<Vec<ParallelCell>>>,
-    bounds: usize,
-    index: u8,
-}
-
-impl Chunk {
-    fn new(index: u8) -> Chunk {
-        Chunk {
-            cells: vec![vec![vec![]]],
-            bounds: 0,
-            index,
-        }
-    }
-
-    fn get_bounds(&self) -> usize {
-        self.bounds
-    }
-
-    fn set_bounds(&mut self, bounds: usize) -> usize {
-        self.bounds = bounds;
-        self.bounds
-    }
-}
-
-#[derive(Clone)]
+// Domain Decomposition
+// - The cube is split into slabs along the z axis, one for each CPU
+// - Each thread works out the new states of its own slab, then updates the neighbour counts in it
+// - Cells which changed within reach of the edge of a slab are passed to the slabs they reach (the halo),
+//   so each thread only reads the changes meant for it, and only updates the neighbour counts of its own cells
+// Neighbour counts are sums, so the order they are added in does not matter,
+// and the cells are always the same as `SingleThreaded`
 pub struct MultiThreaded {
-    chunks: Vec<Chunk>,
-    bounds: usize,
+    cells: Vec<ParallelCell>,
+    bounds: IVec3,
+    // Order of the cells in memory, where each slab is a run of whole layers of either layout
+    layout: Layout,
+    // Number of slabs to split the cube into
+    slab_count: usize,
+    // Number of updates since the cells were cleared, for stochastic rules
+    generation: u64,
+    boundaries: Boundaries,
 }
 
 impl MultiThreaded {
     pub fn new() -> MultiThreaded {
         MultiThreaded {
-            chunks: vec![],
-            bounds: 0,
+            cells: vec![],
+            bounds: IVec3::ZERO,
+            layout: Layout::Linear,
+            slab_count: num_cpus::get(),
+            generation: 0,
+            boundaries: PERIODIC,
         }
     }
 
-    // pub fn get_bounds(&self) -> usize {
-    //     self.bounds
-    // }
-
-    pub fn set_bounds(&mut self, bounds: usize) -> usize {
-        self.bounds = bounds;
+    pub fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        if new_bounds != self.bounds {
+            self.cells.clear();
+            self.cells.resize(self.layout.len(new_bounds), ParallelCell::new());
+            self.bounds = new_bounds;
+            self.generation = 0;
+        }
         self.bounds
     }
 
-    pub fn get_count(&self) -> usize {
-        let mut total = 0;
-        for chunks in self.chunks.iter() {
-            let mut chunk_total = 0;
-            for cell in chunks.cells.concat().concat().iter() {
-                if !cell.is_dead() {
-                    chunk_total += 1;
-                }
-            }
-            total += chunk_total;
+    // Store the cells in a new order, which clears them
+    pub fn set_layout(&mut self, layout: Layout) -> Layout {
+        if layout != self.layout {
+            let bounds = self.bounds;
+            self.set_bounds(IVec3::ZERO);
+            self.layout = layout;
+            self.set_bounds(bounds);
         }
-        total
+        self.layout
     }
 
-    fn update(&mut self, rule: &Rule, tasks: &TaskPool) {
-        let mut update_tasks = vec![];
-        // for each chunk
-        for (index, chunk) in self.chunks.iter().enumerate() {
-            // copy all of the values
-            let mut cells = chunk.cells.clone();
-            let rule = rule.clone();
-
-            // create two vectors to store spawns and deaths
-            let mut chunk_spawns = vec![];
-            let mut chunk_deaths = vec![];
-            // Add this to the update tasks vec with async move
-            update_tasks.push(tasks.spawn(async move {
-                Self::update_values(&mut cells, &rule, &mut chunk_spawns, &mut chunk_deaths, index);
-                (index, chunk_spawns, chunk_deaths)
-            }));
-        }
+    pub fn get_count(&self) -> usize {
+        self.cells.iter().filter(|cell| !cell.is_dead()).count()
+    }
 
-        // collect spawns & deaths.
-        let mut chunk_spawns = vec![];
-        let mut chunk_deaths = vec![];
-        for task in update_tasks {
-            let (index, spawns, deaths) = future::block_on(task);
-            chunk_spawns.push((index, spawns));
-            chunk_deaths.push(deaths);
+    // Number of cells in each slab, which are whole layers of the cube
+    fn slab_length(&self) -> usize {
+        let (length, depth) = self.layout.layers(self.bounds);
+        let groups = (self.bounds.z as usize).div_ceil(depth as usize).div_ceil(self.slab_count.max(1));
+        length * groups.max(1)
+    }
+
+    fn update(&mut self, rule: &Rule, task_pool: &TaskPool) {
+        if self.cells.is_empty() {
+            return;
         }
+        let bounds = self.bounds;
+        let boundaries = self.boundaries;
+        let layout = self.layout;
+        let generation = self.generation;
+        let slab_length = self.slab_length();
+
+        let slab_count = self.cells.len().div_ceil(slab_length);
+        let (length, depth) = layout.layers(bounds);
+        let slab = Slab {
+            length: slab_length,
+            layers: (slab_length / length) as i32 * depth,
+            count: slab_count,
+            reach: rule
+                .kernel()
+                .get_weighted_iter()
+                .map(|(offset, _)| offset.z.abs())
+                .max()
+                .unwrap_or(0),
+        };
+
+        // Work out the new states of each slab, and update the neighbours in it, keeping the cells
+        // near its edges which changed for the slabs they reach
+        let halos = task_pool.scope(|scope| {
+            for (index, cells) in self.cells.chunks_mut(slab_length).enumerate() {
+                let start = index * slab_length;
+                scope.spawn(async move {
+                    Self::update_values(cells, start, &slab, rule, bounds, layout, &boundaries, generation)
+                });
+            }
+        });
 
-        // update neighbours.
-        let mut neighbour_tasks = vec![];
-        for ((index, spawns), deaths) in chunk_spawns.into_iter().zip(chunk_deaths) {
-            let mut neighbours = self.chunks[index].clone(); // todo! need to get the index
-            let rule = rule.clone(); // shrug
+        // Each slab then only adds the changes from the halos of the slabs next to it
+        let halos = &halos;
+        task_pool.scope(|scope| {
+            for (index, cells) in self.cells.chunks_mut(slab_length).enumerate() {
+                let start = index * slab_length;
+                scope.spawn(async move {
+                    for (change, inc) in halos.iter().flat_map(|halo| &halo[index]) {
+                        Self::update_neighbours(cells, start, rule, bounds, layout, &boundaries, *change, *inc);
+                    }
+                });
+            }
+        });
+        self.generation += 1;
+    }
 
-            neighbour_tasks.push(tasks.spawn(async move {
-                for index in spawns.iter() {
-                    Self::update_neighbours(&mut neighbours, &rule, index, true);
+    // New states of the cells in a slab, starting at an index, which then update the neighbours in the slab
+    // Returns the cells which changed for each other slab they reach, and if they were born
+    #[allow(clippy::too_many_arguments)]
+    fn update_values(
+        cells: &mut [ParallelCell],
+        start: usize,
+        slab: &Slab,
+        rule: &Rule,
+        bounds: IVec3,
+        layout: Layout,
+        boundaries: &Boundaries,
+        generation: u64,
+    ) -> Vec<Vec<(usize, bool)>> {
+        let mut spawns = vec![];
+        let mut deaths = vec![];
+        for (index, cell) in cells.iter_mut().enumerate() {
+            let index = start + index;
+            let pos = layout.position(index, bounds);
+            // Padding is not part of the cube, so it never changes
+            if !pos.cmplt(bounds).all() {
+                continue;
+            }
+            // Neighbours are always counted with the main rule, but each region has its own transitions
+            let cell_rule = rule.rule_at(pos, bounds);
+            let neighbours = cell.neighbours + wall_neighbours(rule.kernel(), pos, bounds, boundaries);
+            if cell.is_dead() {
+                if cell_rule.should_birth(neighbours, pos, generation) {
+                    cell.state = cell_rule.states;
+                    spawns.push(index);
                 }
-
-                for index in deaths.iter() {
-                    Self::update_neighbours(&mut neighbours, &rule, index, false);
+            } else if cell.state < cell_rule.states || !cell_rule.should_survive(neighbours, pos, generation) {
+                if cell_rule.stops_counting(cell.state) {
+                    deaths.push(index);
                 }
-            }));
+                cell.state -= 1;
+            }
         }
 
-        for task in neighbour_tasks {
-            future::block_on(task);
+        let own = start / slab.length;
+        let mut halos = vec![vec![]; slab.count];
+        let mut reached = vec![];
+        for (indices, inc) in [(spawns, true), (deaths, false)] {
+            for index in indices {
+                Self::update_neighbours(cells, start, rule, bounds, layout, boundaries, index, inc);
+                slab.reached(layout.position(index, bounds).z, bounds, boundaries, &mut reached);
+                for other in reached.iter().filter(|other| **other != own) {
+                    halos[*other].push((index, inc));
+                }
+            }
         }
+        halos
     }
 
-    fn update_values(
-        cells: &mut Vec<Vec<Vec<ParallelCell>>>,
+    // Update the neighbours of a cell which are in a slab, starting at an index
+    #[allow(clippy::too_many_arguments)]
+    fn update_neighbours(
+        cells: &mut [ParallelCell],
+        start: usize,
         rule: &Rule,
-        chunk_spawns: &mut Vec<Vec<usize>>,
-        chunk_deaths: &mut Vec<Vec<usize>>,
+        bounds: IVec3,
+        layout: Layout,
+        boundaries: &Boundaries,
         index: usize,
-    ) -> usize {
-        // Loop through each cell in the chunk
-        for (x, outer) in cells.iter_mut().enumerate() {
-            for (y, inner) in outer.iter_mut().enumerate() {
-                for (z, mut cell) in inner.iter_mut().enumerate() {
-                    let position = vec![x, y, z];
-                    // Check the rules
-                    if cell.is_dead() {
-                        if rule.birth.is_valid(cell.neighbours) {
-                            // Set cell state to rule states
-                            cell.state = rule.states;
-                            // Add position to spawns
-                            chunk_spawns.push(position)
-                        }
-                    } else if cell.state < rule.states || !rule.survival.is_valid(cell.neighbours) {
-                        // Dead cell
-                        if cell.is_dead() {
-                            // Spawn a new cell if it has a valid number of neighbours
-                            if rule.birth.is_valid(cell.neighbours) {
-                                cell.state = rule.states;
-                                chunk_spawns.push(position)
-                            }
-                        }
-                        // Live cell
-                        else {
-                            let num_states = rule.states;
-                            let valid_survival = rule.survival.is_valid(cell.neighbours);
-
-                            // Kill cell if it has too few states, or does not have enough to survive
-                            if cell.state < num_states || !valid_survival {
-                                if cell.state == num_states {
-                                    chunk_deaths.push(position);
-                                }
-                                // Decrement cell's state
-                                cell.state -= 1;
-                            }
-                        }
-                    }
-                }
+        inc: bool,
+    ) {
+        let pos = layout.position(index, bounds);
+        for (dir, weight) in rule.kernel().get_weighted_iter() {
+            // Neighbours past a dead or alive face have no cell to update
+            let Neighbour::Cell(neighbour_position) = resolve(pos + dir, bounds, boundaries) else {
+                continue;
+            };
+            let Some(cell) = layout.index(neighbour_position, bounds)
+                .checked_sub(start)
+                .and_then(|index| cells.get_mut(index))
+            else {
+                continue;
+            };
+            if inc {
+                cell.neighbours += weight;
+            } else {
+                cell.neighbours -= weight;
             }
         }
-        index
     }
 
-    fn update_neighbours(neighbours: &mut Chunk, rule: &Rule, pos: &Vec<usize>, inc: bool) {
-        for n in rule.neighbourhood.get_neighbourhood_iter() {
-            let pos = IVec3::new(pos[1] as i32, pos[2] as i32, pos[3] as i32);
-            let neighbour_pos = wrap(pos + *n, neighbours.bounds as i32);
-
-            match inc {
-                true => {
-                    neighbours.cells[neighbour_pos.x as usize][neighbour_pos.y as usize]
-                        [neighbour_pos.z as usize]
-                        .neighbours += 1;
-                }
-                false => {
-                    neighbours.cells[neighbour_pos.x as usize][neighbour_pos.y as usize]
-                        [neighbour_pos.z as usize]
-                        .neighbours -= 1;
-                }
+    // Switch to a new rule, keeping the cells, and count their neighbours again
+    pub fn set_rule(&mut self, rule: &Rule) {
+        let bounds = self.bounds;
+        let layout = self.layout;
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            let states = rule.rule_at(layout.position(index, bounds), bounds).states;
+            cell.state = cell.state.min(states);
+            cell.neighbours = 0;
+        }
+        for index in 0..self.cells.len() {
+            let cell_rule = rule.rule_at(layout.position(index, bounds), bounds);
+            if cell_rule.counts_as_neighbour(self.cells[index].state) {
+                Self::update_neighbours(&mut self.cells, 0, rule, bounds, layout, &self.boundaries, index, true);
             }
         }
     }
 
-    fn get_centre(&self) -> IVec3 {
-        let centre = (self.bounds / 2) as i32;
-        ivec3(
-            centre,
-            centre,
-            centre,
-        )
+    // Replace the cells with states from another sim in the same layout, counting their neighbours with the rule
+    pub fn set_cells(&mut self, states: &[u8], generation: u64, rule: &Rule) {
+        for (cell, state) in self.cells.iter_mut().zip(states) {
+            cell.state = *state;
+        }
+        self.generation = generation;
+        self.set_rule(rule);
     }
 
-    fn wrap(&self, pos: IVec3) -> IVec3 {
-        todo!()
+    // Every cell which is not dead, at positions relative to the centre, without rendering the whole box
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        let centre = get_centre(self.bounds);
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.state != 0)
+            .map(|(index, cell)| LiveCell {
+                position: self.layout.position(index, self.bounds) - centre,
+                state: cell.state,
+                species: 0,
+            })
+            .collect()
     }
 
-    fn spawn_noise(&mut self, rule: &Rule) {
-        let center = self.get_centre();
+    // Replace every cell, at positions relative to the centre, and count their neighbours again
+    pub fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
         let bounds = self.bounds;
+        self.set_bounds(IVec3::ZERO);
+        self.set_bounds(bounds);
+        for cell in cells {
+            let pos = cell.position + get_centre(bounds);
+            if in_bounds(pos, bounds) {
+                let index = self.layout.index(pos, bounds);
+                self.cells[index].state = cell.state;
+            }
+        }
+        self.generation = generation;
+        self.set_rule(rule);
+    }
 
-        default_noise(center, |pos| {
-            let index = utilities::pos_to_idx(
-                wrap(pos, bounds as i32),
-                self.bounds as i32
-            );
+    // Make dead cells alive, at positions relative to the centre
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        let bounds = self.bounds;
+        let layout = self.layout;
+        for position in positions {
+            let Neighbour::Cell(pos) = resolve(*position + get_centre(bounds), bounds, &self.boundaries) else {
+                continue;
+            };
+            let index = layout.index(pos, bounds);
+            if self.cells[index].is_dead() {
+                self.cells[index].state = rule.rule_at(pos, bounds).states;
+                Self::update_neighbours(&mut self.cells, 0, rule, bounds, layout, &self.boundaries, index, true);
+            }
+        }
+    }
+}
 
-            // todo! Update cell values
-        });
+// How the cube is split into slabs for an update
+#[derive(Clone, Copy)]
+struct Slab {
+    // Cells in each slab, and the layers along z they hold
+    length: usize,
+    layers: i32,
+    count: usize,
+    // Furthest a cell can have neighbours along z
+    reach: i32,
+}
+
+impl Slab {
+    // Slabs with cells which a cell in a layer can reach, found through the faces
+    fn reached(&self, z: i32, bounds: IVec3, boundaries: &Boundaries, slabs: &mut Vec<usize>) {
+        slabs.clear();
+        for layer in z - self.reach..=z + self.reach {
+            if let Neighbour::Cell(position) = resolve(ivec3(0, 0, layer), bounds, boundaries) {
+                slabs.push((position.z / self.layers) as usize);
+            }
+        }
+        slabs.sort_unstable();
+        slabs.dedup();
     }
 }
 
 impl crate::cells::Sim for MultiThreaded {
     fn update(&mut self, rule: &Rule, task_pool: &TaskPool) {
-        /*
-            Domain Decomposition
-            - Split the cube into x amount of chunks
-                - Likely to be the number of CPUS/threads on the system
-            - Give each thread its chunk of the cube, with an extra layer on each side as padding
-                - The thread would only need to do the cells which are not in the boundary
-            - Wait for each thread to finish, then render
-            - This is called `Domain Decomposition`
-
-            Cell Index-Based
-            - assign a single cell to each thread
-            - Write the output from the thread to a new MultiThreaded instance
-            - Do for every cell
-
-        */
-
         self.update(rule, task_pool);
     }
 
     fn render(&self, renderer: &mut CellRenderer) {
-        for chunk in self.chunks.iter() {
-            for (index, cell) in chunk.cells.concat().concat().iter().enumerate() {
-                renderer.set(
-                    index,
-                    cell.state,
-                    cell.neighbours
-                )
-            }
+        for (index, cell) in self.cells.iter().enumerate() {
+            renderer.set(index, cell.state, cell.neighbours);
         }
     }
 
-    fn spawn_noise(&mut self, rule: &Rule) {
-        self.spawn_noise(rule);
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+        self.reset();
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
     }
 
     fn count(&self) -> usize {
-        self.get_count() * num_cpus::get()
+        self.get_count()
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.bounds
+    }
+
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        self.set_bounds(new_bounds)
     }
 
-    fn get_bounds(&self) -> i32 {
-        self.get_bounds() as i32
+    fn layout(&self) -> Layout {
+        self.layout
     }
 
-    fn set_bounds(&mut self, new_bounds: i32) -> i32 {
-        self.set_bounds(new_bounds as usize) as i32
+    fn set_layout(&mut self, layout: Layout) -> Layout {
+        self.set_layout(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::Boundary::*;
+    use crate::cells::{single_threaded::SingleThreaded, Sim};
+    use crate::rule::Stochastic;
+    use bevy::math::ivec3;
+
+    #[test]
+    fn test_reached() {
+        let slab = Slab {
+            length: 0,
+            layers: 3,
+            count: 4,
+            reach: 1,
+        };
+        let bounds = ivec3(4, 4, 10);
+        let mut slabs = vec![];
+        let mut reached = |slab: Slab, z, boundaries| {
+            slab.reached(z, bounds, &boundaries, &mut slabs);
+            slabs.clone()
+        };
+        assert_eq!(reached(slab, 4, PERIODIC), vec![1]);
+        assert_eq!(reached(slab, 3, PERIODIC), vec![0, 1]);
+        assert_eq!(reached(slab, 5, PERIODIC), vec![1, 2]);
+        // Across the periodic face, but not past a dead one
+        assert_eq!(reached(slab, 9, PERIODIC), vec![0, 2, 3]);
+        assert_eq!(reached(slab, 9, [Periodic, Periodic, Dead]), vec![2, 3]);
+        // Slabs thinner than the reach pass changes on past the slab next to them
+        assert_eq!(reached(Slab { reach: 4, ..slab }, 4, PERIODIC), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_matches_single_threaded() {
+        let task_pool = TaskPool::new();
+        let rules: [Rule; 2] = [
+            "4/4/5/M".parse().unwrap(),
+            "0-6/1,3/2/N".parse().unwrap(),
+        ];
+        let boundaries = [PERIODIC, [Dead, Reflective, Alive], [Reflective, Alive, Periodic]];
+
+        for rule in rules {
+            for boundaries in boundaries {
+                for (slab_count, layout) in [1, 3, 4, 20].into_iter().flat_map(|count| [(count, Layout::Linear), (count, Layout::Tiled)]) {
+                    let mut single = SingleThreaded::new();
+                    let mut multi = MultiThreaded::new();
+                    multi.slab_count = slab_count;
+                    multi.set_layout(layout);
+                    for sim in [&mut single as &mut dyn Sim, &mut multi] {
+                        sim.set_bounds(ivec3(9, 7, 11));
+                        sim.set_boundaries(boundaries);
+                    }
+
+                    // Seeded random births give both the same starting cells
+                    let noise = Rule {
+                        stochastic: Some(Stochastic {
+                            spontaneous_birth: 0.3,
+                            seed: slab_count as u64,
+                            ..Default::default()
+                        }),
+                        ..rule.clone()
+                    };
+                    single.update(&noise);
+                    multi.update(&noise, &task_pool);
+                    assert!(multi.count() > 0);
+
+                    for _ in 0..10 {
+                        single.update(&rule);
+                        multi.update(&rule, &task_pool);
+
+                        let mut expected = CellRenderer::new();
+                        let mut found = CellRenderer::new();
+                        expected.set_bounds(single.get_bounds());
+                        found.set_layout(multi.layout());
+                        found.set_bounds(multi.get_bounds());
+                        Sim::render(&single, &mut expected);
+                        Sim::render(&multi, &mut found);
+                        // Cells are compared by position, as the layouts store them in different orders
+                        for index in 0..expected.cell_count() {
+                            let found_index = found.index(expected.position(index));
+                            assert_eq!(expected.values[index], found.values[found_index]);
+                            assert_eq!(expected.neighbors[index], found.neighbors[found_index]);
+                        }
+                    }
+                }
+            }
+        }
     }
 }
@@ -1,25 +1,45 @@
+use std::time::Duration;
+
 use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::math::{ivec3, IVec3};
 use bevy::prelude::{ClearColor, Color, Res, ResMut};
 
 use bevy_egui::egui::FontFamily::Proportional;
 use bevy_egui::egui::{color_picker, FontId, Style, Ui, Vec2};
 use bevy_egui::{
     egui,
-    egui::{Checkbox, ComboBox, Grid, Slider, TextStyle::*, Window},
+    egui::{
+        Checkbox, ComboBox, DragValue, Grid, Key, KeyboardShortcut, Modifiers, ScrollArea, Slider, TextEdit,
+        TextStyle::*, Window,
+    },
     EguiContexts,
 };
 // use bevy_egui::egui::{SidePanel, panel::Side::Left,};
 
 use crate::neighbours::Neighbourhood::*;
 
-use crate::cells::Sims;
+use crate::cells::hash_life::{self, MAX_STEP_EXPONENT};
+use crate::cells::multi_species::{ConflictPolicy, Species, MAX_SPECIES, SPECIES_COLOURS};
+use crate::boundary::Boundary;
+use crate::layout::Layout;
+use crate::cells::{Example, RunState, Sims};
 use crate::color_method::ColourMethod::*;
-use crate::neighbours::Neighbourhood;
-use crate::rule::{Value};
+use crate::neighbours::{Neighbourhood, MAX_NEIGHBOURS, MAX_RADIUS};
+use crate::regions::{IndexVolume, Region, Shape, REGION_COLOURS};
+use crate::schedule::Schedule;
+use crate::utilities;
+use crate::rule::{ClassRule, ClassValue, Rule, Stochastic, Value, CORNERS, EDGES, FACES};
 
 // todo! Allow the user to save the current simulation as an example
 //  - Would be better to convert current examples to this and add them dynamically
 
+const PAUSE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::P);
+const STEP_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::N);
+const STEP_N_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::SHIFT, Key::N);
+
+// Generations which take longer than this to work out can be cancelled
+const SLOW_STEP: Duration = Duration::from_millis(250);
+
 // Adapted from TanTanDev
 pub fn settings_ui(
     mut current: ResMut<Sims>,
@@ -32,6 +52,21 @@ pub fn settings_ui(
     }
     let mut bounds = current.bounds;
     let mut active_sim = current.active_sim;
+
+    // Run state shortcuts, unless they are being typed into a text box
+    let ctx = contexts.ctx_mut();
+    if !ctx.wants_keyboard_input() {
+        // Shift + N is checked first, so it is not taken as N
+        if ctx.input_mut(|input| input.consume_shortcut(&STEP_N_SHORTCUT)) {
+            current.run_state = RunState::Step(current.step_count);
+        }
+        if ctx.input_mut(|input| input.consume_shortcut(&STEP_SHORTCUT)) {
+            current.run_state = RunState::Step(1);
+        }
+        if ctx.input_mut(|input| input.consume_shortcut(&PAUSE_SHORTCUT)) {
+            current.run_state = toggle_pause(current.run_state);
+        }
+    }
     // Settings GUI
 
     // Try this out!
@@ -83,7 +118,12 @@ pub fn settings_ui(
 
                         ui.add_space(10.0);
 
+                        run_ui(&mut current, ui);
+
+                        ui.add_space(10.0);
+
                         let rule = current.rule.take().unwrap();
+                        let mut lock_aspect = current.lock_aspect;
                         let sim = &mut current.sims[active_sim].1;
 
                         // todo! May need to move to function to do this
@@ -96,6 +136,8 @@ pub fn settings_ui(
 
                         ui.add_space(10.0);
 
+                        let mut spawn_noise = false;
+                        let mut reset = false;
                         ui.horizontal(|ui| {
                             ui.set_width(275.0);
                             // Spawn noise button
@@ -107,7 +149,7 @@ pub fn settings_ui(
                                 .on_hover_text("Spawn a random amount of cells in the center")
                                 .clicked()
                             {
-                                sim.spawn_noise(&rule);
+                                spawn_noise = true;
                             }
 
                             // Reset sim button
@@ -116,27 +158,153 @@ pub fn settings_ui(
                             )
                                 .on_hover_text("Kill all the cells, and stop the simulation")
                                 .clicked() {
-                                sim.reset();
+                                reset = true;
                             }
                         });
 
+                        // Generations each update jumps, for sims which can move ahead quickly
+                        if let Some(step_exponent) = sim.step_exponent_mut() {
+                            ui.add_space(10.0);
+                            ui.add(Slider::new(step_exponent, 0..=MAX_STEP_EXPONENT).text("Step Exponent"))
+                                .on_hover_text("Move 2 to the power of this many generations ahead each update");
+                            if !hash_life::supports(&rule) {
+                                ui.label("This rule can not jump ahead, as it must only reach one cell, have no regions, not be stochastic, and not give birth with 0 neighbours");
+                            }
+                        }
+
                         ui.add_space(10.0);
 
-                        // Bounding size slider
-                        ui.add(Slider::new(&mut bounds, 32..=255).text("Bounds Size")).on_hover_text("Change the size of the bounding box");
+                        // Bounding size sliders, one for each side
+                        ui.label("Bounds Size: ").on_hover_text("Change the size of the bounding box");
+                        for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                            ui.add(Slider::new(&mut bounds[axis], 1..=512).text(*name));
+                        }
+                        ui.checkbox(&mut lock_aspect, "Lock Aspect")
+                            .on_hover_text("Resize every side together, keeping the shape of the box");
+                        ui.checkbox(&mut current.crop_to_pattern, "Crop Around Pattern")
+                            .on_hover_text("Keep the middle of the pattern when the box shrinks, instead of the middle of the box");
+                        ui.checkbox(&mut current.auto_expand, "Auto Expand")
+                            .on_hover_text("Grow the box when live cells come close to a face, keeping the pattern in place");
+                        if current.auto_expand {
+                            ui.add(Slider::new(&mut current.expand_margin, 1..=16).text("Margin"))
+                                .on_hover_text("Grow the box once live cells are this close to a face");
+                            ui.add(Slider::new(&mut current.expand_limit, 1..=512).text("Limit"))
+                                .on_hover_text("Largest size each side of the box can grow to");
+                        }
                         {
                             if bounds != previous_bounds {
-                                bounds = sim.set_bounds(bounds);
-                                sim.spawn_noise(&rule);
-                                current.renderer.as_mut().unwrap().set_bounds(bounds);
+                                if lock_aspect {
+                                    bounds = utilities::scale_bounds(previous_bounds, bounds, 1, 512);
+                                }
+                                bounds = current.resize(&rule, bounds);
+                            }
+                            if spawn_noise {
+                                current.spawn_noise(&rule);
                             }
+                            if reset {
+                                current.reset();
+                            }
+                            current.lock_aspect = lock_aspect;
                             current.rule = Some(rule);
                         }
+
+                        ui.add_space(10.0);
+
+                        // Order of the cells in memory, which changes how fast they update but not how they look
+                        {
+                            let mut layout = current.layout;
+                            ComboBox::from_label("Cell Layout")
+                                .selected_text(format!("{:?}", layout))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut layout, Layout::Linear, "Linear")
+                                        .on_hover_text("Store the cells row by row, then layer by layer");
+                                    ui.selectable_value(&mut layout, Layout::Tiled, "Tiled")
+                                        .on_hover_text("Store the cells in 8x8x8 tiles in Z-order, so neighbours are close in memory");
+                                });
+
+                            if layout != current.layout {
+                                current.layout = layout;
+                                current.wait_for_step();
+                                let rule = current.rule.take().unwrap();
+                                current.sims[active_sim].1.set_layout(layout);
+                                current.restart(&rule);
+                                current.rule = Some(rule);
+                            }
+                            if current.sims[active_sim].1.layout() != layout {
+                                ui.label("This sim only stores its cells in the Linear layout");
+                            }
+                        }
+
+                        ui.add_space(10.0);
+
+                        // What happens to neighbours past each face of the cube
+                        ui.label("Boundaries: ").on_hover_text("Choose what is past the faces of the cube, for each axis");
+                        {
+                            let mut boundaries = current.boundaries;
+                            ui.horizontal(|ui| {
+                                for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                                    ComboBox::from_label(*name)
+                                        .width(70.0)
+                                        .selected_text(format!("{:?}", boundaries[axis]))
+                                        .show_ui(ui, |ui| {
+                                            ui.selectable_value(&mut boundaries[axis], Boundary::Periodic, "Periodic")
+                                                .on_hover_text("Cells past a face continue from the opposite face");
+                                            ui.selectable_value(&mut boundaries[axis], Boundary::Dead, "Dead")
+                                                .on_hover_text("Cells past a face are always dead");
+                                            ui.selectable_value(&mut boundaries[axis], Boundary::Alive, "Alive")
+                                                .on_hover_text("Cells past a face are always alive");
+                                            ui.selectable_value(&mut boundaries[axis], Boundary::Reflective, "Reflective")
+                                                .on_hover_text("Cells past a face mirror the cells inside it");
+                                        });
+                                }
+                            });
+
+                            if boundaries != current.boundaries {
+                                current.boundaries = boundaries;
+                                current.wait_for_step();
+                                let rule = current.rule.take().unwrap();
+                                current.sims[active_sim].1.set_boundaries(boundaries);
+                                current.restart(&rule);
+                                current.rule = Some(rule);
+                            }
+                        }
+
+                        ui.add_space(10.0);
+
+                        // The same seed, rule and simulator always give the same generations
+                        ui.horizontal(|ui| {
+                            ui.label("Seed: ").on_hover_text("Seed for the noise, so a run can be repeated or shared");
+                            let response = ui.add(egui::TextEdit::singleline(&mut current.seed_text).desired_width(150.0));
+                            if response.lost_focus() {
+                                match current.seed_text.parse() {
+                                    Ok(seed) => current.set_seed(seed),
+                                    Err(_) => current.seed_text = current.seed.to_string(),
+                                }
+                            }
+                            if ui.button("Re-roll")
+                                .on_hover_text("Pick a new random seed, and restart the simulation")
+                                .clicked()
+                            {
+                                current.set_seed(rand::random());
+                            }
+                        });
                     });
                 });
             }
             ui.add_space(15.0);
 
+            // Species Group, for sims with competing cells
+            if current.sims[active_sim].1.competition().is_some() {
+                ui.heading("Species:").on_hover_text("Types of cell which compete for space, each with its own rule");
+                {
+                    ui.group(|ui| {
+                        ui.set_width(275.0);
+                        species_ui(&mut current, active_sim, ui);
+                    });
+                }
+                ui.add_space(15.0);
+            }
+
             // Rules Group
             ui.heading("Rules:");
             {
@@ -195,31 +363,102 @@ pub fn settings_ui(
                         ui.add_space(10.0);
 
                         let mut rule = current.rule.take().unwrap();
-                        let previous_rule = rule;
+                        let previous_rule = rule.clone();
 
-                        // Set neighbour method
-                        ComboBox::from_label("Neighbour Method: ")
-                            .selected_text(format!("{:?}", rule.neighbourhood))
-                            .show_ui(ui, |ui| {
-                                ui.selectable_value(&mut rule.neighbourhood, Moore, "Moore").on_hover_text("Maximum of 26 neighbours");
-                                ui.selectable_value(
-                                    &mut rule.neighbourhood,
-                                    VonNeumann,
-                                    "Von Neumann",
-                                ).on_hover_text("Maximum of 6 neighbours");
-                            });
+                        // Semi-totalistic rules count face, edge and corner neighbours separately
+                        let mut semi_totalistic = rule.classes.is_some();
+                        if ui
+                            .checkbox(&mut semi_totalistic, "Face/Edge/Corner Rule")
+                            .on_hover_text("Count the Moore neighbours sharing a face, an edge or a corner with the cell separately")
+                            .changed()
+                        {
+                            // Start from the counts which add up to the current values, so the rule behaves the same
+                            rule.classes = match semi_totalistic {
+                                true => Some(ClassRule {
+                                    birth: ClassValue::from_value(rule.birth),
+                                    survival: ClassValue::from_value(rule.survival),
+                                }),
+                                false => None,
+                            };
+                            rule.neighbourhood = Moore;
+                        }
+
+                        if rule.classes.is_none() {
+                            // Set neighbour method
+                            let radius = rule.neighbourhood.radius();
+                            ComboBox::from_label("Neighbour Method: ")
+                                .selected_text(match rule.neighbourhood {
+                                    Moore | ExtendedMoore(_) => "Moore",
+                                    VonNeumann | ExtendedVonNeumann(_) => "Von Neumann",
+                                    Custom(_) => "Custom",
+                                    Weighted { .. } => "Weighted",
+                                })
+                                .show_ui(ui, |ui| {
+                                    let moore = Neighbourhood::moore(radius.unwrap_or(1));
+                                    let von_neumann = Neighbourhood::von_neumann(radius.unwrap_or(1));
+                                    let moore_text = format!("Maximum of {} neighbours", moore.max_neighbours());
+                                    let von_neumann_text = format!("Maximum of {} neighbours", von_neumann.max_neighbours());
+                                    ui.selectable_value(&mut rule.neighbourhood, moore, "Moore").on_hover_text(moore_text);
+                                    ui.selectable_value(
+                                        &mut rule.neighbourhood,
+                                        von_neumann,
+                                        "Von Neumann",
+                                    ).on_hover_text(von_neumann_text);
+                                    if ui
+                                        .selectable_label(matches!(rule.neighbourhood, Custom(_)), "Custom")
+                                        .on_hover_text("Pick any set of neighbours, starting from the current neighbourhood")
+                                        .clicked()
+                                        && !matches!(rule.neighbourhood, Custom(_))
+                                    {
+                                        rule.neighbourhood = Custom(rule.neighbourhood.get_neighbourhood_iter().to_vec());
+                                    }
+                                    if ui
+                                        .selectable_label(matches!(rule.neighbourhood, Weighted { .. }), "Weighted")
+                                        .on_hover_text("Give each neighbour its own weight, starting from the current neighbourhood")
+                                        .clicked()
+                                        && !matches!(rule.neighbourhood, Weighted { .. })
+                                    {
+                                        if let Ok(weighted) = Neighbourhood::weighted(rule.neighbourhood.get_weighted_iter()) {
+                                            rule.neighbourhood = weighted;
+                                        }
+                                    }
+                                });
+
+                            // Neighbourhood radius slider, for Moore and Von Neumann
+                            if let Some(mut radius) = radius {
+                                ui.add(Slider::new(&mut radius, 1..=MAX_RADIUS).text("Neighbourhood Radius"))
+                                    .on_hover_text("Count neighbours up to this many cells away");
+                                rule.neighbourhood = match rule.neighbourhood {
+                                    Moore | ExtendedMoore(_) => Neighbourhood::moore(radius),
+                                    _ => Neighbourhood::von_neumann(radius),
+                                };
+                            }
+
+                            if matches!(rule.neighbourhood, Custom(_) | Weighted { .. }) {
+                                custom_neighbourhood_ui(&mut current, &mut rule, ui);
+                            }
+                        }
 
                         // Number of states slider
                         ui.add(Slider::new(&mut rule.states, 1..=255).text("Number of States")).on_hover_text("Change the number of states for the cells");
 
+                        ui.checkbox(&mut rule.decaying_neighbours, "Decaying Cells Count")
+                            .on_hover_text("Count decaying cells as neighbours until they die, instead of only cells in their first state");
+
+                        regions_ui(&mut current, &mut rule, ui);
+                        rule.match_regions();
+
                         // If the slider changes, update the rule, and restart the simulation
                         if rule != previous_rule {
-                            let sim = &mut current.sims[active_sim].1;
-                            sim.reset();
-                            sim.spawn_noise(&rule);
+                            current.restart(&rule);
                         }
                         current.rule = Some(rule);
 
+                        ui.add_space(10.0);
+
+                        // Paste or copy a rule in survival/birth/states/neighbourhood notation
+                        rule_string_ui(&mut current, ui);
+
                         let spacing = egui::vec2(1.0, 1.0);
                         ui.add_space(10.0);
 
@@ -228,16 +467,65 @@ pub fn settings_ui(
                         {
                             ui.group(|ui| {
                                 ui.set_width(250.0);
+
+                                // Weighted neighbourhoods can not add up to every value, so show which they can
+                                let neighbourhood = &current.rule.as_ref().unwrap().neighbourhood;
+                                if matches!(neighbourhood, Weighted { .. }) {
+                                    let reachable: Vec<u16> = neighbourhood
+                                        .reachable_values()
+                                        .iter()
+                                        .enumerate()
+                                        .filter(|(_, reachable)| **reachable)
+                                        .map(|(value, _)| value as u16)
+                                        .collect();
+                                    ui.label(format!("Reachable: {}", Value::new(&reachable)))
+                                        .on_hover_text("The weighted neighbour counts which live neighbours can add up to");
+                                }
+
+                                stochastic_ui(current.rule.as_mut().unwrap(), ui);
+
+                                if current.rule.as_ref().unwrap().classes.is_some() {
+                                    let mut rule = current.rule.take().unwrap();
+                                    let mut classes = rule.classes.unwrap();
+                                    let [birth_selection, survival_selection] = &mut current.class_selection;
+
+                                    ui.horizontal(|ui| {
+                                        ui.vertical(|ui| {
+                                            ui.set_width(120.0);
+                                            ui.label("Birth Values: ");
+                                            {
+                                                classes.birth = class_selector_ui(birth_selection, spacing, ui, classes.birth, "birth_class_grid");
+                                            }
+                                        });
+                                        ui.add_space(5.0);
+
+                                        ui.vertical(|ui| {
+                                            ui.set_width(120.0);
+                                            ui.label("Survival Values");
+                                            {
+                                                classes.survival = class_selector_ui(survival_selection, spacing, ui, classes.survival, "survival_class_grid");
+                                            }
+                                        });
+                                    });
+                                    rule.classes = Some(classes);
+                                    current.rule = Some(rule);
+                                    return;
+                                }
+
                                 ui.horizontal(|ui| {
                                     // Get the current rule
-                                    let mut rule = current.rule.unwrap();
+                                    let mut rule = current.rule.take().unwrap();
+                                    let reachable = rule.neighbourhood.reachable_values();
+                                    let (birth_chances, survival_chances) = match rule.stochastic.as_mut() {
+                                        Some(stochastic) => (Some(&mut stochastic.birth), Some(&mut stochastic.survival)),
+                                        None => (None, None),
+                                    };
 
                                     ui.vertical(|ui| {
                                         ui.set_width(120.0);
                                         ui.label("Birth Values: ");
                                         {
-                                            rule.birth = value_selector_ui(current.rule.unwrap().neighbourhood, spacing, ui, rule.birth, "birth_grid");
-                                            current.rule = Some(rule);
+                                            rule.birth = value_selector_ui(&reachable, birth_chances, spacing, ui, rule.birth, "birth_grid");
                                         }
                                     });
                                     ui.add_space(5.0);
@@ -246,10 +534,10 @@ pub fn settings_ui(
                                         ui.set_width(120.0);
                                         ui.label("Survival Values");
                                         {
-                                            rule.survival = value_selector_ui(current.rule.unwrap().neighbourhood, spacing, ui, rule.survival, "survival_grid");
-                                            current.rule = Some(rule);
+                                            rule.survival = value_selector_ui(&reachable, survival_chances, spacing, ui, rule.survival, "survival_grid");
                                         }
                                     });
+                                    current.rule = Some(rule);
                                 });
                             });
                         }
@@ -268,6 +556,16 @@ pub fn settings_ui(
                     });
                 });
             }
+
+            ui.add_space(10.0);
+
+            ui.label("Schedule:").on_hover_text("Switch between rules automatically as the simulation runs");
+            {
+                ui.group(|ui| {
+                    ui.set_width(275.0);
+                    schedule_ui(&mut current, active_sim, ui);
+                });
+            }
             // Get current rule
             let rule = current.rule.take().unwrap();
 
@@ -276,6 +574,23 @@ pub fn settings_ui(
             current.active_sim = active_sim;
             current.rule = Some(rule);
         });
+
+    // Tell the user the box can not grow any more, until they close the notice
+    if current.expand_notice {
+        Window::new("Auto Expand")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+            .show(contexts.ctx_mut(), |ui| {
+                ui.label(format!(
+                    "The pattern has reached a face, and the box can not grow past the limit of {} cells a side",
+                    current.expand_limit
+                ));
+                if ui.button("OK").clicked() {
+                    current.expand_notice = false;
+                }
+            });
+    }
 }
 
 // Add buttons to change sims
@@ -293,43 +608,668 @@ fn examples_ui(current: &mut ResMut<Sims>, ui: &mut Ui) {
     });
 }
 
-fn value_selector_ui(neighbourhood: Neighbourhood, spacing: Vec2, ui: &mut Ui, mut value: Value, grid_id: &str) -> Value {
-    Grid::new(grid_id).spacing(spacing).show(
-        ui, |ui| {
-            for i in 1..=26 {
-                let mut enabled = true;
-                if i > 6
-                    && neighbourhood
-                    == VonNeumann
-                {
-                    enabled = false;
+// Change the species of a sim with competing cells
+// A species' rule is changed by editing it with the rule settings, then saving it back
+fn species_ui(current: &mut ResMut<Sims>, active_sim: usize, ui: &mut Ui) {
+    let mut rule = current.rule.take().unwrap();
+    let sim = &mut current.sims[active_sim].1;
+    let mut competition = sim.competition().unwrap().clone();
+    let previous = competition.clone();
+
+    ComboBox::from_label("Birth Conflicts")
+        .selected_text(format!("{:?}", competition.policy))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut competition.policy, ConflictPolicy::Majority, "Majority")
+                .on_hover_text("The species with the most neighbours is born");
+            ui.selectable_value(&mut competition.policy, ConflictPolicy::Random, "Random")
+                .on_hover_text("A random species is born");
+            ui.selectable_value(&mut competition.policy, ConflictPolicy::Priority, "Priority")
+                .on_hover_text("The species highest in the list is born");
+        });
+
+    if competition.policy == ConflictPolicy::Random {
+        ui.horizontal(|ui| {
+            ui.label("Seed: ");
+            ui.add(DragValue::new(&mut competition.seed));
+        });
+    }
+
+    let species_count = competition.species.len();
+    let mut removed = None;
+    Grid::new("species_grid").show(ui, |ui| {
+        for (i, species) in competition.species.iter_mut().enumerate() {
+            colour_picker(ui, &mut species.colour);
+            ui.label(&species.name).on_hover_text(species.rule.to_string());
+            if ui.button("Edit").on_hover_text("Show this species' rule in the rule settings").clicked() {
+                rule = species.rule.clone();
+            }
+            if ui.button("Save").on_hover_text("Give this species the rule in the rule settings").clicked() {
+                species.rule = rule.clone();
+            }
+            if ui.add_enabled(species_count > 1, egui::Button::new("Remove")).clicked() {
+                removed = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(i) = removed {
+        competition.species.remove(i);
+    }
+
+    if ui
+        .add_enabled(species_count < MAX_SPECIES, egui::Button::new("Add Species"))
+        .on_hover_text("Add a species with the rule in the rule settings")
+        .clicked()
+    {
+        competition.species.push(Species::new(
+            &format!("Species {}", species_count + 1),
+            rule.clone(),
+            SPECIES_COLOURS[species_count],
+        ));
+    }
+
+    if competition != previous {
+        // Colours can change while running, anything else restarts the simulation
+        let restart = competition.policy != previous.policy
+            || competition.seed != previous.seed
+            || competition.species.len() != previous.species.len()
+            || competition.species.iter().zip(&previous.species).any(|(a, b)| a.rule != b.rule);
+        *sim.competition_mut().unwrap() = competition;
+        if restart {
+            current.restart(&rule);
+        }
+    }
+    current.rule = Some(rule);
+}
+
+// Give parts of the cube their own rule, which can be previewed in each region's colour
+fn regions_ui(current: &mut ResMut<Sims>, rule: &mut Rule, ui: &mut Ui) {
+    ui.collapsing("Regions", |ui| {
+        ui.checkbox(&mut current.preview_regions, "Preview")
+            .on_hover_text("Show the cells in each region, in the region's colour. Later regions cover earlier ones");
+
+        let mut main_rule = rule.clone();
+        main_rule.regions.clear();
+
+        let mut removed = None;
+        for (i, region) in rule.regions.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        colour_picker(ui, &mut region.colour);
+                        let selected = match region.shape {
+                            Shape::Box { .. } => "Box",
+                            Shape::Sphere { .. } => "Sphere",
+                            Shape::HalfSpace { .. } => "Half-Space",
+                            Shape::Indexed { .. } => "Volume",
+                        };
+                        ComboBox::from_id_source("region_shape")
+                            .selected_text(selected)
+                            .show_ui(ui, |ui| {
+                                let shapes = [
+                                    ("Box", Shape::Box { min: ivec3(-10, -10, -10), max: ivec3(10, 10, 10) }),
+                                    ("Sphere", Shape::default()),
+                                    ("Half-Space", Shape::HalfSpace { normal: ivec3(0, 1, 0), offset: 0 }),
+                                ];
+                                for (name, shape) in shapes {
+                                    if ui.selectable_label(name == selected, name).clicked() && name != selected {
+                                        region.shape = shape;
+                                    }
+                                }
+                            });
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+
+                    // Positions are relative to the centre of the cube
+                    match &mut region.shape {
+                        Shape::Box { min, max } => {
+                            position_ui(ui, "Min: ", min);
+                            position_ui(ui, "Max: ", max);
+                        }
+                        Shape::Sphere { centre, radius } => {
+                            position_ui(ui, "Centre: ", centre);
+                            ui.add(DragValue::new(radius).prefix("Radius: ").clamp_range(0.0..=f32::MAX));
+                        }
+                        Shape::HalfSpace { normal, offset } => {
+                            position_ui(ui, "Normal: ", normal);
+                            ui.add(DragValue::new(offset).prefix("Offset: "))
+                                .on_hover_text("Cells where the position dotted with the normal is at least this are in the region");
+                        }
+                        Shape::Indexed { volume, index } => {
+                            ui.label(format!("Index {} of a {} volume", index, volume.size));
+                        }
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label(region.rule.to_string());
+                        ComboBox::from_id_source("region_rule")
+                            .selected_text("Set Rule")
+                            .show_ui(ui, |ui| {
+                                if ui.selectable_label(false, "Current").clicked() {
+                                    region.rule = main_rule.clone();
+                                }
+                                for example in &current.examples {
+                                    if ui.selectable_label(false, &example.name).clicked() {
+                                        region.rule = example.rule.clone();
+                                    }
+                                }
+                            });
+                    });
+                });
+            });
+        }
+        if let Some(i) = removed {
+            rule.regions.remove(i);
+        }
+
+        let colour = REGION_COLOURS[rule.regions.len() % REGION_COLOURS.len()];
+        if ui.button("Add Region").on_hover_text("Add a sphere in the centre, following the current rule").clicked() {
+            rule.regions.push(Region {
+                shape: Shape::default(),
+                rule: main_rule.clone(),
+                colour,
+            });
+        }
+
+        // A volume of indices gives one region for each index
+        ui.horizontal(|ui| {
+            ui.add(TextEdit::singleline(&mut current.region_path).hint_text("regions.txt").desired_width(150.0));
+            if ui
+                .button("Load Volume")
+                .on_hover_text("Read a size, then one region index per cell. 0 is not in any region")
+                .clicked()
+            {
+                match IndexVolume::load(&current.region_path) {
+                    Ok(volume) => {
+                        rule.regions.extend(volume.into_regions(&main_rule));
+                        current.region_error = None;
+                    }
+                    Err(error) => current.region_error = Some(error),
+                }
+            }
+        });
+        if let Some(error) = &current.region_error {
+            ui.colored_label(egui::Color32::RED, error.to_string());
+        }
+    });
+}
+
+// Drag values for each axis of a position
+fn position_ui(ui: &mut Ui, label: &str, position: &mut IVec3) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(DragValue::new(&mut position.x).prefix("x: "));
+        ui.add(DragValue::new(&mut position.y).prefix("y: "));
+        ui.add(DragValue::new(&mut position.z).prefix("z: "));
+    });
+}
+
+// Timeline of rules to switch to at each generation, which can be saved to a file
+fn schedule_ui(current: &mut ResMut<Sims>, active_sim: usize, ui: &mut Ui) {
+    let generation = current.sims[active_sim].1.generation();
+
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut current.schedule.enabled, "Run")
+            .on_hover_text("Switch to each entry's rule and colours once the generation reaches it");
+        ui.checkbox(&mut current.schedule.looping, "Loop Every")
+            .on_hover_text("Start the schedule again after this many generations");
+        ui.add_enabled(
+            current.schedule.looping,
+            DragValue::new(&mut current.schedule.length).clamp_range(1..=u64::MAX),
+        );
+    });
+    ui.label(format!("Generation: {}", generation));
+
+    // One row for each entry, with the generation it starts at
+    let mut removed = None;
+    let mut moved = false;
+    let active = current.schedule_entry;
+    Grid::new("schedule_grid").show(ui, |ui| {
+        for (i, entry) in current.schedule.entries.iter_mut().enumerate() {
+            let text = match active == Some(i) {
+                true => format!("> {}", entry.example.name),
+                false => entry.example.name.clone(),
+            };
+            ui.label(text).on_hover_text(entry.example.rule.to_string());
+            moved |= ui
+                .add(DragValue::new(&mut entry.generation).prefix("at "))
+                .on_hover_text("Generation to switch to this entry")
+                .changed();
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(i) = removed {
+        current.schedule.entries.remove(i);
+        current.schedule_entry = None;
+    }
+    if moved {
+        current.schedule.sort();
+        current.schedule_entry = None;
+    }
+
+    // New entries start after the last one
+    let next = current
+        .schedule
+        .entries
+        .last()
+        .map_or(generation, |entry| entry.generation + 100);
+    ui.horizontal(|ui| {
+        if ui
+            .button("Add Current")
+            .on_hover_text("Add the current rule and colours to the schedule")
+            .clicked()
+        {
+            let rule = current.rule.clone().unwrap();
+            let example = Example {
+                name: rule.to_string(),
+                rule,
+                colour_method: current.colour_method,
+                colour1: current.colour1,
+                colour2: current.colour2,
+            };
+            current.schedule.add(next, example);
+        }
+
+        let mut example = None;
+        ComboBox::from_id_source("schedule_example")
+            .selected_text("Add Example")
+            .show_ui(ui, |ui| {
+                for (i, preset) in current.examples.iter().enumerate() {
+                    if ui.selectable_label(false, &preset.name).clicked() {
+                        example = Some(i);
+                    }
+                }
+            });
+        if let Some(i) = example {
+            let example = current.examples[i].clone();
+            current.schedule.add(next, example);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.add(TextEdit::singleline(&mut current.schedule_path).hint_text("schedule.txt").desired_width(150.0));
+        if ui.button("Save").on_hover_text("Write the schedule to this file").clicked() {
+            current.schedule_error = current.schedule.save(&current.schedule_path).err();
+        }
+        if ui.button("Load").on_hover_text("Read a schedule from this file, and run it").clicked() {
+            match Schedule::load(&current.schedule_path) {
+                Ok(schedule) => {
+                    current.schedule = schedule;
+                    current.schedule_entry = None;
+                    current.schedule_error = None;
+                }
+                Err(error) => current.schedule_error = Some(error),
+            }
+        }
+    });
+    if let Some(error) = &current.schedule_error {
+        ui.colored_label(egui::Color32::RED, error.to_string());
+    }
+}
+
+// Text field to read a rule from, or write the current rule to, e.g. 4/4/5/M
+fn rule_string_ui(current: &mut ResMut<Sims>, ui: &mut Ui) {
+    ui.label("Rule String: ").on_hover_text("Survival/Birth/States/Neighbourhood, e.g. 9-26/5-7,12-13,15/5/M");
+    {
+        ui.add(TextEdit::singleline(&mut current.rule_text).hint_text("4/4/5/M"));
+
+        ui.horizontal(|ui| {
+            if ui
+                .add(egui::Button::new("Apply"))
+                .on_hover_text("Change the rule to the one in the text field, and restart the simulation")
+                .clicked()
+            {
+                match current.rule_text.parse::<Rule>() {
+                    Ok(rule) => {
+                        current.restart(&rule);
+                        current.rule = Some(rule);
+                        current.rule_error = None;
+                    }
+                    Err(error) => current.rule_error = Some(error),
+                }
+            }
+
+            // Regions are edited in their own section and are not part of the notation
+            let hover = if current.rule.as_ref().unwrap().regions.is_empty() {
+                "Write the current rule to the text field and the clipboard"
+            } else {
+                "Write the current rule to the text field and the clipboard, without its regions"
+            };
+            if ui
+                .add(egui::Button::new("Copy Current"))
+                .on_hover_text(hover)
+                .clicked()
+            {
+                let text = current.rule.as_ref().unwrap().to_string();
+                ui.output_mut(|output| output.copied_text = text.clone());
+                current.rule_text = text;
+                current.rule_error = None;
+            }
+        });
+
+        if let Some(error) = &current.rule_error {
+            ui.colored_label(egui::Color32::RED, error.to_string());
+        }
+    }
+}
+
+// Toggle the offsets of a custom neighbourhood, or set the weights of a weighted one
+// Either can also be loaded from a file
+fn custom_neighbourhood_ui(current: &mut ResMut<Sims>, rule: &mut Rule, ui: &mut Ui) {
+    let weighted = matches!(rule.neighbourhood, Weighted { .. });
+    let heading = match weighted {
+        true => "Weighted Neighbourhood",
+        false => "Custom Neighbourhood",
+    };
+    ui.collapsing(heading, |ui| {
+        let mut kernel: Vec<(IVec3, u16)> = rule.neighbourhood.get_weighted_iter().collect();
+        let previous_kernel = kernel.clone();
+
+        // One 3x3 grid for each layer of the cube around the cell
+        ui.horizontal(|ui| {
+            for z in -1..=1 {
+                ui.vertical(|ui| {
+                    ui.label(format!("z = {}", z));
+                    Grid::new(format!("custom_neighbourhood_{}", z)).spacing(egui::vec2(1.0, 1.0)).show(ui, |ui| {
+                        for y in (-1..=1).rev() {
+                            for x in -1..=1 {
+                                let offset = ivec3(x, y, z);
+                                let mut weight = kernel
+                                    .iter()
+                                    .find(|(o, _)| *o == offset)
+                                    .map_or(0, |(_, weight)| *weight);
+                                let previous_weight = weight;
+
+                                let hover_text = format!("Neighbour at ({}, {}, {})", x, y, z);
+                                if weighted {
+                                    ui.add_enabled(
+                                        offset != IVec3::ZERO,
+                                        DragValue::new(&mut weight).clamp_range(0..=MAX_NEIGHBOURS as u16),
+                                    )
+                                    .on_hover_text(hover_text);
+                                } else {
+                                    let mut selected = weight > 0;
+                                    ui.add_enabled(offset != IVec3::ZERO, Checkbox::without_text(&mut selected))
+                                        .on_hover_text(hover_text);
+                                    weight = selected as u16;
+                                }
+
+                                if weight != previous_weight {
+                                    kernel.retain(|(o, _)| *o != offset);
+                                    if weight > 0 {
+                                        kernel.push((offset, weight));
+                                    }
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            }
+        });
+
+        // Offsets loaded from a file may be further away than the grid shows
+        let further = kernel.iter().filter(|(offset, _)| offset.abs().max_element() > 1).count();
+        if further > 0 {
+            ui.label(format!("+{} neighbours outside the grid", further));
+        }
+
+        if weighted {
+            // Weight every Moore neighbour by whether it shares a face, edge or corner with the cell
+            ui.horizontal(|ui| {
+                ui.label("Face/Edge/Corner: ");
+                for weight in current.class_weights.iter_mut() {
+                    ui.add(DragValue::new(weight).clamp_range(0..=MAX_NEIGHBOURS as u16));
+                }
+                if ui.add(egui::Button::new("Set")).on_hover_text("Replace the weights with these for each class of neighbour").clicked() {
+                    let [face, edge, corner] = current.class_weights;
+                    match Neighbourhood::weighted_by_class(face, edge, corner) {
+                        Ok(neighbourhood) => kernel = neighbourhood.get_weighted_iter().collect(),
+                        Err(error) => current.neighbourhood_error = Some(error),
+                    }
+                }
+            });
+        }
+
+        if kernel != previous_kernel {
+            let neighbourhood = match weighted {
+                true => Neighbourhood::weighted(kernel),
+                false => Neighbourhood::custom(kernel.into_iter().map(|(offset, _)| offset)),
+            };
+            match neighbourhood {
+                Ok(neighbourhood) => {
+                    rule.neighbourhood = neighbourhood;
+                    current.neighbourhood_error = None;
                 }
-                // Checkbox for each value
-                if ui
-                    .add_enabled(
-                        enabled,
-                        Checkbox::new(
-                            &mut value.get_value(i),
-                            format!("{}", i),
-                        ),
-                    )
-                    .on_hover_text("Click to select this value. If it is greyed out, try changing the neighbourhood :)").clicked() {
-                    // Update the value
-                    value = value.change_value(i);
-                };
-
-                // Every third element, make a new row
-                if i % 3 == 0 {
-                    ui.end_row()
-                };
+                Err(error) => current.neighbourhood_error = Some(error),
+            }
+        }
+
+        ui.add_space(5.0);
+
+        ui.horizontal(|ui| {
+            ui.add(TextEdit::singleline(&mut current.neighbourhood_path).hint_text("neighbourhood.txt").desired_width(180.0))
+                .on_hover_text("A file with one x,y,z offset per line, optionally followed by :weight");
+            if ui.add(egui::Button::new("Load")).on_hover_text("Load the neighbourhood from the file").clicked() {
+                match Neighbourhood::load(&current.neighbourhood_path) {
+                    Ok(neighbourhood) => {
+                        rule.neighbourhood = neighbourhood;
+                        current.neighbourhood_error = None;
+                    }
+                    Err(error) => current.neighbourhood_error = Some(error),
+                }
+            }
+        });
+
+        if let Some(error) = &current.neighbourhood_error {
+            ui.colored_label(egui::Color32::RED, error.to_string());
+        }
+    });
+}
+
+// Pick a number of face and edge neighbours, then toggle which numbers of corner neighbours are valid with them
+fn class_selector_ui(selection: &mut (u16, u16), spacing: Vec2, ui: &mut Ui, mut value: ClassValue, grid_id: &str) -> ClassValue {
+    let (faces, edges) = selection;
+    ui.add(DragValue::new(faces).clamp_range(0..=FACES).prefix("Faces: "))
+        .on_hover_text("Number of live neighbours sharing a face with the cell");
+    ui.add(DragValue::new(edges).clamp_range(0..=EDGES).prefix("Edges: "))
+        .on_hover_text("Number of live neighbours sharing an edge with the cell");
+    ui.label("Corners: ");
+
+    Grid::new(grid_id).spacing(spacing).show(ui, |ui| {
+        for corners in 0..=CORNERS {
+            if ui
+                .checkbox(&mut value.get_value(*faces, *edges, corners), format!("{}", corners))
+                .on_hover_text("Click to select this number of corner neighbours, with the faces and edges above")
+                .clicked()
+            {
+                value = value.change_value(*faces, *edges, corners);
             }
-        },
-    );
+
+            // Every third element, make a new row
+            if (corners + 1) % 3 == 0 {
+                ui.end_row()
+            };
+        }
+    });
+
+    // Show every selected count, as only one face and edge pair is visible at a time
+    ui.label(format!("{}", value)).on_hover_text("Selected faces:edges:corners counts");
+    value
+}
+
+// Toggle a stochastic rule, and change its seed and spontaneous rates
+fn stochastic_ui(rule: &mut Rule, ui: &mut Ui) {
+    let mut stochastic = rule.stochastic.is_some();
+    if ui
+        .checkbox(&mut stochastic, "Stochastic")
+        .on_hover_text("Give each birth and survival value a chance of happening. Runs with the same seed are identical")
+        .changed()
+    {
+        rule.stochastic = stochastic.then(Stochastic::default);
+    }
+
+    if let Some(stochastic) = rule.stochastic.as_mut() {
+        ui.horizontal(|ui| {
+            ui.label("Seed: ");
+            ui.add(DragValue::new(&mut stochastic.seed));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Spontaneous Birth: ");
+            ui.add(DragValue::new(&mut stochastic.spontaneous_birth).speed(0.001).clamp_range(0.0..=1.0))
+                .on_hover_text("Chance of any dead cell being born");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Spontaneous Death: ");
+            ui.add(DragValue::new(&mut stochastic.spontaneous_death).speed(0.001).clamp_range(0.0..=1.0))
+                .on_hover_text("Chance of any live cell starting to decay");
+        });
+    }
+}
+
+// `reachable` is true for each neighbour count which the neighbourhood can add up to
+fn value_selector_ui(reachable: &[bool], mut chances: Option<&mut Vec<f32>>, spacing: Vec2, ui: &mut Ui, mut value: Value, grid_id: &str) -> Value {
+    // Show at least the values for a Moore neighbourhood, scrolling if there are many more
+    let count = (reachable.len() - 1).max(26);
+    ScrollArea::vertical().id_source(grid_id).max_height(250.0).show(ui, |ui| {
+        Grid::new(grid_id).spacing(spacing).show(
+            ui, |ui| {
+                for i in 1..=count {
+                    // Values above the number of neighbours, or between the weights, can never be reached
+                    let enabled = reachable.get(i).copied().unwrap_or(false);
+                    // Checkbox for each value
+                    if ui
+                        .add_enabled(
+                            enabled,
+                            Checkbox::new(
+                                &mut value.get_value(i),
+                                format!("{}", i),
+                            ),
+                        )
+                        .on_hover_text("Click to select this value. If it is greyed out, try changing the neighbourhood :)").clicked() {
+                        // Update the value
+                        value = value.change_value(i);
+                    };
+
+                    // Chance of the value happening, for stochastic rules
+                    if let Some(chances) = chances.as_mut() {
+                        ui.add_enabled(
+                            enabled && value.get_value(i),
+                            DragValue::new(&mut chances[i]).speed(0.01).clamp_range(0.0..=1.0),
+                        );
+                        ui.end_row();
+                        continue;
+                    }
+
+                    // Every third element, make a new row
+                    if i % 3 == 0 {
+                        ui.end_row()
+                    };
+                }
+            },
+        );
+    });
     // Return the new value
     value
 }
 
 
+// Pausing and stepping, and how fast generations advance while running
+fn run_ui(current: &mut Sims, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        ui.set_width(275.0);
+        let text = match current.run_state {
+            RunState::Running => "Pause",
+            _ => "Run",
+        };
+        if ui
+            .add(egui::Button::new(text).shortcut_text(ui.ctx().format_shortcut(&PAUSE_SHORTCUT)))
+            .on_hover_text("Stop or start advancing generations")
+            .clicked()
+        {
+            current.run_state = toggle_pause(current.run_state);
+        }
+        if ui
+            .add(egui::Button::new("Step").shortcut_text(ui.ctx().format_shortcut(&STEP_SHORTCUT)))
+            .on_hover_text("Advance one generation, then pause")
+            .clicked()
+        {
+            current.run_state = RunState::Step(1);
+        }
+        let step_count = current.step_count;
+        if ui
+            .add(egui::Button::new("Step N").shortcut_text(ui.ctx().format_shortcut(&STEP_N_SHORTCUT)))
+            .on_hover_text(format!("Advance {} generations, then pause", step_count))
+            .clicked()
+        {
+            current.run_state = RunState::Step(step_count);
+        }
+        ui.add(DragValue::new(&mut current.step_count).clamp_range(1..=10000))
+            .on_hover_text("Generations advanced by Step N");
+    });
+    ui.horizontal(|ui| {
+        let slow = current.step_time().filter(|time| *time > SLOW_STEP);
+        if ui
+            .add_enabled(slow.is_some(), egui::Button::new("Cancel"))
+            .on_hover_text("Stop after the generation in progress, and pause")
+            .clicked()
+        {
+            current.cancel_step();
+            current.run_state = RunState::Paused;
+        }
+        if let Some(time) = slow {
+            ui.spinner();
+            ui.label(format!("Working out generations: {:.1?}", time));
+        }
+    });
+    ui.label("Generations per Second:");
+    ui.add(
+        Slider::new(&mut current.generations_per_second, 0.1..=1000.0)
+            .logarithmic(true)
+            .max_decimals(1),
+    )
+    .on_hover_text("How fast generations advance while running, whatever the framerate");
+
+    // Past generations, which can be gone back to
+    if let Some((oldest, newest)) = current.history.range() {
+        let mut generation = current.sims[current.active_sim].1.generation();
+        ui.label("Timeline:");
+        if ui
+            .add(Slider::new(&mut generation, oldest..=newest))
+            .on_hover_text("Go back to an earlier generation, where carrying on replaces the generations after it")
+            .changed()
+        {
+            current.rewind(generation);
+        }
+    }
+    ui.horizontal(|ui| {
+        let mut budget = current.history.budget() >> 20;
+        ui.label("History Budget:");
+        if ui
+            .add(DragValue::new(&mut budget).clamp_range(0..=4096).suffix(" MB"))
+            .on_hover_text("Most memory to keep past generations in, where 0 keeps none")
+            .changed()
+        {
+            current.history.set_budget(budget << 20);
+        }
+        ui.label(format!("{:.1} MB used", current.history.used() as f32 / (1 << 20) as f32));
+    });
+}
+
+// Pausing while stepping stops the steps which are left
+fn toggle_pause(run_state: RunState) -> RunState {
+    match run_state {
+        RunState::Running => RunState::Paused,
+        RunState::Paused => RunState::Running,
+        RunState::Step(_) => RunState::Paused,
+    }
+}
+
 fn controls_ui(current: &ResMut<Sims>, diagnostics: Res<Diagnostics>, active_sim: usize, ui: &mut Ui) {
     ui.heading("Information:").on_hover_text("Information about the current simulation");
     {
@@ -346,6 +1286,31 @@ fn controls_ui(current: &ResMut<Sims>, diagnostics: Res<Diagnostics>, active_sim
                     .unwrap_or(0.0);
 
                 ui.label(format!("Cells: {}", cell_count));
+                ui.label(format!("Generation: {}", current.sims[active_sim].1.generation()));
+                if let Some(evaluated) = current.sims[active_sim].1.cells_evaluated() {
+                    ui.label(format!(
+                        "Cells Evaluated: {} of {} per step",
+                        evaluated,
+                        utilities::cell_count(current.bounds)
+                    ))
+                    .on_hover_text("Cells which could change, and were checked in the last update");
+                }
+                if let Some(outside) = current.sims[active_sim].1.cells_out_of_range().filter(|outside| *outside > 0) {
+                    ui.colored_label(egui::Color32::RED, format!("Cells Out of Range: {}", outside))
+                        .on_hover_text("Cells which have moved too far from the centre to be kept in the history or resized");
+                }
+                // Population of each species, in its colour
+                let sim = &current.sims[active_sim].1;
+                if let Some(competition) = sim.competition() {
+                    for (species, population) in competition.species.iter().zip(sim.populations()) {
+                        let colour = egui::Color32::from_rgb(
+                            (species.colour.r() * 255.0) as u8,
+                            (species.colour.g() * 255.0) as u8,
+                            (species.colour.b() * 255.0) as u8,
+                        );
+                        ui.colored_label(colour, format!("{}: {}", species.name, population));
+                    }
+                }
                 ui.label(format!(
                     "Update: {:.2?} per cell",
                     update_dt / cell_count.max(1) as u32
@@ -378,6 +1343,21 @@ fn controls_ui(current: &ResMut<Sims>, diagnostics: Res<Diagnostics>, active_sim
                             ui.add_space(2.0);
 
                             ui.label("Down: ");
+
+                            ui.end_row();
+                            ui.add_space(2.0);
+
+                            ui.label("Pause/ Run: ");
+
+                            ui.end_row();
+                            ui.add_space(2.0);
+
+                            ui.label("Step: ");
+
+                            ui.end_row();
+                            ui.add_space(2.0);
+
+                            ui.label("Step N: ");
                         });
                         ui.vertical(|ui| {
                             ui.button("ESC").on_hover_text("Press escape to unlock/ lock the camera movement");
@@ -387,6 +1367,12 @@ fn controls_ui(current: &ResMut<Sims>, diagnostics: Res<Diagnostics>, active_sim
                             ui.button("SHIFT").on_hover_text("Press teh Shift key to move the camera upwards");
                             ui.end_row();
                             ui.button("CTRL").on_hover_text("Press the Ctrl (Control) key to move the camera downwards");
+                            ui.end_row();
+                            ui.button("P").on_hover_text("Press P to pause, or carry on running the simulation");
+                            ui.end_row();
+                            ui.button("N").on_hover_text("Press N to advance one generation, then pause");
+                            ui.end_row();
+                            ui.button("SHIFT + N").on_hover_text("Press Shift and N to advance the number of generations set next to Step N, then pause");
                         });
                     });
                 });
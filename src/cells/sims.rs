@@ -1,21 +1,37 @@
 // Adapted from TanTanDev
 use bevy::{
-    prelude::{Color, Plugin, Query, ResMut, Resource, IntoSystemConfig},
-    tasks::AsyncComputeTaskPool,
+    math::{BVec3, IVec3, Vec3},
+    prelude::{Color, Plugin, Query, Res, ResMut, Resource, IntoSystemConfig},
+    time::Time,
+    tasks::{AsyncComputeTaskPool, Task, TaskPool},
+};
+use futures_lite::future;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 
 use crate::{
-    cells::Sim,
+    boundary::{Boundaries, PERIODIC},
+    cells::{history::{self, History, Recorder}, multi_species::Competition, LiveCell, Sim},
+    layout::Layout,
     render::{CellRenderer, InstanceData, InstanceMaterialData},
-    rule::Rule,
+    neighbours::NeighbourhoodError,
+    regions::RegionError,
+    rule::{Rule, RuleParseError},
+    schedule::{Schedule, ScheduleError},
     utilities,
 };
 
 use crate::cells::settings::*;
 use crate::color_method::ColourMethod;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Example {
     pub name: String,
     pub rule: Rule,
@@ -24,33 +40,272 @@ pub struct Example {
     pub colour2: Color,
 }
 
+// Most generations advanced in one frame, so a slow sim does not fall further and further behind
+pub const MAX_GENERATIONS_PER_FRAME: u32 = 32;
+
+// Whether the generations advance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunState {
+    Running,
+    Paused,
+    // Advance this many more generations, then pause
+    Step(u32),
+}
+
+// Generations being worked out on another thread, which the active sim is moved to
+struct Worker {
+    task: Task<Finished>,
+    // Set to stop the sim before its next generation
+    cancelled: Arc<AtomicBool>,
+    started: Instant,
+}
+
+// The active sim once it has been moved ahead, to be moved back
+struct Finished {
+    sim: Box<dyn Sim>,
+    // What to show of the sim while it is moved ahead again
+    published: Published,
+    // Entries for the history after each update
+    recorder: Recorder,
+    entries: Vec<history::Entry>,
+    generations: u32,
+    duration: Duration,
+}
+
+// The last generation a worker published, which stands in for the active sim while it is on another thread
+// Settings which can change without a restart, such as the colours of species, are changed here and kept
+// once the sim is moved back, anything else waits for the sim first
+struct Published {
+    renderer: CellRenderer,
+    generation: u64,
+    count: usize,
+    offset: IVec3,
+    has_faces: bool,
+    competition: Option<Competition>,
+    step_exponent: Option<u8>,
+    cells_evaluated: Option<usize>,
+    populations: Vec<usize>,
+    cells_out_of_range: Option<u64>,
+}
+
+impl Published {
+    fn new(sim: &mut dyn Sim) -> Published {
+        let mut renderer = CellRenderer::new();
+        renderer.set_layout(sim.layout());
+        renderer.set_bounds(sim.get_bounds());
+        sim.render(&mut renderer);
+        Published {
+            renderer,
+            generation: sim.generation(),
+            count: sim.count(),
+            offset: sim.offset(),
+            has_faces: sim.has_faces(),
+            competition: sim.competition().cloned(),
+            step_exponent: sim.step_exponent_mut().copied(),
+            cells_evaluated: sim.cells_evaluated(),
+            populations: sim.populations(),
+            cells_out_of_range: sim.cells_out_of_range(),
+        }
+    }
+}
+
+fn moved() -> ! {
+    unreachable!("the active sim is changed while it is on another thread")
+}
+
+impl Sim for Published {
+    fn update(&mut self, _rule: &Rule, _task_pool: &TaskPool) {
+        moved()
+    }
+
+    fn render(&self, data: &mut CellRenderer) {
+        data.set_layout(self.renderer.layout);
+        data.set_bounds(self.renderer.bounds);
+        data.values.clone_from(&self.renderer.values);
+        data.neighbors.clone_from(&self.renderer.neighbors);
+        data.species.clone_from(&self.renderer.species);
+    }
+
+    fn spawn(&mut self, _rule: &Rule, _positions: &[IVec3]) {
+        moved()
+    }
+
+    fn set_live_cells(&mut self, _rule: &Rule, _cells: &[LiveCell], _generation: u64) {
+        moved()
+    }
+
+    fn set_rule(&mut self, _rule: &Rule) {
+        moved()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn set_boundaries(&mut self, _boundaries: Boundaries) {
+        moved()
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.renderer.bounds
+    }
+
+    fn set_bounds(&mut self, _new_bounds: IVec3) -> IVec3 {
+        moved()
+    }
+
+    fn offset(&self) -> IVec3 {
+        self.offset
+    }
+
+    fn layout(&self) -> Layout {
+        self.renderer.layout
+    }
+
+    fn set_layout(&mut self, _layout: Layout) -> Layout {
+        moved()
+    }
+
+    fn has_faces(&self) -> bool {
+        self.has_faces
+    }
+
+    fn competition(&self) -> Option<&Competition> {
+        self.competition.as_ref()
+    }
+
+    fn competition_mut(&mut self) -> Option<&mut Competition> {
+        self.competition.as_mut()
+    }
+
+    fn step_exponent_mut(&mut self) -> Option<&mut u8> {
+        self.step_exponent.as_mut()
+    }
+
+    fn cells_evaluated(&self) -> Option<usize> {
+        self.cells_evaluated
+    }
+
+    fn populations(&self) -> Vec<usize> {
+        self.populations.clone()
+    }
+
+    fn cells_out_of_range(&self) -> Option<u64> {
+        self.cells_out_of_range
+    }
+}
+
 #[derive(Resource)]
 pub struct Sims {
     pub sims: Vec<(String, Box<dyn Sim>)>,
     pub active_sim: usize,
-    pub bounds: i32,
-    pub update_duration: std::time::Duration,
+    pub bounds: IVec3,
+    // Keep the ratio between the sides when one is resized
+    pub lock_aspect: bool,
+    // Keep the middle of the pattern, instead of the middle of the box, when the box shrinks
+    pub crop_to_pattern: bool,
+    // Grow the box when live cells come within `expand_margin` of a face, up to `expand_limit` along each side
+    pub auto_expand: bool,
+    pub expand_margin: i32,
+    pub expand_limit: i32,
+    // Set while the pattern is near a face which can not move out any further
+    pub expand_capped: bool,
+    // Set when the limit is first reached, until the notice is closed
+    pub expand_notice: bool,
+    // Order of the cells in memory, for sims which support more than one
+    pub layout: Layout,
+    // Seed for the noise, so the same seed, rule and sim always give the same generations
+    pub seed: u64,
+    pub seed_text: String,
+    // Makes the noise, starting again from the seed whenever the sim restarts
+    pub noise: StdRng,
+    pub run_state: RunState,
+    // Target speed while running, which does not depend on the framerate
+    pub generations_per_second: f32,
+    // Part of a generation left over from earlier frames
+    pub pending_generations: f32,
+    // Generations advanced by the "Step N" button
+    pub step_count: u32,
+    // Past generations of the active sim, which can be gone back to
+    pub history: History,
+    worker: Option<Worker>,
+    // What the worker published of the active sim when it last finished, while nothing has changed the sim since
+    published: Option<Published>,
+    // Time taken by the last generation
+    pub update_duration: Duration,
     pub renderer: Option<Box<CellRenderer>>,
     pub rule: Option<Rule>,
     pub colour_method: ColourMethod,
     pub colour1: Color,
     pub colour2: Color,
     pub examples: Vec<Example>,
+    pub rule_text: String,
+    pub rule_error: Option<RuleParseError>,
+    pub neighbourhood_path: String,
+    pub neighbourhood_error: Option<NeighbourhoodError>,
+    pub class_weights: [u16; 3],
+    pub class_selection: [(u16, u16); 2],
+    pub schedule: Schedule,
+    // Entry of the schedule which was last switched to
+    pub schedule_entry: Option<usize>,
+    pub schedule_path: String,
+    pub schedule_error: Option<ScheduleError>,
+    pub preview_regions: bool,
+    pub region_path: String,
+    pub region_error: Option<RegionError>,
+    pub boundaries: Boundaries,
 }
 
 impl Sims {
     pub fn new() -> Sims {
+        let seed = rand::thread_rng().gen();
         Sims {
             sims: vec![],
             active_sim: usize::MAX,
-            bounds: 50,
-            update_duration: std::time::Duration::from_secs(0),
+            bounds: IVec3::splat(50),
+            lock_aspect: true,
+            crop_to_pattern: false,
+            auto_expand: false,
+            expand_margin: 4,
+            expand_limit: 256,
+            expand_capped: false,
+            expand_notice: false,
+            layout: Layout::Linear,
+            seed,
+            seed_text: seed.to_string(),
+            noise: StdRng::seed_from_u64(seed),
+            run_state: RunState::Running,
+            generations_per_second: 30.0,
+            pending_generations: 0.0,
+            step_count: 10,
+            history: History::new(history::DEFAULT_BUDGET),
+            worker: None,
+            published: None,
+            update_duration: Duration::from_secs(0),
             renderer: Some(Box::new(CellRenderer::new())),
             rule: None,
             colour_method: ColourMethod::DistToCenter,
             colour1: Color::NONE,
             colour2: Color::NONE,
             examples: vec![],
+            rule_text: String::new(),
+            rule_error: None,
+            neighbourhood_path: String::new(),
+            neighbourhood_error: None,
+            class_weights: [1, 1, 1],
+            class_selection: [(0, 0); 2],
+            schedule: Schedule::default(),
+            schedule_entry: None,
+            schedule_path: String::new(),
+            schedule_error: None,
+            preview_regions: false,
+            region_path: String::new(),
+            region_error: None,
+            boundaries: PERIODIC,
         }
     }
 
@@ -63,6 +318,7 @@ impl Sims {
     }
 
     pub fn set_sim(&mut self, index: usize) {
+        self.wait_for_step();
         if self.active_sim < self.sims.len() {
             self.sims[self.active_sim].1.reset();
         }
@@ -71,11 +327,252 @@ impl Sims {
 
         self.active_sim = index;
         self.bounds = self.sims[index].1.set_bounds(self.bounds);
-        self.sims[index].1.spawn_noise(&rule);
+        self.sims[index].1.set_layout(self.layout);
+        self.sims[index].1.set_boundaries(self.boundaries);
+        self.restart(&rule);
         self.renderer.as_mut().unwrap().set_bounds(self.bounds);
         self.rule = Some(rule);
     }
 
+    // Clear the cells of the active sim, and spawn the noise for the seed
+    pub fn restart(&mut self, rule: &Rule) {
+        self.wait_for_step();
+        self.noise = StdRng::seed_from_u64(self.seed);
+        let sim = &mut self.sims[self.active_sim].1;
+        sim.reset();
+        sim.spawn_noise(rule, &mut self.noise);
+        self.history.clear();
+        self.record_history();
+    }
+
+    // Change the size of the box, keeping the pattern in the middle of it and counting the neighbours again
+    // Cells which no longer fit are cropped, around the centre of the pattern if `crop_to_pattern` is set
+    pub fn resize(&mut self, rule: &Rule, bounds: IVec3) -> IVec3 {
+        self.wait_for_step();
+        let sim = &mut self.sims[self.active_sim].1;
+        let mut cells = sim.live_cells();
+        let generation = sim.generation();
+        if self.crop_to_pattern && sim.has_faces() && bounds.cmplt(self.bounds).any() && !cells.is_empty() {
+            let total: Vec3 = cells.iter().map(|cell| cell.position.as_vec3()).sum();
+            let centre = (total / cells.len() as f32).round().as_ivec3();
+            for cell in &mut cells {
+                cell.position -= centre;
+            }
+        }
+        self.bounds = sim.set_bounds(bounds);
+        sim.set_live_cells(rule, &cells, generation);
+        self.renderer.as_mut().unwrap().set_bounds(self.bounds);
+        self.record_history();
+        self.bounds
+    }
+
+    // Grow the box along each axis where live cells are within the margin of a face, keeping the pattern in place
+    // Each axis grows by half again, up to the limit, and a notice is shown when the limit is first reached
+    pub fn expand(&mut self) {
+        let sim = &self.sims[self.active_sim].1;
+        if !self.auto_expand || !sim.has_faces() {
+            return;
+        }
+        let near = near_faces(&sim.live_cells(), self.bounds, self.expand_margin);
+        let limit = IVec3::splat(self.expand_limit);
+        let grown = (self.bounds + (self.bounds / 2).max(IVec3::splat(2 * self.expand_margin))).min(limit);
+        let bounds = IVec3::select(near, grown.max(self.bounds), self.bounds);
+
+        let capped = (near & self.bounds.cmpge(limit)).any();
+        if capped && !self.expand_capped {
+            self.expand_notice = true;
+        }
+        self.expand_capped = capped;
+        if bounds != self.bounds {
+            let rule = self.rule.take().unwrap();
+            self.resize(&rule, bounds);
+            self.rule = Some(rule);
+        }
+    }
+
+    // Clear the cells of the active sim, without spawning anything
+    pub fn reset(&mut self) {
+        self.wait_for_step();
+        self.sims[self.active_sim].1.reset();
+        self.history.clear();
+        self.record_history();
+    }
+
+    // Spawn more noise, carrying on from the last noise since the restart
+    pub fn spawn_noise(&mut self, rule: &Rule) {
+        self.wait_for_step();
+        self.sims[self.active_sim].1.spawn_noise(rule, &mut self.noise);
+        self.record_history();
+    }
+
+    // Start moving the active sim ahead on another thread, showing the last generation it published until it has finished
+    // The sim is moved rather than copied, so the settings which need it wait for it to come back
+    pub fn start_step(&mut self, generations: u32) {
+        self.stop_worker();
+        let active = self.active_sim;
+        // Settings changed since the sim was last published are kept while it is away
+        let published = match self.published.take() {
+            Some(mut published) => {
+                let sim = &mut self.sims[active].1;
+                published.competition = sim.competition().cloned();
+                published.step_exponent = sim.step_exponent_mut().copied();
+                published
+            }
+            None => Published::new(self.sims[active].1.as_mut()),
+        };
+        let mut sim = std::mem::replace(&mut self.sims[active].1, Box::new(published));
+        let mut rule = self.rule.clone().unwrap();
+        let schedule = self.schedule.clone();
+        let mut schedule_entry = self.schedule_entry;
+        // Stop early once the pattern needs more space, so the box can grow before it reaches a face
+        let expand_margin = (self.auto_expand && !self.expand_capped && sim.has_faces()).then_some(self.expand_margin);
+        let bounds = self.bounds;
+        // Listing the live cells takes a pass over every cell, so it is skipped when nothing needs them
+        let recording = self.history.budget() > 0;
+        let list_cells = recording || expand_margin.is_some();
+        let mut recorder = self.history.recorder(sim.generation() + 1);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop = cancelled.clone();
+
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let started = Instant::now();
+            let mut entries = vec![];
+            let mut finished = 0;
+            while finished < generations && !stop.load(Ordering::Relaxed) {
+                // Switch to the schedule's entries as `run_schedule` does
+                let active = schedule.enabled.then(|| schedule.active(sim.generation())).flatten();
+                if active != schedule_entry {
+                    schedule_entry = active;
+                    if let Some(index) = active {
+                        rule = schedule.entries[index].example.rule.clone();
+                        sim.set_rule(&rule);
+                    }
+                }
+                sim.update(&rule, AsyncComputeTaskPool::get());
+                finished += 1;
+                let cells = match list_cells {
+                    true => sim.live_cells(),
+                    false => vec![],
+                };
+                if recording {
+                    entries.push(recorder.entry(sim.generation(), &cells));
+                }
+                if expand_margin.is_some_and(|margin| near_faces(&cells, bounds, margin).any()) {
+                    break;
+                }
+            }
+            Finished {
+                published: Published::new(sim.as_mut()),
+                sim,
+                recorder,
+                entries,
+                generations: finished,
+                duration: started.elapsed(),
+            }
+        });
+        self.worker = Some(Worker {
+            task,
+            cancelled,
+            started: Instant::now(),
+        });
+    }
+
+    // Move the active sim back once it has finished, and add its generations to the history
+    pub fn finish_step(&mut self) -> bool {
+        let Some(worker) = &mut self.worker else {
+            return false;
+        };
+        let Some(finished) = future::block_on(future::poll_once(&mut worker.task)) else {
+            return false;
+        };
+        self.worker = None;
+        self.move_back(finished);
+        true
+    }
+
+    // Stop the active sim after the generation in progress, which is still shown once it has finished
+    pub fn cancel_step(&mut self) {
+        if let Some(worker) = &self.worker {
+            worker.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Stop the active sim after the generation in progress and wait for it to be moved back, so it can be changed
+    // Anything which changes the sim waits first, so what was published of it is no longer shown
+    pub fn wait_for_step(&mut self) {
+        self.stop_worker();
+        self.published = None;
+    }
+
+    // Stop the active sim after the generation in progress, and move it back once it has finished
+    fn stop_worker(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.cancelled.store(true, Ordering::Relaxed);
+            self.move_back(future::block_on(worker.task));
+        }
+    }
+
+    // Put the active sim back in place of what it published, keeping the settings changed while it was away
+    fn move_back(&mut self, finished: Finished) {
+        let mut sim = finished.sim;
+        let shown = &mut self.sims[self.active_sim].1;
+        if let (Some(step_exponent), Some(finished)) = (shown.step_exponent_mut(), sim.step_exponent_mut()) {
+            *finished = *step_exponent;
+        }
+        if let (Some(competition), Some(finished)) = (shown.competition(), sim.competition_mut()) {
+            *finished = competition.clone();
+        }
+        *shown = sim;
+        self.published = Some(finished.published);
+        self.history.append(finished.recorder, finished.entries);
+        if finished.generations > 0 {
+            self.update_duration = finished.duration / finished.generations;
+        }
+        self.run_schedule();
+    }
+
+    // Time spent working out the generations in progress, if there are any
+    pub fn step_time(&self) -> Option<Duration> {
+        self.worker.as_ref().map(|worker| worker.started.elapsed())
+    }
+
+    // Keep the cells of the active sim, replacing any history from its generation on
+    fn record_history(&mut self) {
+        if self.history.budget() == 0 {
+            self.history.clear();
+            return;
+        }
+        let sim = &self.sims[self.active_sim].1;
+        self.history.record(sim.generation(), &sim.live_cells());
+    }
+
+    // Go back, or forward, to the newest generation in the history at or before this one, and pause there
+    // Carrying on from there starts a new history
+    pub fn rewind(&mut self, generation: u64) {
+        self.wait_for_step();
+        let Some((generation, cells)) = self.history.get(generation) else {
+            return;
+        };
+        self.run_state = RunState::Paused;
+        let rule = self.rule.take().unwrap();
+        self.sims[self.active_sim].1.set_live_cells(&rule, &cells, generation);
+        self.rule = Some(rule);
+        // The schedule's entry for that generation is switched to, if it has one
+        self.schedule_entry = None;
+        self.run_schedule();
+    }
+
+    // Use a new seed, and restart with it
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.seed_text = seed.to_string();
+        if self.active_sim < self.sims.len() {
+            let rule = self.rule.take().unwrap();
+            self.restart(&rule);
+            self.rule = Some(rule);
+        }
+    }
+
     pub fn set_example(&mut self, index: usize) {
         let example = self.examples[index].clone();
         let rule = example.rule;
@@ -84,12 +581,76 @@ impl Sims {
         self.colour2 = example.colour2;
 
         if self.active_sim < self.sims.len() {
-            let sim = &mut self.sims[self.active_sim].1;
-            sim.reset();
-            sim.spawn_noise(&rule);
+            self.restart(&rule);
         }
         self.rule = Some(rule);
     }
+
+    // Build up the generations due in a frame, given the seconds since the last one
+    // This carries on while generations are worked out, so the time they take is not lost
+    pub fn add_frame_time(&mut self, delta: f32) {
+        match self.run_state {
+            RunState::Running => {
+                self.pending_generations += delta * self.generations_per_second;
+                self.pending_generations = self.pending_generations.min(MAX_GENERATIONS_PER_FRAME as f32);
+            }
+            RunState::Paused => self.pending_generations = 0.0,
+            RunState::Step(_) => {}
+        }
+    }
+
+    // Number of generations to start advancing, taken from the ones which are due
+    pub fn generations_due(&mut self) -> u32 {
+        match self.run_state {
+            RunState::Running => {
+                let due = self.pending_generations.floor();
+                self.pending_generations -= due;
+                due as u32
+            }
+            RunState::Paused => 0,
+            RunState::Step(remaining) => {
+                let due = remaining.min(MAX_GENERATIONS_PER_FRAME);
+                self.run_state = match remaining - due {
+                    0 => RunState::Paused,
+                    remaining => RunState::Step(remaining),
+                };
+                due
+            }
+        }
+    }
+
+    // Switch to the rule and colours of the schedule's entry for the current generation
+    // The cells are kept, so the new rule carries on from the current pattern
+    pub fn run_schedule(&mut self) {
+        if !self.schedule.enabled {
+            self.schedule_entry = None;
+            return;
+        }
+        let sim = &mut self.sims[self.active_sim].1;
+        let active = self.schedule.active(sim.generation());
+        if active == self.schedule_entry {
+            return;
+        }
+        self.schedule_entry = active;
+
+        if let Some(index) = active {
+            let example = self.schedule.entries[index].example.clone();
+            sim.set_rule(&example.rule);
+            self.colour_method = example.colour_method;
+            self.colour1 = example.colour1;
+            self.colour2 = example.colour2;
+            self.rule = Some(example.rule);
+        }
+    }
+}
+
+// Axes along which live cells are within a margin of a face
+fn near_faces(cells: &[LiveCell], bounds: IVec3, margin: i32) -> BVec3 {
+    let centre = utilities::get_centre(bounds);
+    cells.iter().fold(BVec3::FALSE, |near, cell| {
+        let position = cell.position + centre;
+        near | position.cmplt(IVec3::splat(margin)) | position.cmpge(bounds - margin)
+    })
 }
 
 pub struct SimsPlugin;
@@ -104,52 +665,88 @@ impl Plugin for SimsPlugin {
 pub fn update(
     mut current: ResMut<Sims>,
     mut query: Query<&mut InstanceMaterialData>,
+    time: Res<Time>,
 ) {
     if current.active_sim > current.sims.len() {
         current.set_sim(0);
     }
-    let bounds = current.bounds;
     let active_sim = current.active_sim;
+
+    // Generations are worked out on another thread, so a slow rule never holds up the camera or settings
+    // Once they are shown, as many generations as are due are started, which can be none or several
+    if current.finish_step() {
+        current.expand();
+    }
+    current.add_frame_time(time.delta_seconds());
+    if current.step_time().is_none() {
+        let due = current.generations_due();
+        if due > 0 {
+            current.start_step(due);
+        }
+    }
+
     let rule = current.rule.take().unwrap();
     let mut renderer = current.renderer.take().unwrap();
-
     let sim = &mut current.sims[active_sim].1;
-
-    let t0 = std::time::Instant::now();
-    sim.update(&rule, AsyncComputeTaskPool::get());
-    let update_dt = t0.elapsed();
+    // Sims without a box only render the part of the space they use
+    let bounds = sim.get_bounds();
+    let offset = sim.offset();
+    renderer.set_layout(sim.layout());
+    renderer.set_bounds(bounds);
     sim.render(&mut renderer);
+    // Sims with species give each its own rule and colour
+    let species = sim
+        .competition()
+        .map(|competition| competition.species.clone())
+        .unwrap_or_default();
 
     let instance_data = &mut query.iter_mut().next().unwrap().0;
     instance_data.truncate(0);
     for index in 0..renderer.cell_count() {
         let value = renderer.values[index];
         let neighbors = renderer.neighbors[index];
+        let pos = renderer.position(index);
+
+        // Show the regions as small cells where there are no live ones
+        // Positions are relative to the centre of the space, so no bounds are needed to find the region
+        // Padding from the layout is past the edge of the box, so it is skipped
+        if value == 0 && current.preview_regions && !rule.regions.is_empty() && pos.cmplt(bounds).all() {
+            if let Some(region) = rule.region_at(pos + offset, IVec3::ZERO) {
+                instance_data.push(InstanceData {
+                    position: (pos + offset).as_vec3(),
+                    scale: 0.2,
+                    color: region.colour.into(),
+                });
+            }
+        }
 
         if value != 0 {
-            let pos = utilities::idx_to_pos(index as i32, bounds);
+            let (colour1, rule) = match species.get(renderer.species[index] as usize) {
+                Some(species) => (species.colour, &species.rule),
+                None => (current.colour1, rule.rule_at(pos + offset, IVec3::ZERO)),
+            };
             instance_data.push(InstanceData {
-                position: (pos - utilities::get_centre(bounds)).as_vec3(),
+                position: (pos + offset).as_vec3(),
                 scale: 1.0,
                 color: current
                     .colour_method
                     .set_colour(
-                        current.colour1,
+                        colour1,
                         current.colour2,
                         value,
                         rule.states,
-                        neighbors,
+                        rule.neighbour_total(neighbors),
+                        rule.max_neighbours(),
                         utilities::get_dist_to_centre(pos, bounds),
-                        index,
-                        renderer.cell_count(),
+                        // Colour by the position in the box, so each layout looks the same
+                        utilities::pos_to_idx(pos, bounds),
+                        utilities::cell_count(bounds),
                     )
                     .into(),
             });
         }
     }
-    current.bounds = bounds;
     current.active_sim = active_sim;
-    current.update_duration = update_dt;
     current.renderer = Some(renderer);
     current.rule = Some(rule);
 }
@@ -159,6 +756,7 @@ mod tests {
     use crate::cells::{multi_dimensional};
     use crate::neighbours::Neighbourhood::*;
     use crate::rule::Value;
+    use bevy::{math::ivec3, tasks::TaskPool};
     use super::*;
 
     #[test]
@@ -166,7 +764,21 @@ mod tests {
         let sims = Sims::new();
         assert_eq!(sims.sims.len(), 0);
         assert_eq!(sims.active_sim, usize::MAX);
-        assert_eq!(sims.bounds, 50);
+        assert_eq!(sims.bounds, IVec3::splat(50));
+        assert!(sims.lock_aspect);
+        assert!(!sims.crop_to_pattern);
+        assert!(!sims.auto_expand);
+        assert_eq!(sims.expand_margin, 4);
+        assert_eq!(sims.expand_limit, 256);
+        assert!(!sims.expand_capped);
+        assert!(!sims.expand_notice);
+        assert_eq!(sims.layout, Layout::Linear);
+        assert_eq!(sims.seed_text, sims.seed.to_string());
+        assert_eq!(sims.run_state, RunState::Running);
+        assert_eq!(sims.generations_per_second, 30.0);
+        assert_eq!(sims.pending_generations, 0.0);
+        assert_eq!(sims.step_count, 10);
+        assert_eq!(sims.history.range(), None);
         assert_eq!(sims.update_duration.as_nanos(), 0);
         assert!(sims.renderer.is_some());
         assert!(sims.rule.is_none());
@@ -174,6 +786,20 @@ mod tests {
         assert_eq!(sims.colour1, Color::NONE);
         assert_eq!(sims.colour2, Color::NONE);
         assert_eq!(sims.examples.len(), 0);
+        assert!(sims.rule_text.is_empty());
+        assert!(sims.rule_error.is_none());
+        assert!(sims.neighbourhood_path.is_empty());
+        assert!(sims.neighbourhood_error.is_none());
+        assert_eq!(sims.class_weights, [1, 1, 1]);
+        assert_eq!(sims.class_selection, [(0, 0); 2]);
+        assert!(sims.schedule.entries.is_empty());
+        assert!(sims.schedule_entry.is_none());
+        assert!(sims.schedule_path.is_empty());
+        assert!(sims.schedule_error.is_none());
+        assert!(!sims.preview_regions);
+        assert!(sims.region_path.is_empty());
+        assert!(sims.region_error.is_none());
+        assert_eq!(sims.boundaries, PERIODIC);
     }
 
     #[test]
@@ -196,6 +822,7 @@ mod tests {
                 birth: Value::new(&[4]),
                 states: 5,
                 neighbourhood: Moore,
+                ..Default::default()
             },
             colour_method: ColourMethod::DistToCenter,
             colour1: Color::RED,
@@ -214,10 +841,11 @@ mod tests {
             birth: Value::new(&[4]),
             states: 5,
             neighbourhood: Moore,
+            ..Default::default()
         };
         let example = Example {
             name: "TestExample".into(),
-            rule,
+            rule: rule.clone(),
             colour_method: ColourMethod::DistToCenter,
             colour1: Color::RED,
             colour2: Color::BLUE,
@@ -231,4 +859,365 @@ mod tests {
         assert_eq!(sims.colour2, Color::BLUE);
         assert_eq!(sims.rule.unwrap(), rule);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_generations_due() {
+        let mut sims = Sims::new();
+        sims.generations_per_second = 10.0;
+        let frame = |sims: &mut Sims, delta| {
+            sims.add_frame_time(delta);
+            sims.generations_due()
+        };
+
+        // A quarter of a generation each frame
+        let due: Vec<u32> = (0..8).map(|_| frame(&mut sims, 0.025)).collect();
+        assert_eq!(due.iter().sum::<u32>(), 2);
+        assert_eq!(frame(&mut sims, 1.0), 10);
+        // A long frame does not build up generations to catch up on
+        assert_eq!(frame(&mut sims, 100.0), MAX_GENERATIONS_PER_FRAME);
+        assert_eq!(frame(&mut sims, 0.0), 0);
+
+        // Frames while generations are worked out still count, up to the same limit
+        for _ in 0..3 {
+            sims.add_frame_time(0.1);
+        }
+        assert_eq!(sims.generations_due(), 3);
+        for _ in 0..2 {
+            sims.add_frame_time(100.0);
+        }
+        assert_eq!(sims.generations_due(), MAX_GENERATIONS_PER_FRAME);
+
+        sims.run_state = RunState::Paused;
+        assert_eq!(frame(&mut sims, 1.0), 0);
+
+        // Steps happen whatever the speed, then pause
+        sims.run_state = RunState::Step(1);
+        assert_eq!(frame(&mut sims, 0.0), 1);
+        assert_eq!(sims.run_state, RunState::Paused);
+        sims.run_state = RunState::Step(40);
+        assert_eq!(frame(&mut sims, 0.0), MAX_GENERATIONS_PER_FRAME);
+        assert_eq!(frame(&mut sims, 0.0), 8);
+        assert_eq!(sims.run_state, RunState::Paused);
+    }
+
+    // Sims with every sim the app registers
+    fn registered() -> Sims {
+        let mut sims = Sims::new();
+        crate::setup::add_sims(&mut sims);
+        sims
+    }
+
+    #[test]
+    fn test_seed() {
+        let task_pool = TaskPool::new();
+        let rule: Rule = "4/4/5/M".parse().unwrap();
+        let run = |seed, index| {
+            let mut sims = registered();
+            sims.rule = Some(rule.clone());
+            sims.set_seed(seed);
+            sims.set_sim(index);
+            let mut renderer = CellRenderer::new();
+            let sim = &mut sims.sims[index].1;
+            for _ in 0..5 {
+                sim.update(&rule, &task_pool);
+            }
+            renderer.set_bounds(sim.get_bounds());
+            sim.render(&mut renderer);
+            (sim.offset(), renderer.values)
+        };
+
+        // The same seed always gives the same generations, in every sim
+        for index in 0..registered().sims.len() {
+            assert_eq!(run(3, index), run(3, index));
+        }
+        assert_ne!(run(3, 0), run(4, 0));
+    }
+
+    // Move the active sim ahead on another thread, and wait for it to be shown
+    fn step(sims: &mut Sims, generations: u32) {
+        AsyncComputeTaskPool::init(TaskPool::new);
+        sims.start_step(generations);
+        while !sims.finish_step() {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_live_cells() {
+        let sorted = |mut cells: Vec<LiveCell>| {
+            cells.sort_by_key(|cell| cell.position.to_array());
+            cells
+        };
+        // Packed cells are only used with 2 states, and padding only with tiles
+        for rule in ["4/4/5/M", "4/4/2/M"] {
+            for layout in [Layout::Linear, Layout::Tiled] {
+                for index in 0..registered().sims.len() {
+                    let mut sims = registered();
+                    sims.bounds = ivec3(70, 13, 20);
+                    sims.rule = Some(rule.parse().unwrap());
+                    sims.layout = layout;
+                    sims.set_sim(index);
+                    step(&mut sims, 3);
+
+                    // Sims which find their cells without rendering find the same ones
+                    let (name, sim) = &sims.sims[index];
+                    if sim.has_faces() {
+                        let cells = sorted(sim.live_cells());
+                        assert!(!cells.is_empty(), "{}", name);
+                        assert_eq!(cells, sorted(crate::cells::rendered_cells(sim.as_ref())), "{}", name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewind() {
+        let cells = |sims: &Sims| {
+            let mut cells = sims.sims[sims.active_sim].1.live_cells();
+            cells.sort_by_key(|cell| cell.position.to_array());
+            cells
+        };
+        // Packed cells are only used with 2 states
+        for rule in ["4/4/5/M", "4/4/2/M"] {
+            for index in 0..registered().sims.len() {
+                let mut sims = registered();
+                sims.bounds = IVec3::splat(20);
+                sims.rule = Some(rule.parse().unwrap());
+                sims.set_sim(index);
+                let mut generations = vec![cells(&sims)];
+                for _ in 0..6 {
+                    step(&mut sims, 1);
+                    generations.push(cells(&sims));
+                }
+                assert_eq!(sims.history.range(), Some((0, 6)));
+                assert_ne!(generations[2], generations[3]);
+
+                let name = sims.sims[index].0.clone();
+                sims.rewind(2);
+                assert_eq!(sims.run_state, RunState::Paused);
+                assert_eq!(sims.sims[index].1.generation(), 2, "{}", name);
+                assert_eq!(cells(&sims), generations[2], "{}", name);
+
+                // Carrying on gives the same generations, which replace the ones after
+                step(&mut sims, 1);
+                assert_eq!(cells(&sims), generations[3], "{}", name);
+                assert_eq!(sims.history.range(), Some((0, 3)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_resize() {
+        let cells = |sims: &Sims| {
+            let mut cells = sims.sims[sims.active_sim].1.live_cells();
+            cells.sort_by_key(|cell| cell.position.to_array());
+            cells
+        };
+        let mut results = vec![];
+        for index in 0..registered().sims.len() {
+            let mut sims = registered();
+            sims.bounds = IVec3::splat(20);
+            sims.rule = Some("4/4/5/M".parse().unwrap());
+            sims.set_seed(1);
+            sims.set_sim(index);
+            step(&mut sims, 3);
+            let before = cells(&sims);
+
+            // Grow along x, and shrink along y
+            let rule = sims.rule.take().unwrap();
+            let bounds = sims.resize(&rule, ivec3(26, 12, 20));
+            let sim = &sims.sims[index].1;
+            let name = sims.sims[index].0.clone();
+            assert_eq!(sim.generation(), 3, "{}", name);
+            let expected: Vec<LiveCell> = match sim.has_faces() {
+                true => before
+                    .into_iter()
+                    .filter(|cell| utilities::in_bounds(cell.position + utilities::get_centre(bounds), bounds))
+                    .collect(),
+                // Sims with no faces have nothing to crop
+                false => before,
+            };
+            assert_eq!(cells(&sims), expected, "{}", name);
+            assert_eq!(sims.history.get(3).map(|(_, cells)| cells.len()), Some(expected.len()));
+
+            // Neighbours are counted again, so every sim carries on the same way
+            let supported = sim.supports(&rule);
+            sims.rule = Some(rule);
+            step(&mut sims, 3);
+            if supported {
+                results.push((sims.sims[index].1.has_faces(), name, cells(&sims)));
+            }
+        }
+        for has_faces in [true, false] {
+            let group: Vec<_> = results.iter().filter(|result| result.0 == has_faces).collect();
+            assert!(!group[0].2.is_empty());
+            for (_, name, cells) in &group[1..] {
+                assert_eq!(cells, &group[0].2, "{} and {}", name, group[0].1);
+            }
+        }
+
+        // Shrinking around the pattern keeps a pattern which is away from the centre
+        let mut sims = Sims::new();
+        crate::setup::add_sims(&mut sims);
+        sims.bounds = IVec3::splat(20);
+        sims.rule = Some("4/4/5/M".parse().unwrap());
+        sims.set_sim(0);
+        let rule = sims.rule.take().unwrap();
+        let pattern: Vec<LiveCell> = [ivec3(6, 6, 6), ivec3(7, 6, 6), ivec3(6, 7, 6)]
+            .map(|position| LiveCell { position, state: 5, species: 0 })
+            .to_vec();
+        sims.sims[0].1.set_live_cells(&rule, &pattern, 0);
+        sims.resize(&rule, IVec3::splat(6));
+        assert_eq!(cells(&sims), []);
+        sims.resize(&rule, IVec3::splat(20));
+        sims.sims[0].1.set_live_cells(&rule, &pattern, 0);
+        sims.crop_to_pattern = true;
+        sims.resize(&rule, IVec3::splat(6));
+        assert_eq!(cells(&sims).len(), 3);
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut sims = Sims::new();
+        crate::setup::add_sims(&mut sims);
+        sims.bounds = IVec3::splat(20);
+        // Cells with a neighbour are born and none die, so a single cell grows one cell each way every generation
+        sims.rule = Some("0-26/1-26/2/M".parse().unwrap());
+        sims.set_sim(0);
+        let rule = sims.rule.take().unwrap();
+        let seed = LiveCell { position: IVec3::ZERO, state: 2, species: 0 };
+        sims.sims[0].1.set_live_cells(&rule, &[seed], 0);
+        sims.rule = Some(rule);
+        sims.auto_expand = true;
+        sims.expand_limit = 30;
+
+        // The step stops once the pattern comes within 4 cells of a face, and the box grows around it
+        step(&mut sims, 32);
+        assert_eq!(sims.sims[0].1.generation(), 6);
+        sims.expand();
+        assert_eq!(sims.bounds, IVec3::splat(30));
+        let cells = sims.sims[0].1.live_cells();
+        assert_eq!(cells.len(), 13 * 13 * 13);
+        assert!(cells.iter().all(|cell| cell.position.abs().max_element() <= 6));
+        assert!(!sims.expand_capped);
+        assert!(!sims.expand_notice);
+
+        // The box does not grow past the limit, which is noticed once
+        step(&mut sims, 32);
+        assert_eq!(sims.sims[0].1.generation(), 11);
+        sims.expand();
+        assert_eq!(sims.bounds, IVec3::splat(30));
+        assert!(sims.expand_capped);
+        assert!(sims.expand_notice);
+        sims.expand_notice = false;
+        step(&mut sims, 2);
+        sims.expand();
+        assert!(!sims.expand_notice);
+
+        // Sims without faces never grow
+        let unbounded = sims.sims.iter().position(|(_, sim)| !sim.has_faces()).unwrap();
+        sims.set_sim(unbounded);
+        sims.expand_limit = 512;
+        sims.expand();
+        assert_eq!(sims.bounds, IVec3::splat(30));
+    }
+
+    #[test]
+    fn test_step_in_background() {
+        AsyncComputeTaskPool::init(TaskPool::new);
+        let mut sims = registered();
+        sims.bounds = IVec3::splat(20);
+        sims.rule = Some("4/4/5/M".parse().unwrap());
+        // The species sim, as its colours can change while it runs
+        let species = sims.sims.iter().position(|(_, sim)| sim.competition().is_some()).unwrap();
+        sims.set_sim(species);
+
+        // The last published generation is shown until the generations are finished
+        let shown = |sims: &Sims| crate::cells::rendered_cells(sims.sims[species].1.as_ref());
+        let before = shown(&sims);
+        sims.start_step(5);
+        assert!(sims.step_time().is_some());
+        assert_eq!(sims.sims[species].1.generation(), 0);
+        assert_eq!(shown(&sims), before);
+        while !sims.finish_step() {
+            std::thread::yield_now();
+        }
+        assert!(sims.step_time().is_none());
+        assert_eq!(sims.sims[species].1.generation(), 5);
+        assert_eq!(sims.history.range(), Some((0, 5)));
+
+        // Settings changed while the generations are worked out are kept
+        sims.start_step(1);
+        sims.sims[species].1.competition_mut().unwrap().species[0].colour = Color::GREEN;
+        while !sims.finish_step() {
+            std::thread::yield_now();
+        }
+        assert_eq!(sims.sims[species].1.generation(), 6);
+        assert_eq!(sims.sims[species].1.competition().unwrap().species[0].colour, Color::GREEN);
+
+        // Cancelling stops after the generation in progress, which is still shown and kept
+        sims.start_step(1000);
+        sims.cancel_step();
+        while !sims.finish_step() {
+            std::thread::yield_now();
+        }
+        let generation = sims.sims[species].1.generation();
+        assert!(generation < 1006);
+        assert_eq!(sims.history.range(), Some((0, generation)));
+
+        // Changing the sim waits for it to come back first
+        sims.start_step(1000);
+        sims.reset();
+        assert!(sims.step_time().is_none());
+        assert_eq!(sims.sims[species].1.generation(), 0);
+        assert_eq!(sims.history.range(), Some((0, 0)));
+
+        // Nothing is kept once the history has no budget
+        sims.history.set_budget(0);
+        step(&mut sims, 3);
+        assert_eq!(sims.sims[species].1.generation(), 3);
+        assert_eq!(sims.history.range(), None);
+        sims.reset();
+        assert_eq!(sims.history.range(), None);
+    }
+
+    #[test]
+    fn test_run_schedule() {
+        let mut sims = Sims::new();
+        sims.add_sim("TestSim".into(), Box::new(multi_dimensional::MultiDimensional::new()));
+        sims.rule = Some(Rule::default());
+        sims.set_sim(0);
+
+        let example = |rule: &str, colour_method| Example {
+            name: "TestExample".into(),
+            rule: rule.parse().unwrap(),
+            colour_method,
+            colour1: Color::RED,
+            colour2: Color::BLUE,
+        };
+        sims.schedule.add(0, example("4/4/5/M", ColourMethod::State));
+        sims.schedule.add(2, example("0-6/1,3/2/N", ColourMethod::Index));
+
+        // Nothing changes until the schedule is enabled
+        sims.run_schedule();
+        assert_eq!(sims.rule.as_ref().unwrap().states, 2);
+
+        sims.schedule.enabled = true;
+        sims.run_schedule();
+        assert_eq!(sims.schedule_entry, Some(0));
+        assert_eq!(sims.rule.as_ref().unwrap().states, 5);
+        assert_eq!(sims.colour_method, ColourMethod::State);
+
+        let task_pool = TaskPool::new();
+        for _ in 0..2 {
+            let rule = sims.rule.take().unwrap();
+            sims.sims[0].1.update(&rule, &task_pool);
+            sims.rule = Some(rule);
+        }
+        sims.run_schedule();
+        assert_eq!(sims.schedule_entry, Some(1));
+        assert_eq!(sims.rule.as_ref().unwrap().neighbourhood, VonNeumann);
+        assert_eq!(sims.colour_method, ColourMethod::Index);
+    }
+}
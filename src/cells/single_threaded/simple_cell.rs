@@ -1,15 +1,18 @@
 use bevy::{math::IVec3, tasks::TaskPool};
 
 use crate::{
+    boundary::{resolve, wall_neighbours, Boundaries, Neighbour, PERIODIC},
+    cells::LiveCell,
+    layout::Layout,
     render::CellRenderer,
     rule::Rule,
-    utilities::{default_noise, get_centre, idx_to_pos, pos_to_idx, wrap},
+    utilities::{cell_count, get_centre, in_bounds},
 };
 
 #[derive(Clone, Copy)]
 struct SimpleCell {
     state: u8,
-    neighbours: u8,
+    neighbours: u16,
 }
 
 impl SimpleCell {
@@ -28,32 +31,66 @@ impl SimpleCell {
 
 pub struct SingleThreaded {
     cells: Vec<SimpleCell>,
-    bounds: i32,
-    // group: u8, // todo! Different cell types that compete
+    bounds: IVec3,
+    // Order of the cells in memory
+    layout: Layout,
+    // Number of updates since the cells were cleared, for stochastic rules
+    generation: u64,
+    boundaries: Boundaries,
+    // Cells whose state or neighbours changed in the last update, which are the only ones that can change in the next
+    active: Vec<usize>,
+    // True for each cell which is in `active`
+    changed: Vec<bool>,
+    // Set when every cell must be checked, such as after the rule changes
+    evaluate_all: bool,
+    // Rule used in the last update, to notice when it changes
+    last_rule: Option<Rule>,
+    // Number of cells checked in the last update
+    evaluated: usize,
 }
 
 impl SingleThreaded {
     pub fn new() -> Self {
         SingleThreaded {
             cells: vec![],
-            bounds: 0,
+            bounds: IVec3::ZERO,
+            layout: Layout::Linear,
+            generation: 0,
+            boundaries: PERIODIC,
+            active: vec![],
+            changed: vec![],
+            evaluate_all: true,
+            last_rule: None,
+            evaluated: 0,
         }
     }
 
     // Set the boundary for cells
-    pub fn set_bounds(&mut self, new_bounds: i32) -> i32 {
+    pub fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
         // Check if bounds has changed
         if new_bounds != self.bounds {
             // Clear the array
             self.cells.clear();
-            // Initialise vector of cells, with length bounds^3
+            // Initialise vector of cells, with one for each position in the bounds, and any padding
             self.cells
-                .resize((new_bounds.pow(3)) as usize, SimpleCell::new());
+                .resize(self.layout.len(new_bounds), SimpleCell::new());
             self.bounds = new_bounds;
+            self.generation = 0;
+            self.changed = vec![false; self.cells.len()];
+            self.active.clear();
+            self.evaluate_all = true;
         }
         self.bounds
     }
 
+    // Check a cell in the next update
+    fn mark(&mut self, index: usize) {
+        if !self.changed[index] {
+            self.changed[index] = true;
+            self.active.push(index);
+        }
+    }
+
     // Count the number of live cells
     pub fn count_cells(&self) -> usize {
         let mut result: usize = 0;
@@ -67,54 +104,108 @@ impl SingleThreaded {
         result
     }
 
+    // Store the cells in a new order, which clears them
+    pub fn set_layout(&mut self, layout: Layout) -> Layout {
+        if layout != self.layout {
+            let bounds = self.bounds;
+            self.set_bounds(IVec3::ZERO);
+            self.layout = layout;
+            self.set_bounds(bounds);
+        }
+        self.layout
+    }
+
     // Convert index in vector to a position (x, y, z) in a 3D cube
     fn idx_to_pos(&self, index: usize) -> IVec3 {
-        idx_to_pos(index as i32, self.bounds)
+        self.layout.position(index, self.bounds)
     }
 
     // Convert position (x, y, z) in a 3D cube to an index in a 2D vector
     fn pos_to_idx(&self, position: IVec3) -> usize {
-        pos_to_idx(position, self.bounds)
-    }
-
-    fn wrap(&self, pos: IVec3) -> IVec3 {
-        wrap(pos, self.bounds)
+        self.layout.index(position, self.bounds)
     }
 
     fn update_neighbours(&mut self, rule: &Rule, index: usize, inc: bool) {
         let pos = self.idx_to_pos(index);
-        for dir in rule.neighbourhood.get_neighbourhood_iter() {
-            let neighbour_position = self.wrap(pos + *dir);
+        for (dir, weight) in rule.kernel().get_weighted_iter() {
+            // Neighbours past a dead or alive face have no cell to update
+            let Neighbour::Cell(neighbour_position) = resolve(pos + dir, self.bounds, &self.boundaries) else {
+                continue;
+            };
             let index = self.pos_to_idx(neighbour_position);
             if inc {
-                self.cells[index].neighbours += 1;
+                self.cells[index].neighbours += weight;
             } else {
-                self.cells[index].neighbours -= 1;
+                self.cells[index].neighbours -= weight;
             }
+            self.mark(index);
         }
     }
 
     // Update the cells
+    // A cell with the same state and neighbours as last time changes the same way, so only cells which
+    // are decaying, or which had themselves or a neighbour change, are checked
     pub fn update(&mut self, rule: &Rule) {
         let mut spawns = vec![];
         let mut deaths = vec![];
+        let mut changes = vec![];
+        let bounds = self.bounds;
+        let generation = self.generation;
+        let boundaries = self.boundaries;
+        let layout = self.layout;
+
+        // Stochastic rules or regions can change any cell, and a new rule can change every cell
+        if rule.is_stochastic() || self.last_rule.as_ref() != Some(rule) {
+            self.evaluate_all = true;
+            self.last_rule = Some(rule.clone());
+        }
+        let evaluate_all = std::mem::take(&mut self.evaluate_all);
+        let active = std::mem::take(&mut self.active);
+        let count = match evaluate_all {
+            true => self.cells.len(),
+            false => active.len(),
+        };
+        self.evaluated = match evaluate_all {
+            true => cell_count(bounds),
+            false => active.len(),
+        };
+        for index in &active {
+            self.changed[*index] = false;
+        }
 
-        for (index, cell) in self.cells.iter_mut().enumerate() {
+        let indices = (0..count).map(|i| if evaluate_all { i } else { active[i] });
+        for index in indices {
+            let pos = layout.position(index, bounds);
+            // Padding is not part of the cube, so it never changes
+            if !pos.cmplt(bounds).all() {
+                continue;
+            }
+            let cell = &mut self.cells[index];
+            let state = cell.state;
+            // Neighbours are always counted with the main rule, but each region has its own transitions
+            let cell_rule = rule.rule_at(pos, bounds);
+            let neighbours = cell.neighbours + wall_neighbours(rule.kernel(), pos, bounds, &boundaries);
             if cell.is_dead() {
-                if rule.birth.is_valid(cell.neighbours) {
-                    cell.state = rule.states;
+                if cell_rule.should_birth(neighbours, pos, generation) {
+                    cell.state = cell_rule.states;
                     spawns.push(index);
                 }
             } else {
-                if cell.state < rule.states || !rule.survival.is_valid(cell.neighbours) {
-                    if cell.state == rule.states {
+                if cell.state < cell_rule.states || !cell_rule.should_survive(neighbours, pos, generation) {
+                    if cell_rule.stops_counting(cell.state) {
                         deaths.push(index);
                     }
                     cell.state -= 1;
                 }
             }
+            if cell.state != state {
+                changes.push(index);
+            }
         }
 
+        for index in changes {
+            self.mark(index);
+        }
         // Update neighbouring cells
         for index in spawns {
             self.update_neighbours(rule, index, true);
@@ -123,16 +214,69 @@ impl SingleThreaded {
         for index in deaths {
             self.update_neighbours(rule, index, false);
         }
+        self.generation += 1;
+    }
+
+    // Switch to a new rule, keeping the cells, and count their neighbours again
+    pub fn set_rule(&mut self, rule: &Rule) {
+        self.evaluate_all = true;
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            let states = rule.rule_at(self.layout.position(index, self.bounds), self.bounds).states;
+            cell.state = cell.state.min(states);
+            cell.neighbours = 0;
+        }
+        for index in 0..self.cells.len() {
+            let cell_rule = rule.rule_at(self.idx_to_pos(index), self.bounds);
+            if cell_rule.counts_as_neighbour(self.cells[index].state) {
+                self.update_neighbours(rule, index, true);
+            }
+        }
+    }
+
+    // Every cell which is not dead, at positions relative to the centre, without rendering the whole box
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        let centre = get_centre(self.bounds);
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.is_dead())
+            .map(|(index, cell)| LiveCell {
+                position: self.layout.position(index, self.bounds) - centre,
+                state: cell.state,
+                species: 0,
+            })
+            .collect()
     }
 
-    pub fn spawn_noise(&mut self, rule: &Rule) {
-        default_noise(get_centre(self.bounds), |pos| {
-            let index = self.pos_to_idx(self.wrap(pos));
+    // Replace every cell, at positions relative to the centre, and count their neighbours again
+    pub fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        let bounds = self.bounds;
+        self.set_bounds(IVec3::ZERO);
+        self.set_bounds(bounds);
+        for cell in cells {
+            let pos = cell.position + get_centre(bounds);
+            if in_bounds(pos, bounds) {
+                let index = self.pos_to_idx(pos);
+                self.cells[index].state = cell.state;
+            }
+        }
+        self.generation = generation;
+        self.set_rule(rule);
+    }
+
+    // Make dead cells alive, at positions relative to the centre
+    pub fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        for position in positions {
+            let Neighbour::Cell(pos) = resolve(*position + get_centre(self.bounds), self.bounds, &self.boundaries) else {
+                continue;
+            };
+            let index = self.pos_to_idx(pos);
             if self.cells[index].is_dead() {
-                self.cells[index].state = rule.states;
+                self.cells[index].state = rule.rule_at(pos, self.bounds).states;
+                self.mark(index);
                 self.update_neighbours(rule, index, true);
             }
-        });
+        }
     }
 }
 
@@ -147,19 +291,52 @@ impl crate::cells::Sim for SingleThreaded {
         }
     }
 
-    fn spawn_noise(&mut self, rule: &Rule) {
-        self.spawn_noise(rule);
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+        self.reset();
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
     }
 
     fn count(&self) -> usize {
         self.count_cells()
     }
 
-    fn get_bounds(&self) -> i32 {
+    fn cells_evaluated(&self) -> Option<usize> {
+        Some(self.evaluated)
+    }
+
+    fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    fn set_layout(&mut self, layout: Layout) -> Layout {
+        self.set_layout(layout)
+    }
+
+    fn get_bounds(&self) -> IVec3 {
         self.bounds
     }
 
-    fn set_bounds(&mut self, new_bounds: i32) -> i32 {
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
         self.set_bounds(new_bounds)
     }
 }
@@ -167,6 +344,10 @@ impl crate::cells::Sim for SingleThreaded {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::neighbours::Neighbourhood;
+    use crate::regions::{Region, Shape};
+    use crate::rule::Value;
+    use bevy::{math::ivec3, prelude::Color};
 
     #[test]
     fn test_is_dead() {
@@ -188,27 +369,31 @@ mod tests {
         let mut sim = SingleThreaded::new();
 
         // Check initial bounds
-        assert_eq!(sim.bounds, 0);
+        assert_eq!(sim.bounds, IVec3::ZERO);
 
         // Set bounds to 10
-        let bounds = sim.set_bounds(10);
-        assert_eq!(bounds, 10);
+        let bounds = sim.set_bounds(IVec3::splat(10));
+        assert_eq!(bounds, IVec3::splat(10));
 
         // Check that the vector has the correct length
         assert_eq!(sim.cells.len(), 1000);
 
         // Set bounds to 5
-        let bounds = sim.set_bounds(5);
-        assert_eq!(bounds, 5);
+        let bounds = sim.set_bounds(IVec3::splat(5));
+        assert_eq!(bounds, IVec3::splat(5));
 
         // Check that the vector has the correct length
         assert_eq!(sim.cells.len(), 125);
+
+        // Each axis can have its own length
+        sim.set_bounds(ivec3(20, 10, 2));
+        assert_eq!(sim.cells.len(), 400);
     }
 
     #[test]
     fn test_count_cells() {
         let mut sim = SingleThreaded::new();
-        sim.set_bounds(10);
+        sim.set_bounds(IVec3::splat(10));
 
         // Initially all cells should be dead
         assert_eq!(sim.count_cells(), 0);
@@ -222,6 +407,260 @@ mod tests {
         assert_eq!(sim.count_cells(), 3);
     }
 
+    #[test]
+    fn test_weighted_update_neighbours() {
+        let mut sim = SingleThreaded::new();
+        sim.set_bounds(IVec3::splat(5));
+
+        let rule = Rule {
+            birth: Value::new(&[]),
+            survival: Value::new(&[]),
+            neighbourhood: Neighbourhood::weighted([(ivec3(0, 1, 0), 2), (ivec3(1, 0, 0), 1)]).unwrap(),
+            states: 2,
+            ..Default::default()
+        };
+
+        let centre = sim.pos_to_idx(ivec3(2, 2, 2));
+        sim.update_neighbours(&rule, centre, true);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(2, 3, 2))].neighbours, 2);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(3, 2, 2))].neighbours, 1);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(1, 2, 2))].neighbours, 0);
+
+        sim.update_neighbours(&rule, centre, false);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(2, 3, 2))].neighbours, 0);
+    }
+
+    #[test]
+    fn test_decaying_neighbours() {
+        for decaying_neighbours in [false, true] {
+            let mut sim = SingleThreaded::new();
+            sim.set_bounds(IVec3::splat(5));
+
+            let rule = Rule {
+                neighbourhood: Neighbourhood::VonNeumann,
+                states: 3,
+                decaying_neighbours,
+                ..Default::default()
+            };
+
+            let centre = sim.pos_to_idx(ivec3(2, 2, 2));
+            let neighbour = sim.pos_to_idx(ivec3(2, 3, 2));
+            sim.cells[centre].state = rule.states;
+            sim.update_neighbours(&rule, centre, true);
+
+            // The cell starts decaying, as it has no neighbours to survive with
+            sim.update(&rule);
+            assert_eq!(sim.cells[centre].state, 2);
+            assert_eq!(sim.cells[neighbour].neighbours, decaying_neighbours as u16);
+
+            sim.update(&rule);
+            assert_eq!(sim.cells[centre].state, 1);
+            assert_eq!(sim.cells[neighbour].neighbours, decaying_neighbours as u16);
+
+            // Once dead, the cell never counts
+            sim.update(&rule);
+            assert_eq!(sim.cells[centre].state, 0);
+            assert_eq!(sim.cells[neighbour].neighbours, 0);
+        }
+    }
+
+    #[test]
+    fn test_set_rule() {
+        let mut sim = SingleThreaded::new();
+        sim.set_bounds(IVec3::splat(5));
+
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            states: 5,
+            ..Default::default()
+        };
+        let centre = sim.pos_to_idx(ivec3(2, 2, 2));
+        sim.cells[centre].state = rule.states;
+        sim.update_neighbours(&rule, centre, true);
+
+        // Fewer states keeps the cell alive, and a bigger neighbourhood is counted
+        let rule = Rule {
+            neighbourhood: Neighbourhood::Moore,
+            states: 2,
+            ..Default::default()
+        };
+        sim.set_rule(&rule);
+        assert_eq!(sim.cells[centre].state, 2);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(3, 3, 3))].neighbours, 1);
+        assert_eq!(sim.cells[centre].neighbours, 0);
+    }
+
+    #[test]
+    fn test_active_cells() {
+        let mut sim = SingleThreaded::new();
+        sim.set_bounds(IVec3::splat(20));
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            states: 2,
+            ..Default::default()
+        };
+
+        // Every cell is checked the first time
+        let cell = sim.pos_to_idx(ivec3(10, 10, 10));
+        sim.cells[cell].state = rule.states;
+        sim.update_neighbours(&rule, cell, true);
+        sim.update(&rule);
+        assert_eq!(sim.evaluated, 8000);
+        assert_eq!(sim.cells[cell].state, 1);
+
+        // Then the decaying cell, and its neighbours as it stopped counting
+        sim.update(&rule);
+        assert_eq!(sim.evaluated, 7);
+        assert_eq!(sim.cells[cell].state, 0);
+
+        // The cell which died, then nothing
+        sim.update(&rule);
+        assert_eq!(sim.evaluated, 1);
+        sim.update(&rule);
+        assert_eq!(sim.evaluated, 0);
+
+        // A new rule checks every cell again
+        sim.update(&Rule::default());
+        assert_eq!(sim.evaluated, 8000);
+    }
+
+    #[test]
+    fn test_layouts_match() {
+        use crate::boundary::Boundary::*;
+        use crate::rule::Stochastic;
+        use crate::utilities::idx_to_pos;
+
+        let bounds = ivec3(13, 9, 10);
+        let rules: [Rule; 2] = ["4/4/5/M".parse().unwrap(), "0-6/1,3/2/N".parse().unwrap()];
+        for rule in rules {
+            let mut linear = SingleThreaded::new();
+            let mut tiled = SingleThreaded::new();
+            tiled.set_layout(Layout::Tiled);
+            for sim in [&mut linear, &mut tiled] {
+                sim.set_bounds(bounds);
+                crate::cells::Sim::set_boundaries(sim, [Periodic, Reflective, Dead]);
+            }
+            // Padding rounds each side up to a whole tile
+            assert_eq!(tiled.cells.len(), 16 * 16 * 16);
+
+            // Seeded random births give both the same starting cells
+            let noise = Rule {
+                stochastic: Some(Stochastic {
+                    spontaneous_birth: 0.3,
+                    ..Default::default()
+                }),
+                ..rule.clone()
+            };
+            for sim in [&mut linear, &mut tiled] {
+                sim.update(&noise);
+            }
+            assert!(linear.count_cells() > 0);
+
+            for _ in 0..10 {
+                linear.update(&rule);
+                tiled.update(&rule);
+                assert_eq!(linear.count_cells(), tiled.count_cells());
+                assert_eq!(linear.evaluated, tiled.evaluated);
+                for index in 0..cell_count(bounds) {
+                    let pos = idx_to_pos(index as i32, bounds);
+                    let (expected, found) = (linear.cells[index], tiled.cells[tiled.pos_to_idx(pos)]);
+                    assert_eq!((expected.state, expected.neighbours), (found.state, found.neighbours));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        use crate::rule::Stochastic;
+
+        let mut sim = SingleThreaded::new();
+        sim.set_bounds(IVec3::splat(6));
+
+        // Only cells near the centre can be born
+        let region = Region {
+            shape: Shape::Sphere {
+                centre: ivec3(0, 0, 0),
+                radius: 1.0,
+            },
+            rule: Rule {
+                birth: Value::new(&[1]),
+                neighbourhood: Neighbourhood::VonNeumann,
+                ..Default::default()
+            },
+            colour: Color::ORANGE,
+        };
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            regions: vec![region],
+            ..Default::default()
+        };
+
+        let cell = sim.pos_to_idx(ivec3(3, 3, 2));
+        sim.cells[cell].state = rule.states;
+        sim.update_neighbours(&rule, cell, true);
+        sim.update(&rule);
+
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(3, 3, 3))].state, 2);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(3, 3, 1))].state, 0);
+
+        // A stochastic region can change quiet cells, even when the rule around it is not stochastic
+        let region = Region {
+            shape: Shape::Sphere {
+                centre: ivec3(0, 0, 0),
+                radius: 6.0,
+            },
+            rule: Rule {
+                stochastic: Some(Stochastic {
+                    spontaneous_birth: 0.002,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            colour: Color::ORANGE,
+        };
+        let rule = Rule {
+            regions: vec![region],
+            ..Default::default()
+        };
+        let mut active = SingleThreaded::new();
+        let mut every = SingleThreaded::new();
+        for sim in [&mut active, &mut every] {
+            sim.set_bounds(IVec3::splat(16));
+        }
+        for _ in 0..10 {
+            active.update(&rule);
+            every.evaluate_all = true;
+            every.update(&rule);
+            assert_eq!(active.count_cells(), every.count_cells());
+        }
+        assert!(active.count_cells() > 1);
+    }
+
+    #[test]
+    fn test_boundaries() {
+        use crate::boundary::Boundary::*;
+
+        let mut sim = SingleThreaded::new();
+        sim.set_bounds(IVec3::splat(5));
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            ..Default::default()
+        };
+        let corner = sim.pos_to_idx(ivec3(0, 0, 0));
+
+        // Periodic faces update the cell on the opposite side
+        sim.update_neighbours(&rule, corner, true);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(4, 0, 0))].neighbours, 1);
+
+        crate::cells::Sim::set_boundaries(&mut sim, [Dead, Reflective, Alive]);
+        sim.update_neighbours(&rule, corner, true);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(4, 0, 0))].neighbours, 0);
+        // The cell is next to its own image on a reflective face
+        assert_eq!(sim.cells[corner].neighbours, 1);
+        assert_eq!(sim.cells[sim.pos_to_idx(ivec3(0, 0, 4))].neighbours, 0);
+    }
+
     // #[test]
     // fn test_update_neighbours() {
     //
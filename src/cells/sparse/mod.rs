@@ -0,0 +1,2 @@
+mod sparse;
+pub use sparse::*;
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+
+use bevy::{math::IVec3, tasks::TaskPool};
+
+use crate::{
+    boundary::Boundaries,
+    cells::LiveCell,
+    render::CellRenderer,
+    rule::Rule,
+    utilities::{idx_to_pos, pos_to_idx},
+};
+
+// Chunks are 16 cells along each side
+const CHUNK_BITS: i32 = 4;
+const CHUNK_SIZE: IVec3 = IVec3::splat(1 << CHUNK_BITS);
+
+#[derive(Clone, Copy)]
+struct SimpleCell {
+    state: u8,
+    neighbours: u16,
+}
+
+impl SimpleCell {
+    fn new() -> SimpleCell {
+        SimpleCell {
+            state: 0,
+            neighbours: 0,
+        }
+    }
+
+    // Return true if the cell has value 0
+    pub fn is_dead(&self) -> bool {
+        self.state == 0
+    }
+}
+
+// A cube of cells, which only exists while it has live cells or neighbours of them
+struct Chunk {
+    cells: Vec<SimpleCell>,
+    // Number of cells which are not dead
+    live: usize,
+}
+
+impl Chunk {
+    fn new() -> Chunk {
+        Chunk {
+            cells: vec![SimpleCell::new(); (1 << (3 * CHUNK_BITS)) as usize],
+            live: 0,
+        }
+    }
+
+    // A chunk with no live cells and no neighbours can never change, so it can be removed
+    fn is_empty(&self) -> bool {
+        self.live == 0 && self.cells.iter().all(|cell| cell.neighbours == 0)
+    }
+}
+
+// Chunk which holds a position, and the index of the position in it
+fn split(position: IVec3) -> (IVec3, usize) {
+    let chunk = position >> CHUNK_BITS;
+    (chunk, pos_to_idx(position - (chunk << CHUNK_BITS), CHUNK_SIZE))
+}
+
+// Cells with no box around them, so patterns can grow and move forever
+// Positions are relative to the centre of the space, where noise is spawned
+pub struct Sparse {
+    chunks: HashMap<IVec3, Chunk>,
+    // Corners of the box holding every live cell
+    min: IVec3,
+    max: IVec3,
+    // Size chosen in the settings, which is kept for the other sims
+    size: IVec3,
+    // Number of updates since the cells were cleared, for stochastic rules
+    generation: u64,
+}
+
+impl Sparse {
+    pub fn new() -> Self {
+        Sparse {
+            chunks: HashMap::new(),
+            min: IVec3::ZERO,
+            max: IVec3::NEG_ONE,
+            size: IVec3::ZERO,
+            generation: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.min = IVec3::ZERO;
+        self.max = IVec3::NEG_ONE;
+        self.generation = 0;
+    }
+
+    // Count the number of live cells
+    pub fn count_cells(&self) -> usize {
+        self.chunks.values().map(|chunk| chunk.live).sum()
+    }
+
+    // Size of the box holding every live cell
+    pub fn occupied(&self) -> IVec3 {
+        self.max - self.min + IVec3::ONE
+    }
+
+    // Make a cell alive, if it is dead
+    pub(crate) fn spawn_cell(&mut self, rule: &Rule, position: IVec3) {
+        let (chunk_position, index) = split(position);
+        let chunk = self.chunks.entry(chunk_position).or_insert_with(Chunk::new);
+        if chunk.cells[index].is_dead() {
+            // There is no box, so regions are found from the position alone
+            chunk.cells[index].state = rule.rule_at(position, IVec3::ZERO).states;
+            chunk.live += 1;
+            self.update_neighbours(rule, position, true);
+        }
+    }
+
+    fn update_neighbours(&mut self, rule: &Rule, position: IVec3, inc: bool) {
+        for (dir, weight) in rule.kernel().get_weighted_iter() {
+            let (chunk, index) = split(position + dir);
+            // Neighbours which are counted always have a chunk, which is kept until they are uncounted
+            let cell = &mut self.chunks.entry(chunk).or_insert_with(Chunk::new).cells[index];
+            if inc {
+                cell.neighbours += weight;
+            } else {
+                cell.neighbours -= weight;
+            }
+        }
+    }
+
+    // Find the box holding every live cell, after they have changed
+    fn update_occupied(&mut self) {
+        let mut min = IVec3::splat(i32::MAX);
+        let mut max = IVec3::splat(i32::MIN);
+        for (chunk_position, chunk) in self.chunks.iter().filter(|(_, chunk)| chunk.live > 0) {
+            for (index, cell) in chunk.cells.iter().enumerate() {
+                if !cell.is_dead() {
+                    let pos = (*chunk_position << CHUNK_BITS) + idx_to_pos(index as i32, CHUNK_SIZE);
+                    min = min.min(pos);
+                    max = max.max(pos);
+                }
+            }
+        }
+        if min.cmpgt(max).any() {
+            min = IVec3::ZERO;
+            max = IVec3::NEG_ONE;
+        }
+        self.min = min;
+        self.max = max;
+    }
+
+    // Update the cells
+    pub fn update(&mut self, rule: &Rule) {
+        let mut spawns = vec![];
+        let mut deaths = vec![];
+        let generation = self.generation;
+
+        for (chunk_position, chunk) in self.chunks.iter_mut() {
+            let origin = *chunk_position << CHUNK_BITS;
+            for (index, cell) in chunk.cells.iter_mut().enumerate() {
+                // The space is endless, so cells are never born without neighbours
+                if cell.is_dead() && cell.neighbours == 0 {
+                    continue;
+                }
+                let pos = origin + idx_to_pos(index as i32, CHUNK_SIZE);
+                let cell_rule = rule.rule_at(pos, IVec3::ZERO);
+                if cell.is_dead() {
+                    if cell_rule.should_birth(cell.neighbours, pos, generation) {
+                        cell.state = cell_rule.states;
+                        chunk.live += 1;
+                        spawns.push(pos);
+                    }
+                } else if cell.state < cell_rule.states || !cell_rule.should_survive(cell.neighbours, pos, generation) {
+                    if cell_rule.stops_counting(cell.state) {
+                        deaths.push(pos);
+                    }
+                    cell.state -= 1;
+                    if cell.is_dead() {
+                        chunk.live -= 1;
+                    }
+                }
+            }
+        }
+
+        // Update neighbouring cells
+        for pos in spawns {
+            self.update_neighbours(rule, pos, true);
+        }
+        // Update dead cells
+        for pos in deaths {
+            self.update_neighbours(rule, pos, false);
+        }
+        self.chunks.retain(|_, chunk| !chunk.is_empty());
+        self.update_occupied();
+        self.generation += 1;
+    }
+
+    // Switch to a new rule, keeping the cells, and count their neighbours again
+    pub fn set_rule(&mut self, rule: &Rule) {
+        let mut counted = vec![];
+        for (chunk_position, chunk) in self.chunks.iter_mut() {
+            let origin = *chunk_position << CHUNK_BITS;
+            for (index, cell) in chunk.cells.iter_mut().enumerate() {
+                cell.neighbours = 0;
+                if cell.is_dead() {
+                    continue;
+                }
+                let pos = origin + idx_to_pos(index as i32, CHUNK_SIZE);
+                let cell_rule = rule.rule_at(pos, IVec3::ZERO);
+                cell.state = cell.state.min(cell_rule.states);
+                if cell_rule.counts_as_neighbour(cell.state) {
+                    counted.push(pos);
+                }
+            }
+        }
+        for pos in counted {
+            self.update_neighbours(rule, pos, true);
+        }
+        self.chunks.retain(|_, chunk| !chunk.is_empty());
+    }
+
+    // Every live cell, relative to the centre
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        let mut cells = vec![];
+        for (chunk_position, chunk) in self.chunks.iter().filter(|(_, chunk)| chunk.live > 0) {
+            for (index, cell) in chunk.cells.iter().enumerate().filter(|(_, cell)| !cell.is_dead()) {
+                cells.push(LiveCell {
+                    position: (*chunk_position << CHUNK_BITS) + idx_to_pos(index as i32, CHUNK_SIZE),
+                    state: cell.state,
+                    species: 0,
+                });
+            }
+        }
+        cells
+    }
+
+    // Replace every cell, at positions relative to the centre, and count their neighbours again
+    pub fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.clear();
+        for cell in cells {
+            let (chunk_position, index) = split(cell.position);
+            let chunk = self.chunks.entry(chunk_position).or_insert_with(Chunk::new);
+            if chunk.cells[index].is_dead() {
+                chunk.live += 1;
+            }
+            chunk.cells[index].state = cell.state;
+        }
+        self.generation = generation;
+        self.set_rule(rule);
+        self.update_occupied();
+    }
+
+    pub fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        for position in positions {
+            self.spawn_cell(rule, *position);
+        }
+        self.update_occupied();
+    }
+}
+
+impl crate::cells::Sim for Sparse {
+    fn update(&mut self, rule: &Rule, _task_pool: &TaskPool) {
+        self.update(rule);
+    }
+
+    // Only the box holding live cells is rendered, which must be the size of the renderer
+    fn render(&self, renderer: &mut CellRenderer) {
+        renderer.clear();
+        for (chunk_position, chunk) in self.chunks.iter().filter(|(_, chunk)| chunk.live > 0) {
+            for (index, cell) in chunk.cells.iter().enumerate() {
+                if !cell.is_dead() {
+                    let pos = (*chunk_position << CHUNK_BITS) + idx_to_pos(index as i32, CHUNK_SIZE);
+                    renderer.set(renderer.index(pos - self.min), cell.state, cell.neighbours);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+
+    fn spawn(&mut self, rule: &Rule, positions: &[IVec3]) {
+        self.spawn(rule, positions);
+    }
+
+    fn live_cells(&self) -> Vec<LiveCell> {
+        self.live_cells()
+    }
+
+    fn set_live_cells(&mut self, rule: &Rule, cells: &[LiveCell], generation: u64) {
+        self.set_live_cells(rule, cells, generation);
+    }
+
+    fn set_rule(&mut self, rule: &Rule) {
+        self.set_rule(rule);
+    }
+
+    // There are no faces, so only the cells are cleared
+    fn set_boundaries(&mut self, _boundaries: Boundaries) {
+        self.clear();
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn count(&self) -> usize {
+        self.count_cells()
+    }
+
+    fn get_bounds(&self) -> IVec3 {
+        self.occupied()
+    }
+
+    // The cells have no box, but changing the size still clears them like the other sims
+    fn set_bounds(&mut self, new_bounds: IVec3) -> IVec3 {
+        if new_bounds != self.size {
+            self.size = new_bounds;
+            self.clear();
+        }
+        self.size
+    }
+
+    fn offset(&self) -> IVec3 {
+        self.min
+    }
+
+    fn has_faces(&self) -> bool {
+        false
+    }
+
+    // Cells with no neighbours are never checked, so they can not be born
+    fn supports(&self, rule: &Rule) -> bool {
+        !rule.is_birth(0) && rule.stochastic.as_ref().is_none_or(|stochastic| stochastic.spontaneous_birth == 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::Sim;
+    use crate::neighbours::Neighbourhood;
+    use crate::rule::Value;
+    use bevy::math::ivec3;
+
+    impl Sparse {
+        fn get(&self, position: IVec3) -> Option<&SimpleCell> {
+            let (chunk, index) = split(position);
+            self.chunks.get(&chunk).map(|chunk| &chunk.cells[index])
+        }
+    }
+
+    // A 2D glider, flat in the z axis
+    fn glider(sim: &mut Sparse, rule: &Rule) {
+        for (x, y) in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            sim.spawn_cell(rule, ivec3(x, y, 0));
+        }
+        sim.update_occupied();
+    }
+
+    fn life() -> Rule {
+        Rule {
+            survival: Value::new(&[2, 3]),
+            birth: Value::new(&[3]),
+            states: 1,
+            neighbourhood: Neighbourhood::weighted(
+                (-1..=1)
+                    .flat_map(|x| (-1..=1).map(move |y| ivec3(x, y, 0)))
+                    .filter(|offset| *offset != IVec3::ZERO)
+                    .map(|offset| (offset, 1)),
+            )
+            .unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(ivec3(0, 0, 0)), (ivec3(0, 0, 0), 0));
+        assert_eq!(split(ivec3(17, 1, 0)), (ivec3(1, 0, 0), 17));
+        // Negative positions belong to the chunk before zero
+        assert_eq!(split(ivec3(-1, 0, 0)), (ivec3(-1, 0, 0), 15));
+    }
+
+    #[test]
+    fn test_glider_moves_forever() {
+        let rule = life();
+        let mut sim = Sparse::new();
+        glider(&mut sim, &rule);
+        assert_eq!(sim.count_cells(), 5);
+        assert_eq!(sim.occupied(), ivec3(3, 3, 1));
+
+        // Every 4 generations the glider moves one cell diagonally
+        for _ in 0..400 {
+            sim.update(&rule);
+        }
+        assert_eq!(sim.count_cells(), 5);
+        assert_eq!(sim.min, ivec3(100, 100, 0));
+        assert_eq!(sim.occupied(), ivec3(3, 3, 1));
+        // Only the chunks around the glider are kept
+        assert!(sim.chunks.len() <= 8);
+        assert!(sim.get(ivec3(1, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn test_render() {
+        let rule = life();
+        let mut sim = Sparse::new();
+        glider(&mut sim, &rule);
+        for _ in 0..40 {
+            sim.update(&rule);
+        }
+
+        let mut renderer = CellRenderer::new();
+        renderer.set_bounds(sim.get_bounds());
+        sim.render(&mut renderer);
+        assert_eq!(sim.offset(), ivec3(10, 10, 0));
+        assert_eq!(renderer.cell_count(), 9);
+        assert_eq!(renderer.values.iter().filter(|value| **value != 0).count(), 5);
+        assert_eq!(renderer.values[pos_to_idx(ivec3(1, 0, 0), renderer.bounds)], 1);
+    }
+
+    #[test]
+    fn test_set_rule() {
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            states: 5,
+            ..Default::default()
+        };
+        let mut sim = Sparse::new();
+        sim.spawn_cell(&rule, IVec3::ZERO);
+
+        // Fewer states keeps the cell alive, and a bigger neighbourhood is counted
+        let rule = Rule {
+            neighbourhood: Neighbourhood::Moore,
+            states: 2,
+            ..Default::default()
+        };
+        sim.set_rule(&rule);
+        assert_eq!(sim.get(IVec3::ZERO).unwrap().state, 2);
+        assert_eq!(sim.get(ivec3(-1, -1, -1)).unwrap().neighbours, 1);
+
+        sim.reset();
+        assert_eq!(sim.count(), 0);
+        assert_eq!(sim.get_bounds(), IVec3::ZERO);
+    }
+}
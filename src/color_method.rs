@@ -23,7 +23,8 @@ impl ColourMethod {
         c2: Color,
         current_state: u8,
         total_states: u8,
-        neighbours: u8,
+        neighbours: u16,
+        max_neighbours: usize,
         distance_to_centre: f32,
         index: usize,
         total_cells: usize,
@@ -37,7 +38,7 @@ impl ColourMethod {
             }
             DistToCenter => state_colour(c1, c2, distance_to_centre),
             Neighbour => {
-                let gradient = neighbours as f32 / 26f32;
+                let gradient = neighbours as f32 / max_neighbours as f32;
                 state_colour(c1, c2, gradient)
             }
             Index => {
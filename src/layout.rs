@@ -0,0 +1,112 @@
+use bevy::math::{ivec3, IVec3};
+
+use crate::utilities::{cell_count, idx_to_pos, pos_to_idx};
+
+// Cells in each tile of the tiled layout are 2^TILE_BITS along each side
+const TILE_BITS: i32 = 3;
+const TILE_CELLS: usize = 1 << (3 * TILE_BITS);
+
+// How the cells of the cube are ordered in memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    // x, then y, then z, so cells next to each other in z are a whole layer apart
+    Linear,
+    // Tiles of 8x8x8 cells, ordered in the same way as `Linear`, with the cells in each tile in
+    // Morton (Z-order), so most neighbours are close in memory
+    // Sides which are not a multiple of 8 are padded with cells outside the cube
+    Tiled,
+}
+
+impl Layout {
+    // Number of cells to store, including padding
+    pub fn len(&self, bounds: IVec3) -> usize {
+        match self {
+            Layout::Linear => cell_count(bounds),
+            Layout::Tiled => cell_count(tiles(bounds)) * TILE_CELLS,
+        }
+    }
+
+    pub fn index(&self, position: IVec3, bounds: IVec3) -> usize {
+        match self {
+            Layout::Linear => pos_to_idx(position, bounds),
+            Layout::Tiled => {
+                let tile = pos_to_idx(position >> TILE_BITS, tiles(bounds));
+                let cell = position & ((1 << TILE_BITS) - 1);
+                tile * TILE_CELLS + (spread(cell.x) | spread(cell.y) << 1 | spread(cell.z) << 2)
+            }
+        }
+    }
+
+    pub fn position(&self, index: usize, bounds: IVec3) -> IVec3 {
+        match self {
+            Layout::Linear => idx_to_pos(index as i32, bounds),
+            Layout::Tiled => {
+                let tile = idx_to_pos((index / TILE_CELLS) as i32, tiles(bounds));
+                let cell = index % TILE_CELLS;
+                (tile << TILE_BITS) + ivec3(compact(cell), compact(cell >> 1), compact(cell >> 2))
+            }
+        }
+    }
+
+    // Number of cells in the smallest run of indices which holds whole layers along z,
+    // and the number of layers it holds
+    pub fn layers(&self, bounds: IVec3) -> (usize, i32) {
+        match self {
+            Layout::Linear => ((bounds.x * bounds.y) as usize, 1),
+            Layout::Tiled => {
+                let tiles = tiles(bounds);
+                ((tiles.x * tiles.y) as usize * TILE_CELLS, 1 << TILE_BITS)
+            }
+        }
+    }
+}
+
+// Number of tiles along each axis
+fn tiles(bounds: IVec3) -> IVec3 {
+    (bounds + (1 << TILE_BITS) - 1) >> TILE_BITS
+}
+
+// Move each of the 3 bits of a value to every third bit
+fn spread(value: i32) -> usize {
+    let value = value as usize;
+    (value & 1) | (value & 2) << 2 | (value & 4) << 4
+}
+
+// Read every third bit of a value back into a number
+fn compact(value: usize) -> i32 {
+    ((value & 1) | (value >> 2 & 2) | (value >> 4 & 4)) as i32
+}
+
+#[cfg(test)]
+mod layout {
+    use super::*;
+
+    #[test]
+    fn test_tiled() {
+        let bounds = ivec3(10, 8, 3);
+        let tiled = Layout::Tiled;
+        // Two tiles along x, padded to 16
+        assert_eq!(tiled.len(bounds), 2 * 512);
+
+        assert_eq!(tiled.index(ivec3(1, 0, 0), bounds), 1);
+        assert_eq!(tiled.index(ivec3(0, 1, 0), bounds), 2);
+        assert_eq!(tiled.index(ivec3(0, 0, 1), bounds), 4);
+        assert_eq!(tiled.index(ivec3(1, 1, 1), bounds), 7);
+        assert_eq!(tiled.index(ivec3(8, 0, 0), bounds), 512);
+
+        // Every cell has its own index, which gives the position back
+        let mut seen = vec![false; tiled.len(bounds)];
+        for index in 0..cell_count(bounds) {
+            let position = idx_to_pos(index as i32, bounds);
+            let index = tiled.index(position, bounds);
+            assert!(!seen[index]);
+            seen[index] = true;
+            assert_eq!(tiled.position(index, bounds), position);
+        }
+        assert_eq!(seen.iter().filter(|seen| **seen).count(), 240);
+        // Padding is outside the cube
+        assert_eq!(tiled.position(tiled.index(ivec3(12, 0, 0), bounds), bounds), ivec3(12, 0, 0));
+        assert_eq!(tiled.layers(bounds), (1024, 8));
+        assert_eq!(Layout::Linear.layers(bounds), (80, 1));
+    }
+}
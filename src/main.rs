@@ -7,11 +7,15 @@ use bevy_flycam::prelude::*;
 use render::*;
 use setup::*;
 
+mod boundary;
 mod cells;
 mod color_method;
+mod layout;
 mod neighbours;
+mod regions;
 mod render;
 mod rule;
+mod schedule;
 mod setup;
 mod utilities;
 
@@ -24,7 +28,15 @@ fn main() {
     task_pool_settings.compute.percent = 1.0f32;
     task_pool_settings.io.percent = 1.0f32;
 
-    // todo! add pause functionality
+    // Check that every sim agrees instead of opening the window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--differential") {
+        if let Err(error) = cells::differential::run(&args) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     App::new()
         // Add default plugins
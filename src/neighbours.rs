@@ -1,22 +1,392 @@
-use bevy::math::IVec3;
+use bevy::math::{ivec3, IVec3};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Largest radius for the extended Moore and Von Neumann neighbourhoods
+pub const MAX_RADIUS: u8 = 3;
+// Largest number of neighbours any neighbourhood may have, i.e. Moore with the largest radius
+pub const MAX_NEIGHBOURS: usize = (2 * MAX_RADIUS as usize + 1).pow(3) - 1;
+// Largest distance along any axis for a custom offset
+pub const MAX_OFFSET: i32 = 8;
 
 // Adapted from TanTanDev
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Neighbourhood {
     VonNeumann,
     Moore,
-    // todo! Add custom neighbourhoods, and a tool to make them
+    // Von Neumann and Moore with a radius from 2 to `MAX_RADIUS`
+    ExtendedVonNeumann(u8),
+    ExtendedMoore(u8),
+    // Any set of offsets, see `Neighbourhood::custom`
+    Custom(Vec<IVec3>),
+    // Offsets which each add their own weight to the neighbour count, see `Neighbourhood::weighted`
+    Weighted { offsets: Vec<IVec3>, weights: Vec<u16> },
 }
 
 impl Neighbourhood {
-    pub fn get_neighbourhood_iter(&self) -> &'static [IVec3] {
+    pub fn get_neighbourhood_iter(&self) -> &[IVec3] {
         match self {
             Neighbourhood::VonNeumann => &VON_NEUMANN[..],
             Neighbourhood::Moore => &MOORE[..],
+            Neighbourhood::ExtendedVonNeumann(radius) => extended_offsets(*radius, true),
+            Neighbourhood::ExtendedMoore(radius) => extended_offsets(*radius, false),
+            Neighbourhood::Custom(offsets) => &offsets[..],
+            Neighbourhood::Weighted { offsets, .. } => &offsets[..],
+        }
+    }
+
+    // Iterate through each offset with the amount it adds to the neighbour count
+    pub fn get_weighted_iter(&self) -> impl Iterator<Item = (IVec3, u16)> + '_ {
+        let weights = match self {
+            Neighbourhood::Weighted { weights, .. } => Some(weights),
+            _ => None,
+        };
+        self.get_neighbourhood_iter()
+            .iter()
+            .enumerate()
+            .map(move |(index, offset)| (*offset, weights.map_or(1, |weights| weights[index])))
+    }
+
+    // Moore neighbourhood with the given radius, clamped between 1 and `MAX_RADIUS`
+    pub fn moore(radius: u8) -> Self {
+        match radius.clamp(1, MAX_RADIUS) {
+            1 => Neighbourhood::Moore,
+            radius => Neighbourhood::ExtendedMoore(radius),
+        }
+    }
+
+    // Von Neumann neighbourhood with the given radius, clamped between 1 and `MAX_RADIUS`
+    pub fn von_neumann(radius: u8) -> Self {
+        match radius.clamp(1, MAX_RADIUS) {
+            1 => Neighbourhood::VonNeumann,
+            radius => Neighbourhood::ExtendedVonNeumann(radius),
+        }
+    }
+
+    // The radius of a Moore or Von Neumann neighbourhood, custom neighbourhoods have none
+    pub fn radius(&self) -> Option<u8> {
+        match self {
+            Neighbourhood::VonNeumann | Neighbourhood::Moore => Some(1),
+            Neighbourhood::ExtendedVonNeumann(radius) | Neighbourhood::ExtendedMoore(radius) => {
+                Some(*radius)
+            }
+            Neighbourhood::Custom(_) | Neighbourhood::Weighted { .. } => None,
+        }
+    }
+
+    // The largest number of neighbours a cell can have, i.e. the sum of the weights
+    pub fn max_neighbours(&self) -> usize {
+        match self {
+            Neighbourhood::Weighted { weights, .. } => {
+                weights.iter().map(|weight| *weight as usize).sum()
+            }
+            _ => self.get_neighbourhood_iter().len(),
+        }
+    }
+
+    // Index n is true if some combination of live neighbours adds up to n
+    pub fn reachable_values(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.max_neighbours() + 1];
+        reachable[0] = true;
+        for (_, weight) in self.get_weighted_iter() {
+            // Go downwards so each neighbour is only added once
+            for total in (weight as usize..reachable.len()).rev() {
+                if reachable[total - weight as usize] {
+                    reachable[total] = true;
+                }
+            }
+        }
+        reachable
+    }
+
+    // Create a custom neighbourhood, ignoring duplicates and the cell itself
+    pub fn custom<I: IntoIterator<Item = IVec3>>(offsets: I) -> Result<Self, NeighbourhoodError> {
+        let mut result: Vec<IVec3> = vec![];
+        for offset in offsets {
+            if offset.abs().max_element() > MAX_OFFSET {
+                return Err(NeighbourhoodError::OffsetTooLarge(offset));
+            }
+            if offset != IVec3::ZERO && !result.contains(&offset) {
+                result.push(offset);
+            }
+        }
+
+        if result.is_empty() {
+            return Err(NeighbourhoodError::Empty);
         }
+        if result.len() > MAX_NEIGHBOURS {
+            return Err(NeighbourhoodError::TooMany(result.len()));
+        }
+        Ok(Neighbourhood::Custom(result))
+    }
+
+    // Create a weighted neighbourhood from (offset, weight) pairs
+    // Offsets given more than once add their weights together, and zero weights are ignored
+    pub fn weighted<I: IntoIterator<Item = (IVec3, u16)>>(
+        kernel: I,
+    ) -> Result<Self, NeighbourhoodError> {
+        let mut offsets: Vec<IVec3> = vec![];
+        let mut weights: Vec<u16> = vec![];
+        let mut total: usize = 0;
+        for (offset, weight) in kernel {
+            if offset.abs().max_element() > MAX_OFFSET {
+                return Err(NeighbourhoodError::OffsetTooLarge(offset));
+            }
+            if offset == IVec3::ZERO || weight == 0 {
+                continue;
+            }
+            total += weight as usize;
+            if total > MAX_NEIGHBOURS {
+                return Err(NeighbourhoodError::TooHeavy(total));
+            }
+            match offsets.iter().position(|o| *o == offset) {
+                Some(index) => weights[index] += weight,
+                None => {
+                    offsets.push(offset);
+                    weights.push(weight);
+                }
+            }
+        }
+
+        if offsets.is_empty() {
+            return Err(NeighbourhoodError::Empty);
+        }
+        Ok(Neighbourhood::Weighted { offsets, weights })
+    }
+
+    // Weight the Moore neighbours by whether they share a face, an edge or a corner with the cell
+    pub fn weighted_by_class(face: u16, edge: u16, corner: u16) -> Result<Self, NeighbourhoodError> {
+        Neighbourhood::weighted(MOORE.iter().map(|offset| {
+            let weight = match offset.abs().to_array().iter().sum::<i32>() {
+                1 => face,
+                2 => edge,
+                _ => corner,
+            };
+            (*offset, weight)
+        }))
+    }
+
+    // Read a custom neighbourhood from a list of offsets, e.g. "1,0,0; 0,1,0"
+    // Offsets are separated by ';' or new lines, and their values by ',' or spaces
+    // An offset may be given a weight, e.g. "0,1,0:2", which makes the neighbourhood weighted
+    // Anything after a '#' on a line is ignored
+    pub fn from_offsets(text: &str) -> Result<Self, NeighbourhoodError> {
+        let (kernel, weighted) = parse_kernel(text)?;
+        match weighted {
+            true => Neighbourhood::weighted(kernel),
+            false => Neighbourhood::custom(kernel.into_iter().map(|(offset, _)| offset)),
+        }
+    }
+
+    // Load a custom neighbourhood from a file, with one offset per line
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, NeighbourhoodError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| NeighbourhoodError::Io(error.to_string()))?;
+        Neighbourhood::from_offsets(&text)
     }
 }
 
+// Read a list of offsets with optional weights, also returning whether any weights were given
+fn parse_kernel(text: &str) -> Result<(Vec<(IVec3, u16)>, bool), NeighbourhoodError> {
+    let mut kernel = vec![];
+    let mut weighted = false;
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for item in line.split(';') {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+            let (position, weight) = match item.split_once(':') {
+                Some((position, weight)) => {
+                    weighted = true;
+                    let weight = weight
+                        .trim()
+                        .parse::<u16>()
+                        .map_err(|_| NeighbourhoodError::InvalidOffset(item.to_string()))?;
+                    (position, weight)
+                }
+                None => (item, 1),
+            };
+            let values: Vec<&str> = position
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .collect();
+            let values: Vec<i32> = values
+                .iter()
+                .map(|value| value.parse::<i32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| NeighbourhoodError::InvalidOffset(item.to_string()))?;
+            if values.len() != 3 {
+                return Err(NeighbourhoodError::InvalidOffset(item.to_string()));
+            }
+            kernel.push((ivec3(values[0], values[1], values[2]), weight));
+        }
+    }
+    Ok((kernel, weighted))
+}
+
+// Moore is written as M, Von Neumann as N, followed by the radius if it is above one, e.g. M2
+// Custom neighbourhoods are written as their offsets, e.g. C(1,0,0;-1,0,0)
+// Weighted neighbourhoods also give the weight of each offset, e.g. W(1,0,0:2;-1,0,0:1)
+impl fmt::Display for Neighbourhood {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Neighbourhood::VonNeumann => write!(f, "N"),
+            Neighbourhood::Moore => write!(f, "M"),
+            Neighbourhood::ExtendedVonNeumann(radius) => write!(f, "N{}", radius),
+            Neighbourhood::ExtendedMoore(radius) => write!(f, "M{}", radius),
+            Neighbourhood::Custom(offsets) => {
+                let offsets: Vec<String> = offsets
+                    .iter()
+                    .map(|offset| format!("{},{},{}", offset.x, offset.y, offset.z))
+                    .collect();
+                write!(f, "C({})", offsets.join(";"))
+            }
+            Neighbourhood::Weighted { offsets, weights } => {
+                let offsets: Vec<String> = offsets
+                    .iter()
+                    .zip(weights)
+                    .map(|(offset, weight)| {
+                        format!("{},{},{}:{}", offset.x, offset.y, offset.z, weight)
+                    })
+                    .collect();
+                write!(f, "W({})", offsets.join(";"))
+            }
+        }
+    }
+}
+
+impl FromStr for Neighbourhood {
+    type Err = NeighbourhoodError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        match text {
+            "M" | "m" => Ok(Neighbourhood::Moore),
+            "N" | "n" => Ok(Neighbourhood::VonNeumann),
+            _ if text.starts_with(['M', 'm', 'N', 'n']) => {
+                let radius = text[1..]
+                    .parse::<u8>()
+                    .map_err(|_| NeighbourhoodError::Unknown(text.to_string()))?;
+                if radius == 0 || radius > MAX_RADIUS {
+                    return Err(NeighbourhoodError::InvalidRadius(radius));
+                }
+                match text.starts_with(['M', 'm']) {
+                    true => Ok(Neighbourhood::moore(radius)),
+                    false => Ok(Neighbourhood::von_neumann(radius)),
+                }
+            }
+            _ => {
+                let offsets = text
+                    .strip_prefix(['C', 'c', 'W', 'w'])
+                    .and_then(|rest| rest.trim().strip_prefix('('))
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .ok_or_else(|| NeighbourhoodError::Unknown(text.to_string()))?;
+                let (kernel, _) = parse_kernel(offsets)?;
+                match text.starts_with(['C', 'c']) {
+                    true => Neighbourhood::custom(kernel.into_iter().map(|(offset, _)| offset)),
+                    false => Neighbourhood::weighted(kernel),
+                }
+            }
+        }
+    }
+}
+
+// Errors which can occur when creating a custom neighbourhood
+#[derive(Clone, PartialEq, Debug)]
+pub enum NeighbourhoodError {
+    // The neighbourhood code was not recognised
+    Unknown(String),
+    // An offset was not three whole numbers
+    InvalidOffset(String),
+    // An offset was further than `MAX_OFFSET` away along an axis
+    OffsetTooLarge(IVec3),
+    // There were no offsets other than the cell itself
+    Empty,
+    // There were more than `MAX_NEIGHBOURS` offsets
+    TooMany(usize),
+    // The weights added up to more than `MAX_NEIGHBOURS`
+    TooHeavy(usize),
+    // The radius was not between 1 and `MAX_RADIUS`
+    InvalidRadius(u8),
+    // The file could not be read
+    Io(String),
+}
+
+impl fmt::Display for NeighbourhoodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NeighbourhoodError::Unknown(text) => write!(
+                f,
+                "'{}' is not a known neighbourhood, use M (Moore), N (Von Neumann), C(x,y,z;...) or W(x,y,z:w;...)",
+                text
+            ),
+            NeighbourhoodError::InvalidOffset(text) => {
+                write!(f, "'{}' is not a valid offset, expected x,y,z", text)
+            }
+            NeighbourhoodError::OffsetTooLarge(offset) => write!(
+                f,
+                "offset {} is too far away, offsets must be within {} of the cell",
+                offset, MAX_OFFSET
+            ),
+            NeighbourhoodError::Empty => write!(f, "a neighbourhood needs at least one offset"),
+            NeighbourhoodError::TooMany(count) => write!(
+                f,
+                "{} offsets given, a neighbourhood can have at most {}",
+                count, MAX_NEIGHBOURS
+            ),
+            NeighbourhoodError::TooHeavy(total) => write!(
+                f,
+                "the weights add up to {}, they can add up to at most {}",
+                total, MAX_NEIGHBOURS
+            ),
+            NeighbourhoodError::InvalidRadius(radius) => write!(
+                f,
+                "{} is not a valid radius, it must be between 1 and {}",
+                radius, MAX_RADIUS
+            ),
+            NeighbourhoodError::Io(error) => write!(f, "could not read file: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for NeighbourhoodError {}
+
+// Offsets for the extended neighbourhoods, generated the first time each radius is used
+static EXTENDED_VON_NEUMANN: OnceLock<Vec<Vec<IVec3>>> = OnceLock::new();
+static EXTENDED_MOORE: OnceLock<Vec<Vec<IVec3>>> = OnceLock::new();
+
+fn extended_offsets(radius: u8, von_neumann: bool) -> &'static [IVec3] {
+    let table = match von_neumann {
+        true => &EXTENDED_VON_NEUMANN,
+        false => &EXTENDED_MOORE,
+    };
+    let offsets = table.get_or_init(|| {
+        (0..=MAX_RADIUS as i32)
+            .map(|radius| {
+                let mut offsets = vec![];
+                for z in -radius..=radius {
+                    for y in -radius..=radius {
+                        for x in -radius..=radius {
+                            let offset = ivec3(x, y, z);
+                            // Von Neumann only includes cells within `radius` steps along the axes
+                            let in_range = !von_neumann || x.abs() + y.abs() + z.abs() <= radius;
+                            if offset != IVec3::ZERO && in_range {
+                                offsets.push(offset);
+                            }
+                        }
+                    }
+                }
+                offsets
+            })
+            .collect()
+    });
+    &offsets[radius.min(MAX_RADIUS) as usize][..]
+}
+
 // Check VN neighbourhood (Cells touching a face of target cell)
 pub static VON_NEUMANN: [IVec3; 6] = [
     IVec3::from_array([1, 0, 0]),
@@ -106,4 +476,144 @@ mod neighbours {
         assert!(moore_neigh.contains(&IVec3::from_array([0, 1, 1])));
         assert!(moore_neigh.contains(&IVec3::from_array([1, 1, 1])));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_custom_neighbourhood() {
+        let offsets = [ivec3(1, 0, 0), ivec3(0, 0, 0), ivec3(1, 0, 0), ivec3(0, 2, 0)];
+        let custom = Neighbourhood::custom(offsets).unwrap();
+
+        // Duplicates and the cell itself are removed
+        assert_eq!(custom.get_neighbourhood_iter(), &[ivec3(1, 0, 0), ivec3(0, 2, 0)]);
+        assert_eq!(custom.max_neighbours(), 2);
+
+        assert_eq!(Neighbourhood::custom([]), Err(NeighbourhoodError::Empty));
+        assert_eq!(
+            Neighbourhood::custom([ivec3(9, 0, 0)]),
+            Err(NeighbourhoodError::OffsetTooLarge(ivec3(9, 0, 0)))
+        );
+        let too_many = (-8..=8).flat_map(|x| (-8..=8).flat_map(move |y| (1..=2).map(move |z| ivec3(x, y, z))));
+        assert_eq!(Neighbourhood::custom(too_many), Err(NeighbourhoodError::TooMany(578)));
+    }
+
+    #[test]
+    fn test_neighbourhood_from_offsets() {
+        let custom = Neighbourhood::from_offsets("1,0,0; 0 1 0\n# comment\n0, 0, -1 # up\n").unwrap();
+        assert_eq!(
+            custom.get_neighbourhood_iter(),
+            &[ivec3(1, 0, 0), ivec3(0, 1, 0), ivec3(0, 0, -1)]
+        );
+
+        assert_eq!(
+            Neighbourhood::from_offsets("1,0"),
+            Err(NeighbourhoodError::InvalidOffset("1,0".into()))
+        );
+        assert_eq!(
+            Neighbourhood::from_offsets("1,a,0"),
+            Err(NeighbourhoodError::InvalidOffset("1,a,0".into()))
+        );
+    }
+
+    #[test]
+    fn test_neighbourhood_notation() {
+        assert_eq!("M".parse::<Neighbourhood>(), Ok(Neighbourhood::Moore));
+        assert_eq!("N".parse::<Neighbourhood>(), Ok(Neighbourhood::VonNeumann));
+        assert_eq!("M1".parse::<Neighbourhood>(), Ok(Neighbourhood::Moore));
+        assert_eq!("M2".parse::<Neighbourhood>(), Ok(Neighbourhood::ExtendedMoore(2)));
+        assert_eq!("N3".parse::<Neighbourhood>(), Ok(Neighbourhood::ExtendedVonNeumann(3)));
+        assert_eq!(Neighbourhood::ExtendedMoore(2).to_string(), "M2");
+        assert_eq!(
+            "M4".parse::<Neighbourhood>(),
+            Err(NeighbourhoodError::InvalidRadius(4))
+        );
+        assert_eq!(
+            "X".parse::<Neighbourhood>(),
+            Err(NeighbourhoodError::Unknown("X".into()))
+        );
+        assert_eq!(
+            "Mx".parse::<Neighbourhood>(),
+            Err(NeighbourhoodError::Unknown("Mx".into()))
+        );
+
+        let custom = Neighbourhood::custom([ivec3(1, 0, 0), ivec3(-1, 0, 0)]).unwrap();
+        assert_eq!(custom.to_string(), "C(1,0,0;-1,0,0)");
+        assert_eq!(custom.to_string().parse::<Neighbourhood>(), Ok(custom));
+    }
+
+    #[test]
+    fn test_extended_neighbourhoods() {
+        assert_eq!(Neighbourhood::moore(1), Neighbourhood::Moore);
+        assert_eq!(Neighbourhood::von_neumann(1), Neighbourhood::VonNeumann);
+        assert_eq!(Neighbourhood::moore(9), Neighbourhood::ExtendedMoore(MAX_RADIUS));
+
+        assert_eq!(Neighbourhood::ExtendedMoore(2).max_neighbours(), 124);
+        assert_eq!(Neighbourhood::ExtendedMoore(3).max_neighbours(), MAX_NEIGHBOURS);
+        assert_eq!(Neighbourhood::ExtendedVonNeumann(2).max_neighbours(), 24);
+        assert_eq!(Neighbourhood::ExtendedVonNeumann(3).max_neighbours(), 62);
+
+        let moore = Neighbourhood::ExtendedMoore(2);
+        assert!(moore.get_neighbourhood_iter().contains(&ivec3(-2, 2, -2)));
+        assert!(!moore.get_neighbourhood_iter().contains(&IVec3::ZERO));
+
+        let von_neumann = Neighbourhood::ExtendedVonNeumann(2);
+        assert!(von_neumann.get_neighbourhood_iter().contains(&ivec3(1, 0, -1)));
+        assert!(!von_neumann.get_neighbourhood_iter().contains(&ivec3(1, 1, -1)));
+
+        assert_eq!(moore.radius(), Some(2));
+        assert_eq!(Neighbourhood::custom([ivec3(1, 0, 0)]).unwrap().radius(), None);
+    }
+
+    #[test]
+    fn test_weighted_neighbourhood() {
+        let weighted =
+            Neighbourhood::weighted([(ivec3(0, 1, 0), 2), (ivec3(0, -1, 0), 1), (ivec3(0, 1, 0), 1), (ivec3(1, 0, 0), 0)])
+                .unwrap();
+
+        // Duplicates are added together, and zero weights removed
+        assert_eq!(
+            weighted.get_weighted_iter().collect::<Vec<_>>(),
+            vec![(ivec3(0, 1, 0), 3), (ivec3(0, -1, 0), 1)]
+        );
+        assert_eq!(weighted.max_neighbours(), 4);
+        assert_eq!(weighted.reachable_values(), vec![true, true, false, true, true]);
+
+        assert_eq!(
+            Neighbourhood::weighted([(ivec3(1, 0, 0), MAX_NEIGHBOURS as u16 + 1)]),
+            Err(NeighbourhoodError::TooHeavy(MAX_NEIGHBOURS + 1))
+        );
+        assert_eq!(Neighbourhood::weighted([(ivec3(1, 0, 0), 0)]), Err(NeighbourhoodError::Empty));
+
+        // Unweighted neighbourhoods count each neighbour once
+        assert!(Neighbourhood::Moore.get_weighted_iter().all(|(_, weight)| weight == 1));
+        assert!(Neighbourhood::Moore.reachable_values().iter().all(|reachable| *reachable));
+    }
+
+    #[test]
+    fn test_weighted_by_class() {
+        let weighted = Neighbourhood::weighted_by_class(3, 2, 1).unwrap();
+        assert_eq!(weighted.max_neighbours(), 6 * 3 + 12 * 2 + 8);
+
+        let weights: Vec<(IVec3, u16)> = weighted.get_weighted_iter().collect();
+        assert!(weights.contains(&(ivec3(0, 0, 1), 3)));
+        assert!(weights.contains(&(ivec3(0, 1, 1), 2)));
+        assert!(weights.contains(&(ivec3(1, 1, 1), 1)));
+
+        // Classes with no weight are left out
+        let faces = Neighbourhood::weighted_by_class(1, 0, 0).unwrap();
+        assert_eq!(faces.get_neighbourhood_iter().len(), 6);
+    }
+
+    #[test]
+    fn test_weighted_notation() {
+        let weighted = Neighbourhood::from_offsets("0,1,0:2\n0,-1,0").unwrap();
+        assert_eq!(weighted.to_string(), "W(0,1,0:2;0,-1,0:1)");
+        assert_eq!(weighted.to_string().parse::<Neighbourhood>(), Ok(weighted));
+
+        // Without any weights the neighbourhood is custom
+        assert!(matches!(Neighbourhood::from_offsets("0,1,0"), Ok(Neighbourhood::Custom(_))));
+        assert!(matches!("W(0,1,0)".parse::<Neighbourhood>(), Ok(Neighbourhood::Weighted { .. })));
+        assert_eq!(
+            Neighbourhood::from_offsets("0,1,0:x"),
+            Err(NeighbourhoodError::InvalidOffset("0,1,0:x".into()))
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy::{
+    math::{ivec3, IVec3},
+    prelude::Color,
+};
+
+use crate::rule::Rule;
+
+// Colours given to new regions, so they can be told apart in the preview
+pub const REGION_COLOURS: [Color; 6] = [
+    Color::ORANGE,
+    Color::CYAN,
+    Color::PURPLE,
+    Color::YELLOW,
+    Color::LIME_GREEN,
+    Color::PINK,
+];
+
+// The cells a region covers, with positions relative to the centre of the cube
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    // Cells between two corners, including the corners
+    Box { min: IVec3, max: IVec3 },
+    // Cells within a distance of a point
+    Sphere { centre: IVec3, radius: f32 },
+    // Cells on the side of a plane which the normal points towards
+    HalfSpace { normal: IVec3, offset: i32 },
+    // Cells given an index in a volume, which is placed in the centre of the cube
+    Indexed { volume: Arc<IndexVolume>, index: u8 },
+}
+
+impl Shape {
+    pub fn contains(&self, position: IVec3) -> bool {
+        match self {
+            Shape::Box { min, max } => position.cmpge(*min).all() && position.cmple(*max).all(),
+            Shape::Sphere { centre, radius } => {
+                (position - *centre).as_vec3().length_squared() <= radius * radius
+            }
+            Shape::HalfSpace { normal, offset } => position.dot(*normal) >= *offset,
+            Shape::Indexed { volume, index } => volume.get(position) == Some(*index),
+        }
+    }
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Shape::Sphere {
+            centre: ivec3(0, 0, 0),
+            radius: 10.0,
+        }
+    }
+}
+
+// A part of the cube which follows its own rule
+// Regions count neighbours with the main rule's neighbourhood, so only the rest of their rule is used
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub shape: Shape,
+    pub rule: Rule,
+    // Colour of the region when previewing
+    pub colour: Color,
+}
+
+// A grid of region indices, where 0 is not part of any region
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexVolume {
+    pub size: IVec3,
+    pub indices: Vec<u8>,
+}
+
+impl IndexVolume {
+    // Get the index at a position relative to the centre of the volume
+    pub fn get(&self, position: IVec3) -> Option<u8> {
+        let position = position + self.size / 2;
+        if position.cmplt(IVec3::ZERO).any() || position.cmpge(self.size).any() {
+            return None;
+        }
+        let index = position.x + position.y * self.size.x + position.z * self.size.x * self.size.y;
+        self.indices.get(index as usize).copied()
+    }
+
+    // Read a volume from text, with its size then one index per cell, x first, e.g.
+    // 2 2 2
+    // 0 1 0 1
+    // 2 2 0 0
+    pub fn parse(text: &str) -> Result<Self, RegionError> {
+        let mut numbers = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+
+        let mut size = [0; 3];
+        for axis in size.iter_mut() {
+            let number = numbers.next().ok_or(RegionError::InvalidSize)?;
+            *axis = match number.parse::<i32>() {
+                Ok(length) if length > 0 => length,
+                _ => return Err(RegionError::InvalidSize),
+            };
+        }
+        let size = IVec3::from_array(size);
+
+        let indices = numbers
+            .map(|number| {
+                number
+                    .parse::<u8>()
+                    .map_err(|_| RegionError::InvalidIndex(number.to_string()))
+            })
+            .collect::<Result<Vec<u8>, RegionError>>()?;
+        let expected = (size.x * size.y * size.z) as usize;
+        if indices.len() != expected {
+            return Err(RegionError::WrongCount {
+                expected,
+                found: indices.len(),
+            });
+        }
+        Ok(IndexVolume { size, indices })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RegionError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|error| RegionError::Io(error.to_string()))?;
+        IndexVolume::parse(&text)
+    }
+
+    // One region for each index used in the volume, all starting with the same rule
+    pub fn into_regions(self, rule: &Rule) -> Vec<Region> {
+        let mut used = [false; 256];
+        for index in &self.indices {
+            used[*index as usize] = true;
+        }
+        let volume = Arc::new(self);
+        (1..=u8::MAX)
+            .filter(|index| used[*index as usize])
+            .enumerate()
+            .map(|(i, index)| Region {
+                shape: Shape::Indexed {
+                    volume: volume.clone(),
+                    index,
+                },
+                rule: rule.clone(),
+                colour: REGION_COLOURS[i % REGION_COLOURS.len()],
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegionError {
+    // The volume did not start with three lengths above 0
+    InvalidSize,
+    // An index was not a whole number from 0 to 255
+    InvalidIndex(String),
+    // The number of indices did not match the size
+    WrongCount { expected: usize, found: usize },
+    // The file could not be read
+    Io(String),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegionError::InvalidSize => {
+                write!(f, "a volume must start with its size, as three whole numbers above 0")
+            }
+            RegionError::InvalidIndex(text) => {
+                write!(f, "'{}' is not a valid index, expected a number from 0 to 255", text)
+            }
+            RegionError::WrongCount { expected, found } => write!(
+                f,
+                "expected {} indices for the size given, found {}",
+                expected, found
+            ),
+            RegionError::Io(error) => write!(f, "could not read file: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
+
+#[cfg(test)]
+mod regions {
+    use super::*;
+
+    #[test]
+    fn test_shape_contains() {
+        let cube = Shape::Box {
+            min: ivec3(-1, -1, -1),
+            max: ivec3(1, 2, 1),
+        };
+        assert!(cube.contains(ivec3(1, 2, -1)));
+        assert!(!cube.contains(ivec3(1, 3, 0)));
+
+        let sphere = Shape::Sphere {
+            centre: ivec3(2, 0, 0),
+            radius: 2.0,
+        };
+        assert!(sphere.contains(ivec3(0, 0, 0)));
+        assert!(!sphere.contains(ivec3(0, 1, 0)));
+
+        let half = Shape::HalfSpace {
+            normal: ivec3(0, 1, 0),
+            offset: 3,
+        };
+        assert!(half.contains(ivec3(-10, 3, 5)));
+        assert!(!half.contains(ivec3(0, 2, 0)));
+    }
+
+    #[test]
+    fn test_index_volume() {
+        let volume = IndexVolume::parse("# size\n2 2 1\n0 1\n2 1\n").unwrap();
+        assert_eq!(volume.size, ivec3(2, 2, 1));
+        // The volume is centred on the cube
+        assert_eq!(volume.get(ivec3(-1, -1, 0)), Some(0));
+        assert_eq!(volume.get(ivec3(0, -1, 0)), Some(1));
+        assert_eq!(volume.get(ivec3(-1, 0, 0)), Some(2));
+        assert_eq!(volume.get(ivec3(1, 0, 0)), None);
+
+        let regions = volume.into_regions(&Rule::default());
+        assert_eq!(regions.len(), 2);
+        assert!(regions[1].shape.contains(ivec3(-1, 0, 0)));
+
+        assert_eq!(IndexVolume::parse("2 0 1"), Err(RegionError::InvalidSize));
+        assert_eq!(
+            IndexVolume::parse("1 1 1 300"),
+            Err(RegionError::InvalidIndex("300".into()))
+        );
+        assert_eq!(
+            IndexVolume::parse("1 1 2 1"),
+            Err(RegionError::WrongCount {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+}
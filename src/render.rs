@@ -21,6 +21,8 @@ use bevy::{
 };
 use bytemuck::{Pod, Zeroable};
 
+use crate::layout::Layout;
+
 #[derive(Component, Deref)]
 pub struct InstanceMaterialData(pub Vec<InstanceData>);
 
@@ -215,35 +217,71 @@ impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
 }
 
 pub struct CellRenderer {
-    pub bounds: i32,
+    pub bounds: IVec3,
+    // Order of the cells, which is the same as the sim rendering them
+    pub layout: Layout,
     pub values: Vec<u8>,
-    pub neighbors: Vec<u8>,
+    pub neighbors: Vec<u16>,
+    pub species: Vec<u8>,
 }
 
 impl CellRenderer {
     pub fn new() -> CellRenderer {
         CellRenderer {
-            bounds: 0,
+            bounds: IVec3::ZERO,
+            layout: Layout::Linear,
             values: vec![],
             neighbors: vec![],
+            species: vec![],
         }
     }
 
+    // Number of cells, including any padding from the layout
     pub fn cell_count(&self) -> usize {
-        (self.bounds.pow(3)) as usize
+        self.layout.len(self.bounds)
     }
 
-    pub fn set_bounds(&mut self, new_bounds: i32) {
+    pub fn set_bounds(&mut self, new_bounds: IVec3) {
         if new_bounds != self.bounds {
-            let new_count = new_bounds.pow(3);
-            self.values.resize(new_count as usize, 0);
-            self.neighbors.resize(new_count as usize, 0);
+            let new_count = self.layout.len(new_bounds);
+            self.values.resize(new_count, 0);
+            self.neighbors.resize(new_count, 0);
+            self.species.resize(new_count, 0);
             self.bounds = new_bounds;
         }
     }
 
-    pub fn set(&mut self, index: usize, value: u8, neighbors: u8) {
+    pub fn set_layout(&mut self, layout: Layout) {
+        if layout != self.layout {
+            let bounds = self.bounds;
+            self.set_bounds(IVec3::ZERO);
+            self.layout = layout;
+            self.set_bounds(bounds);
+        }
+    }
+
+    pub fn index(&self, position: IVec3) -> usize {
+        self.layout.index(position, self.bounds)
+    }
+
+    pub fn position(&self, index: usize) -> IVec3 {
+        self.layout.position(index, self.bounds)
+    }
+
+    // Make every cell dead, for sims which only set their live cells
+    pub fn clear(&mut self) {
+        self.values.fill(0);
+        self.neighbors.fill(0);
+        self.species.fill(0);
+    }
+
+    pub fn set(&mut self, index: usize, value: u8, neighbors: u16) {
+        self.set_species(index, value, neighbors, 0);
+    }
+
+    pub fn set_species(&mut self, index: usize, value: u8, neighbors: u16, species: u8) {
         self.values[index] = value;
         self.neighbors[index] = neighbors;
+        self.species[index] = species;
     }
 }
@@ -1,13 +1,20 @@
-use crate::neighbours::Neighbourhood;
+use crate::neighbours::{Neighbourhood, NeighbourhoodError, MAX_NEIGHBOURS, MOORE};
+use crate::regions::Region;
+use crate::utilities::{cell_random, get_centre};
+use bevy::math::IVec3;
+use std::fmt;
 use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
 // Adapted from TanTanDev
+// Index n is true if n neighbours is a valid value, up to the largest neighbourhood
 #[derive(Clone, Copy, PartialEq, Debug)]
-pub struct Value([bool; 27]);
+pub struct Value([bool; MAX_NEIGHBOURS + 1]);
 
 impl Value {
-    pub fn new(indices: &[u8]) -> Self {
-        let mut result = Value([false; 27]);
+    pub fn new(indices: &[u16]) -> Self {
+        let mut result = Value([false; MAX_NEIGHBOURS + 1]);
         for index in indices {
             result.0[*index as usize] = true;
         }
@@ -15,8 +22,8 @@ impl Value {
     }
 
     // Generate a new value from a range
-    pub fn from_range(indices: RangeInclusive<u8>) -> Self {
-        let mut result = Value([false; 27]);
+    pub fn from_range(indices: RangeInclusive<u16>) -> Self {
+        let mut result = Value([false; MAX_NEIGHBOURS + 1]);
         for index in indices {
             result.0[index as usize] = true;
         }
@@ -24,7 +31,7 @@ impl Value {
     }
 
     // Check if a value is valid, i.e. is true
-    pub fn is_valid(&self, value: u8) -> bool {
+    pub fn is_valid(&self, value: u16) -> bool {
         if (value as usize) < self.0.len() {
             *self.0.get(value as usize).unwrap()
         } else {
@@ -44,12 +51,648 @@ impl Value {
     }
 }
 
+// Write the enabled values as a comma list, collapsing consecutive runs into ranges, e.g. 5-7,12-13,15
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        let mut index = 0;
+        while index < self.0.len() {
+            if !self.0[index] {
+                index += 1;
+                continue;
+            }
+            // Find the end of this run of enabled values
+            let start = index;
+            while index + 1 < self.0.len() && self.0[index + 1] {
+                index += 1;
+            }
+            if !first {
+                write!(f, ",")?;
+            }
+            if start == index {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, index)?;
+            }
+            first = false;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Value {
+    // Parse a comma list of values and ranges, e.g. "5-7,12-13,15"
+    // Any value above `max` is rejected
+    pub fn parse(text: &str, max: u16) -> Result<Self, RuleParseError> {
+        let mut result = Value([false; MAX_NEIGHBOURS + 1]);
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(result);
+        }
+
+        for item in text.split(',') {
+            let item = item.trim();
+            let (start, end) = match item.split_once('-') {
+                Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+                None => {
+                    let value = parse_number(item)?;
+                    (value, value)
+                }
+            };
+            if start > end {
+                return Err(RuleParseError::InvalidRange(item.to_string()));
+            }
+            if end > max as u32 {
+                return Err(RuleParseError::ValueOutOfRange { value: end, max });
+            }
+            for index in start..=end {
+                result.0[index as usize] = true;
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, RuleParseError> {
+    text.trim()
+        .parse::<u32>()
+        .map_err(|_| RuleParseError::InvalidNumber(text.trim().to_string()))
+}
+
+// Number of Moore neighbours which share a face, an edge or only a corner with the cell
+pub const FACES: u16 = 6;
+pub const EDGES: u16 = 12;
+pub const CORNERS: u16 = 8;
+
+// Semi-totalistic rules count each class of neighbour separately, by weighting them so the
+// count of each class can be read back from the total: faces + 7 * edges + 91 * corners
+const EDGE_WEIGHT: u16 = FACES + 1;
+const CORNER_WEIGHT: u16 = EDGE_WEIGHT * (EDGES + 1);
+const CLASS_VALUES: usize = (CORNER_WEIGHT * (CORNERS + 1)) as usize;
+
+static CLASS_KERNEL: OnceLock<Neighbourhood> = OnceLock::new();
+
+// Index n is true if the (faces, edges, corners) count encoded as n is a valid value
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClassValue([bool; CLASS_VALUES]);
+
+impl ClassValue {
+    // Every (faces, edges, corners) count which adds up to a valid value of a totalistic rule
+    pub fn from_value(value: Value) -> Self {
+        let mut result = ClassValue([false; CLASS_VALUES]);
+        for (index, valid) in result.0.iter_mut().enumerate() {
+            let (faces, edges, corners) = class_counts(index as u16);
+            *valid = value.is_valid(faces + edges + corners);
+        }
+        result
+    }
+
+    // Check if an encoded neighbour count is valid
+    pub fn is_valid(&self, value: u16) -> bool {
+        self.0.get(value as usize).copied().unwrap_or(false)
+    }
+
+    // Change the state of a (faces, edges, corners) count
+    pub fn change_value(mut self, faces: u16, edges: u16, corners: u16) -> Self {
+        let index = class_index(faces, edges, corners);
+        self.0[index] = !self.0[index];
+        self
+    }
+
+    // Get a specified (faces, edges, corners) count
+    pub fn get_value(self, faces: u16, edges: u16, corners: u16) -> bool {
+        self.0[class_index(faces, edges, corners)]
+    }
+
+    // Parse a comma list of faces:edges:corners counts, e.g. "1:0-2:*,2:2:0"
+    // Each count may be a number, a range, or '*' for any number
+    pub fn parse(text: &str) -> Result<Self, RuleParseError> {
+        let mut result = ClassValue([false; CLASS_VALUES]);
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(result);
+        }
+
+        for item in text.split(',') {
+            let item = item.trim();
+            let counts: Vec<&str> = item.split(':').collect();
+            if counts.len() != 3 {
+                return Err(RuleParseError::InvalidClassValue(item.to_string()));
+            }
+            let faces = parse_class_range(counts[0], FACES)?;
+            let edges = parse_class_range(counts[1], EDGES)?;
+            let corners = parse_class_range(counts[2], CORNERS)?;
+            for f in faces.clone() {
+                for e in edges.clone() {
+                    for c in corners.clone() {
+                        result.0[class_index(f, e, c)] = true;
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+// Write each faces:edges:corners count, collapsing consecutive corner counts into ranges
+impl fmt::Display for ClassValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = vec![];
+        for faces in 0..=FACES {
+            for edges in 0..=EDGES {
+                let mut corners = 0;
+                while corners <= CORNERS {
+                    if !self.get_value(faces, edges, corners) {
+                        corners += 1;
+                        continue;
+                    }
+                    let start = corners;
+                    while corners < CORNERS && self.get_value(faces, edges, corners + 1) {
+                        corners += 1;
+                    }
+                    match start == corners {
+                        true => items.push(format!("{}:{}:{}", faces, edges, start)),
+                        false => items.push(format!("{}:{}:{}-{}", faces, edges, start, corners)),
+                    }
+                    corners += 1;
+                }
+            }
+        }
+        write!(f, "{}", items.join(","))
+    }
+}
+
+fn class_index(faces: u16, edges: u16, corners: u16) -> usize {
+    (faces + edges * EDGE_WEIGHT + corners * CORNER_WEIGHT) as usize
+}
+
+// Read the (faces, edges, corners) counts back from an encoded neighbour count
+fn class_counts(value: u16) -> (u16, u16, u16) {
+    (
+        value % EDGE_WEIGHT,
+        (value / EDGE_WEIGHT) % (EDGES + 1),
+        value / CORNER_WEIGHT,
+    )
+}
+
+fn parse_class_range(text: &str, max: u16) -> Result<RangeInclusive<u16>, RuleParseError> {
+    let text = text.trim();
+    if text == "*" {
+        return Ok(0..=max);
+    }
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => {
+            let value = parse_number(text)?;
+            (value, value)
+        }
+    };
+    if start > end {
+        return Err(RuleParseError::InvalidRange(text.to_string()));
+    }
+    if end > max as u32 {
+        return Err(RuleParseError::ValueOutOfRange { value: end, max });
+    }
+    Ok(start as u16..=end as u16)
+}
+
+// Birth and survival conditions which count face, edge and corner neighbours separately
 #[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClassRule {
+    pub birth: ClassValue,
+    pub survival: ClassValue,
+}
+
+// Chances for the transitions of a rule to happen, rather than always happening
+// Each cell draws its own random numbers from the seed, its position and the generation,
+// so a run can be repeated from the same seed
+#[derive(Clone, PartialEq, Debug)]
+pub struct Stochastic {
+    // Chance of a valid birth happening, for each number of live neighbours
+    pub birth: Vec<f32>,
+    // Chance of a cell with a valid number of live neighbours surviving
+    pub survival: Vec<f32>,
+    // Chance of any dead cell being born, whatever its neighbours
+    pub spontaneous_birth: f32,
+    // Chance of any live cell starting to decay, whatever its neighbours
+    pub spontaneous_death: f32,
+    pub seed: u64,
+}
+
+impl Default for Stochastic {
+    fn default() -> Self {
+        Stochastic {
+            birth: vec![1.0; MAX_NEIGHBOURS + 1],
+            survival: vec![1.0; MAX_NEIGHBOURS + 1],
+            spontaneous_birth: 0.0,
+            spontaneous_death: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Stochastic {
+    // Chance for a number of live neighbours, missing values always happen
+    fn chance(chances: &[f32], neighbours: u16) -> f32 {
+        chances.get(neighbours as usize).copied().unwrap_or(1.0)
+    }
+
+    // Read the notation written by `Display`, without the P
+    fn parse(text: &str) -> Result<Self, RuleParseError> {
+        let error = || RuleParseError::InvalidStochastic(text.to_string());
+        let fields: Vec<&str> = text
+            .strip_prefix('(')
+            .and_then(|text| text.strip_suffix(')'))
+            .ok_or_else(error)?
+            .split(';')
+            .map(|field| field.trim())
+            .collect();
+        if fields.len() != 5 {
+            return Err(error());
+        }
+        let chance = |text: &str| match text.parse::<f32>() {
+            Ok(chance) if (0.0..=1.0).contains(&chance) => Ok(chance),
+            _ => Err(error()),
+        };
+        // Counts which are not listed always happen
+        let chances = |text: &str| {
+            let mut chances = vec![1.0; MAX_NEIGHBOURS + 1];
+            for pair in text.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (count, value) = pair.split_once(':').ok_or_else(error)?;
+                match count.trim().parse::<usize>() {
+                    Ok(count) if count <= MAX_NEIGHBOURS => chances[count] = chance(value.trim())?,
+                    _ => return Err(error()),
+                }
+            }
+            Ok(chances)
+        };
+        Ok(Stochastic {
+            seed: fields[0].parse().map_err(|_| error())?,
+            spontaneous_birth: chance(fields[1])?,
+            spontaneous_death: chance(fields[2])?,
+            birth: chances(fields[3])?,
+            survival: chances(fields[4])?,
+        })
+    }
+}
+
+// Written as P(seed;spontaneous birth;spontaneous death;birth chances;survival chances),
+// where the chances are count:chance pairs for the counts which do not always happen, e.g. P(7;0.002;0;4:0.5;)
+impl fmt::Display for Stochastic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let chances = |chances: &[f32]| {
+            let pairs: Vec<String> = chances
+                .iter()
+                .enumerate()
+                .filter(|(_, chance)| **chance != 1.0)
+                .map(|(count, chance)| format!("{}:{}", count, chance))
+                .collect();
+            pairs.join(",")
+        };
+        write!(
+            f,
+            "P({};{};{};{};{})",
+            self.seed,
+            self.spontaneous_birth,
+            self.spontaneous_death,
+            chances(&self.birth),
+            chances(&self.survival)
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct Rule {
     pub birth: Value,
     pub survival: Value,
     pub neighbourhood: Neighbourhood,
     pub states: u8,
+    // Semi-totalistic conditions, used instead of birth, survival and neighbourhood when set
+    pub classes: Option<ClassRule>,
+    // Whether decaying cells still count as neighbours until they reach state 0
+    // If not, cells stop counting as soon as they start to decay, as in "Generations" rules
+    pub decaying_neighbours: bool,
+    // Chances for births and survivals, which are certain when not set
+    pub stochastic: Option<Stochastic>,
+    // Parts of the cube which follow a different rule, later regions cover earlier ones
+    // These are not part of the rule notation
+    pub regions: Vec<Region>,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Rule {
+            birth: Value::new(&[]),
+            survival: Value::new(&[]),
+            neighbourhood: Neighbourhood::Moore,
+            states: 2,
+            classes: None,
+            decaying_neighbours: false,
+            stochastic: None,
+            regions: vec![],
+        }
+    }
+}
+
+impl Rule {
+    // The neighbourhood used to count neighbours
+    // Semi-totalistic rules use a Moore neighbourhood weighted so each class can be told apart
+    pub fn kernel(&self) -> &Neighbourhood {
+        match self.classes {
+            Some(_) => CLASS_KERNEL.get_or_init(|| {
+                let weights = MOORE
+                    .iter()
+                    .map(|offset| match offset.abs().to_array().iter().sum::<i32>() {
+                        1 => 1,
+                        2 => EDGE_WEIGHT,
+                        _ => CORNER_WEIGHT,
+                    })
+                    .collect();
+                Neighbourhood::Weighted {
+                    offsets: MOORE.to_vec(),
+                    weights,
+                }
+            }),
+            None => &self.neighbourhood,
+        }
+    }
+
+    // The region a cell is in, if any
+    pub fn region_at(&self, position: IVec3, bounds: IVec3) -> Option<&Region> {
+        let position = position - get_centre(bounds);
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.shape.contains(position))
+    }
+
+    // The rule a cell follows, from its region or this rule
+    pub fn rule_at(&self, position: IVec3, bounds: IVec3) -> &Rule {
+        self.region_at(position, bounds)
+            .map_or(self, |region| &region.rule)
+    }
+
+    // Check if this rule or any of its regions is stochastic, so any cell can change
+    pub fn is_stochastic(&self) -> bool {
+        self.stochastic.is_some() || self.regions.iter().any(|region| region.rule.stochastic.is_some())
+    }
+
+    // Make the regions count neighbours the same way as this rule
+    pub fn match_regions(&mut self) {
+        for region in self.regions.iter_mut() {
+            let rule = &mut region.rule;
+            rule.neighbourhood = self.neighbourhood.clone();
+            rule.regions.clear();
+            rule.classes = match (self.classes, rule.classes) {
+                (Some(_), None) => Some(ClassRule {
+                    birth: ClassValue::from_value(rule.birth),
+                    survival: ClassValue::from_value(rule.survival),
+                }),
+                (Some(_), classes) => classes,
+                (None, _) => None,
+            };
+        }
+    }
+
+    // Check if a live cell in this state stops counting as a neighbour when it decays
+    pub fn stops_counting(&self, state: u8) -> bool {
+        match self.decaying_neighbours {
+            true => state == 1,
+            false => state == self.states,
+        }
+    }
+
+    // Check if a cell in this state is currently counted as a neighbour
+    pub fn counts_as_neighbour(&self, state: u8) -> bool {
+        match self.decaying_neighbours {
+            true => state >= 1,
+            false => state == self.states,
+        }
+    }
+
+    // Check if a dead cell with this neighbour count should be born
+    pub fn is_birth(&self, neighbours: u16) -> bool {
+        match &self.classes {
+            Some(classes) => classes.birth.is_valid(neighbours),
+            None => self.birth.is_valid(neighbours),
+        }
+    }
+
+    // Check if a live cell with this neighbour count should survive
+    pub fn is_survival(&self, neighbours: u16) -> bool {
+        match &self.classes {
+            Some(classes) => classes.survival.is_valid(neighbours),
+            None => self.survival.is_valid(neighbours),
+        }
+    }
+
+    // Check if a dead cell should be born this generation, including the chances of a stochastic rule
+    pub fn should_birth(&self, neighbours: u16, position: IVec3, generation: u64) -> bool {
+        match &self.stochastic {
+            Some(stochastic) => {
+                let chance = Stochastic::chance(&stochastic.birth, self.neighbour_total(neighbours));
+                (self.is_birth(neighbours)
+                    && cell_random(stochastic.seed, generation, position, 0) < chance)
+                    || cell_random(stochastic.seed, generation, position, 1)
+                        < stochastic.spontaneous_birth
+            }
+            None => self.is_birth(neighbours),
+        }
+    }
+
+    // Check if a live cell should survive this generation, including the chances of a stochastic rule
+    pub fn should_survive(&self, neighbours: u16, position: IVec3, generation: u64) -> bool {
+        match &self.stochastic {
+            Some(stochastic) => {
+                let chance =
+                    Stochastic::chance(&stochastic.survival, self.neighbour_total(neighbours));
+                self.is_survival(neighbours)
+                    && cell_random(stochastic.seed, generation, position, 0) < chance
+                    && cell_random(stochastic.seed, generation, position, 1)
+                        >= stochastic.spontaneous_death
+            }
+            None => self.is_survival(neighbours),
+        }
+    }
+
+    // The number of live neighbours a neighbour count stands for
+    pub fn neighbour_total(&self, neighbours: u16) -> u16 {
+        match self.classes {
+            Some(_) => {
+                let (faces, edges, corners) = class_counts(neighbours);
+                faces + edges + corners
+            }
+            None => neighbours,
+        }
+    }
+
+    // The largest number of live neighbours, see `neighbour_total`
+    pub fn max_neighbours(&self) -> usize {
+        match self.classes {
+            Some(_) => MOORE.len(),
+            None => self.neighbourhood.max_neighbours(),
+        }
+    }
+}
+
+// Errors which can occur when reading a rule from its string notation
+#[derive(Clone, PartialEq, Debug)]
+pub enum RuleParseError {
+    // The rule did not have four or five '/' separated fields
+    WrongFieldCount(usize),
+    // A value could not be read as a number
+    InvalidNumber(String),
+    // A range had its start after its end, e.g. 7-5
+    InvalidRange(String),
+    // A value was larger than the neighbourhood allows
+    ValueOutOfRange { value: u32, max: u16 },
+    // The number of states was not between 1 and 255
+    InvalidStates(String),
+    // The optional fifth field was not recognised
+    UnknownFlag(String),
+    // The neighbourhood code was not recognised
+    UnknownNeighbourhood(String),
+    // A custom neighbourhood could not be created
+    InvalidNeighbourhood(NeighbourhoodError),
+    // A semi-totalistic value was not written as faces:edges:corners
+    InvalidClassValue(String),
+    // The chances of a stochastic rule were not written as P(seed;birth;death;count:chance,...;count:chance,...)
+    InvalidStochastic(String),
+}
+
+impl fmt::Display for RuleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleParseError::WrongFieldCount(count) => write!(
+                f,
+                "expected 4 or 5 fields (survival/birth/states/neighbourhood[/D][P(...)]), found {}",
+                count
+            ),
+            RuleParseError::InvalidNumber(text) => write!(f, "'{}' is not a valid number", text),
+            RuleParseError::InvalidRange(text) => {
+                write!(f, "'{}' is not a valid range, the start must not be after the end", text)
+            }
+            RuleParseError::ValueOutOfRange { value, max } => write!(
+                f,
+                "{} is out of range, this neighbourhood has at most {} neighbours",
+                value, max
+            ),
+            RuleParseError::InvalidStates(text) => {
+                write!(f, "'{}' is not a valid number of states (1-255)", text)
+            }
+            RuleParseError::UnknownFlag(text) => write!(
+                f,
+                "'{}' is not a known option, use D for decaying cells to count as neighbours, then P(...) for chances",
+                text
+            ),
+            RuleParseError::UnknownNeighbourhood(text) => write!(
+                f,
+                "'{}' is not a known neighbourhood, use M (Moore), N (Von Neumann), S (semi-totalistic), C(x,y,z;...) or W(x,y,z:w;...)",
+                text
+            ),
+            RuleParseError::InvalidNeighbourhood(error) => write!(f, "{}", error),
+            RuleParseError::InvalidClassValue(text) => {
+                write!(f, "'{}' is not a valid value, expected faces:edges:corners", text)
+            }
+            RuleParseError::InvalidStochastic(text) => write!(
+                f,
+                "'{}' are not valid chances, expected (seed;spontaneous birth;spontaneous death;count:chance,...;count:chance,...) with chances from 0 to 1",
+                text
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RuleParseError {}
+
+// Standard 3D rule notation: survival/birth/states/neighbourhood, e.g. 4/4/5/M
+// Semi-totalistic rules use the neighbourhood S, with faces:edges:corners values, e.g. 1:2:*/0:2:1/5/S
+// A fifth field of D means decaying cells count as neighbours, e.g. 4/4/5/M/D
+// Stochastic rules add their chances to the fifth field, after any D, e.g. 4/4/5/M/DP(7;0.002;0;4:0.5;)
+// Regions are not part of the notation
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.classes {
+            Some(classes) => write!(f, "{}/{}/{}/S", classes.survival, classes.birth, self.states)?,
+            None => write!(
+                f,
+                "{}/{}/{}/{}",
+                self.survival, self.birth, self.states, self.neighbourhood
+            )?,
+        }
+        if self.decaying_neighbours || self.stochastic.is_some() {
+            write!(f, "/")?;
+        }
+        if self.decaying_neighbours {
+            write!(f, "D")?;
+        }
+        if let Some(stochastic) = &self.stochastic {
+            write!(f, "{}", stochastic)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = RuleParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = text.trim().split('/').collect();
+        if fields.len() != 4 && fields.len() != 5 {
+            return Err(RuleParseError::WrongFieldCount(fields.len()));
+        }
+
+        let flags = fields.get(4).map_or("", |flags| flags.trim());
+        let (decaying_neighbours, chances) = match flags.strip_prefix(['D', 'd']) {
+            Some(chances) => (true, chances.trim()),
+            None => (false, flags),
+        };
+        let stochastic = match chances.strip_prefix(['P', 'p']) {
+            Some(chances) => Some(Stochastic::parse(chances.trim())?),
+            None if chances.is_empty() => None,
+            None => return Err(RuleParseError::UnknownFlag(flags.to_string())),
+        };
+
+        let states = match fields[2].trim().parse::<u8>() {
+            Ok(states) if states > 0 => states,
+            _ => return Err(RuleParseError::InvalidStates(fields[2].trim().to_string())),
+        };
+
+        if matches!(fields[3].trim(), "S" | "s") {
+            return Ok(Rule {
+                states,
+                classes: Some(ClassRule {
+                    survival: ClassValue::parse(fields[0])?,
+                    birth: ClassValue::parse(fields[1])?,
+                }),
+                decaying_neighbours,
+                stochastic,
+                ..Default::default()
+            });
+        }
+
+        // Read the neighbourhood first, as it limits the valid values
+        let neighbourhood = match fields[3].parse::<Neighbourhood>() {
+            Ok(neighbourhood) => neighbourhood,
+            Err(NeighbourhoodError::Unknown(text)) => {
+                return Err(RuleParseError::UnknownNeighbourhood(text))
+            }
+            Err(error) => return Err(RuleParseError::InvalidNeighbourhood(error)),
+        };
+        let max = neighbourhood.max_neighbours() as u16;
+
+        Ok(Rule {
+            survival: Value::parse(fields[0], max)?,
+            birth: Value::parse(fields[1], max)?,
+            neighbourhood,
+            states,
+            classes: None,
+            decaying_neighbours,
+            stochastic,
+            regions: vec![],
+        })
+    }
 }
 
 #[cfg(test)]
@@ -173,10 +816,281 @@ mod rule {
         let survival = Value::new(&indices2);
         let neighbourhood = Neighbourhood::Moore;
         let states = 2;
-        let rule = Rule { birth, survival, neighbourhood, states };
+        let rule = Rule { birth, survival, neighbourhood: neighbourhood.clone(), states, ..Default::default() };
         assert_eq!(rule.birth, birth);
         assert_eq!(rule.survival, survival);
         assert_eq!(rule.neighbourhood, neighbourhood);
         assert_eq!(rule.states, states);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rule_from_str() {
+        let rule: Rule = "4/4/5/M".parse().unwrap();
+        assert_eq!(rule.survival, Value::new(&[4]));
+        assert_eq!(rule.birth, Value::new(&[4]));
+        assert_eq!(rule.states, 5);
+        assert_eq!(rule.neighbourhood, Neighbourhood::Moore);
+
+        let rule: Rule = "0-6/1,3/2/N".parse().unwrap();
+        assert_eq!(rule.survival, Value::from_range(0..=6));
+        assert_eq!(rule.birth, Value::new(&[1, 3]));
+        assert_eq!(rule.neighbourhood, Neighbourhood::VonNeumann);
+
+        let rule: Rule = "9-26/5-7,12-13,15/5/M".parse().unwrap();
+        assert_eq!(rule.survival, Value::from_range(9..=26));
+        assert_eq!(rule.birth, Value::new(&[5, 6, 7, 12, 13, 15]));
+
+        // Extended neighbourhoods allow values above 26
+        let rule: Rule = "30-124/40/2/M2".parse().unwrap();
+        assert_eq!(rule.survival, Value::from_range(30..=124));
+        assert!(rule.birth.is_valid(40));
+        assert_eq!(rule.neighbourhood, Neighbourhood::ExtendedMoore(2));
+        assert_eq!(
+            "125/40/2/M2".parse::<Rule>(),
+            Err(RuleParseError::ValueOutOfRange { value: 125, max: 124 })
+        );
+
+        // Empty values are allowed
+        let rule: Rule = "/4/2/M".parse().unwrap();
+        assert_eq!(rule.survival, Value::new(&[]));
+    }
+
+    #[test]
+    fn test_rule_from_str_errors() {
+        assert_eq!("4/4/5".parse::<Rule>(), Err(RuleParseError::WrongFieldCount(3)));
+        assert_eq!("4/4/5/M/D/D".parse::<Rule>(), Err(RuleParseError::WrongFieldCount(6)));
+        assert_eq!(
+            "4/4/5/M/X".parse::<Rule>(),
+            Err(RuleParseError::UnknownFlag("X".into()))
+        );
+        assert_eq!(
+            "4/a/5/M".parse::<Rule>(),
+            Err(RuleParseError::InvalidNumber("a".into()))
+        );
+        assert_eq!(
+            "7-5/4/5/M".parse::<Rule>(),
+            Err(RuleParseError::InvalidRange("7-5".into()))
+        );
+        assert_eq!(
+            "4/7/5/N".parse::<Rule>(),
+            Err(RuleParseError::ValueOutOfRange { value: 7, max: 6 })
+        );
+        assert_eq!(
+            "4/4/0/M".parse::<Rule>(),
+            Err(RuleParseError::InvalidStates("0".into()))
+        );
+        assert_eq!(
+            "4/4/256/M".parse::<Rule>(),
+            Err(RuleParseError::InvalidStates("256".into()))
+        );
+        assert_eq!(
+            "4/4/5/X".parse::<Rule>(),
+            Err(RuleParseError::UnknownNeighbourhood("X".into()))
+        );
+        assert_eq!(
+            "2/3/5/C(1,0,0;0,1,0)".parse::<Rule>(),
+            Err(RuleParseError::ValueOutOfRange { value: 3, max: 2 })
+        );
+        assert_eq!(
+            "4/4/5/C()".parse::<Rule>(),
+            Err(RuleParseError::InvalidNeighbourhood(NeighbourhoodError::Empty))
+        );
+        assert_eq!(
+            "4/4/5/M/PD(0;0;0;;)".parse::<Rule>(),
+            Err(RuleParseError::InvalidStochastic("D(0;0;0;;)".into()))
+        );
+        assert_eq!(
+            "4/4/5/M/P(0;1.5;0;;)".parse::<Rule>(),
+            Err(RuleParseError::InvalidStochastic("(0;1.5;0;;)".into()))
+        );
+        assert_eq!(
+            "4/4/5/M/P(0;0;0;;999:0.5)".parse::<Rule>(),
+            Err(RuleParseError::InvalidStochastic("(0;0;0;;999:0.5)".into()))
+        );
+        assert_eq!(
+            "4/4/5/M/P(0;0;0)".parse::<Rule>(),
+            Err(RuleParseError::InvalidStochastic("(0;0;0)".into()))
+        );
+    }
+
+    #[test]
+    fn test_rule_display() {
+        let rule = Rule {
+            survival: Value::from_range(9..=26),
+            birth: Value::new(&[5, 6, 7, 12, 13, 15]),
+            neighbourhood: Neighbourhood::Moore,
+            states: 5,
+            ..Default::default()
+        };
+        assert_eq!(rule.to_string(), "9-26/5-7,12-13,15/5/M");
+
+        let rule = Rule {
+            survival: Value::new(&[]),
+            birth: Value::new(&[1, 3]),
+            neighbourhood: Neighbourhood::VonNeumann,
+            states: 2,
+            ..Default::default()
+        };
+        assert_eq!(rule.to_string(), "/1,3/2/N");
+        assert_eq!(rule.to_string().parse::<Rule>().unwrap(), rule);
+
+        let rule: Rule = "1/1-2/3/C(1,0,0;0,-1,0)".parse().unwrap();
+        assert_eq!(rule.to_string(), "1/1-2/3/C(1,0,0;0,-1,0)");
+
+        let rule: Rule = "4/4/5/M/D".parse().unwrap();
+        assert!(rule.decaying_neighbours);
+        assert_eq!(rule.to_string(), "4/4/5/M/D");
+        assert!(!"4/4/5/M".parse::<Rule>().unwrap().decaying_neighbours);
+
+        // Chances follow any D and only list the counts which do not always happen
+        let mut stochastic = Stochastic {
+            seed: 7,
+            spontaneous_birth: 0.002,
+            ..Default::default()
+        };
+        stochastic.birth[4] = 0.5;
+        stochastic.survival[2] = 0.1;
+        stochastic.survival[3] = 0.0;
+        let rule = Rule {
+            birth: Value::new(&[4]),
+            survival: Value::new(&[2, 3]),
+            stochastic: Some(stochastic),
+            ..Default::default()
+        };
+        let text = rule.to_string();
+        assert!(text.ends_with("/P(7;0.002;0;4:0.5;2:0.1,3:0)"), "{}", text);
+        assert_eq!(text.parse::<Rule>().unwrap(), rule);
+
+        let rule: Rule = "4/4/5/M/DP(1;0;0.25;;)".parse().unwrap();
+        assert!(rule.decaying_neighbours);
+        assert_eq!(rule.stochastic.as_ref().unwrap().spontaneous_death, 0.25);
+        assert_eq!(rule.to_string(), "4/4/5/M/DP(1;0;0.25;;)");
+    }
+
+    #[test]
+    fn test_class_value() {
+        let value = ClassValue::parse("1:2:3,6:12:8").unwrap();
+        assert!(value.get_value(1, 2, 3));
+        assert!(value.get_value(6, 12, 8));
+        assert!(!value.get_value(1, 2, 4));
+        assert!(value.is_valid(1 + 2 * 7 + 3 * 91));
+        assert!(!value.is_valid(u16::MAX));
+
+        let value = value.change_value(1, 2, 3);
+        assert!(!value.get_value(1, 2, 3));
+
+        // Every count adding up to 4 is valid
+        let value = ClassValue::from_value(Value::new(&[4]));
+        assert!(value.get_value(4, 0, 0));
+        assert!(value.get_value(1, 2, 1));
+        assert!(value.get_value(0, 0, 4));
+        assert!(!value.get_value(1, 1, 1));
+    }
+
+    #[test]
+    fn test_class_value_notation() {
+        let value = ClassValue::parse("1:0-1:*, 2:2:0").unwrap();
+        assert!(value.get_value(1, 0, 0));
+        assert!(value.get_value(1, 1, 8));
+        assert!(value.get_value(2, 2, 0));
+        assert!(!value.get_value(2, 2, 1));
+        assert_eq!(value.to_string(), "1:0:0-8,1:1:0-8,2:2:0");
+        assert_eq!(ClassValue::parse(&value.to_string()), Ok(value));
+
+        assert_eq!(
+            ClassValue::parse("1:2"),
+            Err(RuleParseError::InvalidClassValue("1:2".into()))
+        );
+        assert_eq!(
+            ClassValue::parse("7:0:0"),
+            Err(RuleParseError::ValueOutOfRange { value: 7, max: FACES })
+        );
+    }
+
+    #[test]
+    fn test_class_rule() {
+        let rule: Rule = "1:2:*/0:0:1-2/5/S".parse().unwrap();
+        let classes = rule.classes.unwrap();
+        assert!(classes.survival.get_value(1, 2, 8));
+        assert!(classes.birth.get_value(0, 0, 2));
+        assert_eq!(rule.states, 5);
+        assert_eq!(rule.to_string(), "1:2:0-8/0:0:1-2/5/S");
+
+        // Each class adds a different weight, so a single corner can be told apart from faces
+        let corner = rule
+            .kernel()
+            .get_weighted_iter()
+            .find(|(offset, _)| offset.abs().to_array().iter().sum::<i32>() == 3)
+            .unwrap();
+        assert!(rule.is_birth(corner.1));
+        assert!(!rule.is_birth(1));
+        assert_eq!(rule.neighbour_total(corner.1 * 2 + 3), 5);
+        assert_eq!(rule.max_neighbours(), 26);
+        assert_eq!(rule.kernel().max_neighbours(), 6 + 12 * 7 + 8 * 91);
+    }
+
+    #[test]
+    fn test_stops_counting() {
+        let mut rule = Rule {
+            states: 5,
+            ..Default::default()
+        };
+        // Cells stop counting as soon as they start to decay
+        assert!(rule.stops_counting(5));
+        assert!(!rule.stops_counting(1));
+
+        // Cells count until they die
+        rule.decaying_neighbours = true;
+        assert!(!rule.stops_counting(5));
+        assert!(rule.stops_counting(1));
+    }
+
+    #[test]
+    fn test_stochastic_rule() {
+        let position = IVec3::new(1, 2, 3);
+        let mut rule = Rule {
+            birth: Value::new(&[4]),
+            survival: Value::new(&[4]),
+            ..Default::default()
+        };
+        assert!(rule.should_birth(4, position, 0));
+        assert!(!rule.should_birth(3, position, 0));
+
+        // Certain chances behave like the deterministic rule
+        rule.stochastic = Some(Stochastic::default());
+        assert!(rule.should_birth(4, position, 0));
+        assert!(!rule.should_birth(3, position, 0));
+        assert!(rule.should_survive(4, position, 0));
+
+        // Impossible chances never happen
+        let stochastic = rule.stochastic.as_mut().unwrap();
+        stochastic.birth[4] = 0.0;
+        stochastic.spontaneous_death = 1.0;
+        assert!(!rule.should_birth(4, position, 0));
+        assert!(!rule.should_survive(4, position, 0));
+
+        // Spontaneous births ignore the neighbours
+        rule.stochastic.as_mut().unwrap().spontaneous_birth = 1.0;
+        assert!(rule.should_birth(0, position, 0));
+
+        // Roughly half of the cells are born with an even chance, the same way each time
+        let stochastic = rule.stochastic.as_mut().unwrap();
+        stochastic.birth[4] = 0.5;
+        stochastic.spontaneous_birth = 0.0;
+        let births = |rule: &Rule| {
+            (0..1000)
+                .filter(|x| rule.should_birth(4, IVec3::new(*x, 0, 0), 7))
+                .count()
+        };
+        let count = births(&rule);
+        assert!(count > 400 && count < 600);
+        assert_eq!(births(&rule), count);
+
+        // A different seed gives different cells
+        rule.stochastic.as_mut().unwrap().seed = 1;
+        let other: Vec<bool> = (0..100).map(|x| rule.should_birth(4, IVec3::new(x, 0, 0), 7)).collect();
+        rule.stochastic.as_mut().unwrap().seed = 0;
+        let first: Vec<bool> = (0..100).map(|x| rule.should_birth(4, IVec3::new(x, 0, 0), 7)).collect();
+        assert_ne!(first, other);
+    }
+}
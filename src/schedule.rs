@@ -0,0 +1,326 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use bevy::prelude::Color;
+
+use crate::cells::Example;
+use crate::color_method::ColourMethod;
+use crate::rule::{Rule, RuleParseError};
+
+// A rule and colours to switch to once the simulation reaches a generation
+#[derive(Clone, Debug)]
+pub struct ScheduleEntry {
+    pub generation: u64,
+    pub example: Example,
+}
+
+// A list of rules to switch between as the simulation runs, which can repeat
+// Saved as text, with one entry per line, e.g.
+// loop 200
+// 0 | Crystals | 0-6/1,3/2/N | State | ff0000ff | 00ff00ff
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub entries: Vec<ScheduleEntry>,
+    pub enabled: bool,
+    pub looping: bool,
+    // Number of generations before the schedule repeats
+    pub length: u64,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            entries: vec![],
+            enabled: false,
+            looping: false,
+            length: 100,
+        }
+    }
+}
+
+impl Schedule {
+    // Add an entry, keeping the entries in order of generation
+    pub fn add(&mut self, generation: u64, example: Example) {
+        self.entries.push(ScheduleEntry { generation, example });
+        self.sort();
+    }
+
+    pub fn sort(&mut self) {
+        self.entries.sort_by_key(|entry| entry.generation);
+    }
+
+    // Index of the entry which should be in use at a generation, if any have started
+    pub fn active(&self, generation: u64) -> Option<usize> {
+        let generation = match self.looping {
+            true => generation % self.length.max(1),
+            false => generation,
+        };
+        self.entries
+            .iter()
+            .rposition(|entry| entry.generation <= generation)
+    }
+
+    // Load a schedule from a file, in the format written by `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScheduleError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|error| ScheduleError::Io(error.to_string()))?;
+        text.parse()
+    }
+
+    // Regions are not part of the rule notation, so entries with them can not be saved
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ScheduleError> {
+        if let Some(entry) = self.entries.iter().find(|entry| !entry.example.rule.regions.is_empty()) {
+            return Err(ScheduleError::HasRegions(entry.example.name.clone()));
+        }
+        std::fs::write(path, self.to_string()).map_err(|error| ScheduleError::Io(error.to_string()))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# generation | name | rule | colour method | colour 1 | colour 2")?;
+        if self.looping {
+            writeln!(f, "loop {}", self.length)?;
+        }
+        for ScheduleEntry { generation, example } in &self.entries {
+            writeln!(
+                f,
+                "{} | {} | {} | {:?} | {} | {}",
+                generation,
+                example.name,
+                example.rule,
+                example.colour_method,
+                colour_to_hex(example.colour1),
+                colour_to_hex(example.colour2)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut schedule = Schedule {
+            enabled: true,
+            ..Default::default()
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(length) = line.strip_prefix("loop") {
+                schedule.looping = true;
+                schedule.length = match length.trim().parse::<u64>() {
+                    Ok(length) if length > 0 => length,
+                    _ => return Err(ScheduleError::InvalidLength(number)),
+                };
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('|').map(|field| field.trim()).collect();
+            if fields.len() != 6 {
+                return Err(ScheduleError::WrongFieldCount(number, fields.len()));
+            }
+            let generation = fields[0]
+                .parse::<u64>()
+                .map_err(|_| ScheduleError::InvalidGeneration(number))?;
+            let rule = fields[2]
+                .parse::<Rule>()
+                .map_err(|error| ScheduleError::InvalidRule(number, error))?;
+            let colour_method = parse_colour_method(fields[3])
+                .ok_or_else(|| ScheduleError::UnknownColourMethod(number, fields[3].to_string()))?;
+            let colour1 = Color::hex(fields[4])
+                .map_err(|_| ScheduleError::InvalidColour(number, fields[4].to_string()))?;
+            let colour2 = Color::hex(fields[5])
+                .map_err(|_| ScheduleError::InvalidColour(number, fields[5].to_string()))?;
+
+            schedule.add(
+                generation,
+                Example {
+                    name: fields[1].to_string(),
+                    rule,
+                    colour_method,
+                    colour1,
+                    colour2,
+                },
+            );
+        }
+        Ok(schedule)
+    }
+}
+
+fn parse_colour_method(text: &str) -> Option<ColourMethod> {
+    use ColourMethod::*;
+    [Colour1, Colour2, State, DistToCenter, Neighbour, Index]
+        .into_iter()
+        .find(|method| format!("{:?}", method).eq_ignore_ascii_case(text))
+}
+
+// Write a colour as RRGGBBAA, which `Color::hex` can read back
+fn colour_to_hex(colour: Color) -> String {
+    colour
+        .as_rgba_f32()
+        .iter()
+        .map(|channel| format!("{:02x}", (channel * 255.0).round() as u8))
+        .collect()
+}
+
+// Reasons a schedule could not be read, with the line they were found on
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleError {
+    // A line did not have six fields separated by '|'
+    WrongFieldCount(usize, usize),
+    // The generation was not a whole number
+    InvalidGeneration(usize),
+    // The loop length was not a whole number above zero
+    InvalidLength(usize),
+    InvalidRule(usize, RuleParseError),
+    UnknownColourMethod(usize, String),
+    InvalidColour(usize, String),
+    // The file could not be read or written
+    Io(String),
+    // An entry's rule has regions, which the file can not hold
+    HasRegions(String),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::WrongFieldCount(line, count) => write!(
+                f,
+                "line {}: expected generation | name | rule | colour method | colour 1 | colour 2, found {} fields",
+                line, count
+            ),
+            ScheduleError::InvalidGeneration(line) => {
+                write!(f, "line {}: the generation must be a whole number", line)
+            }
+            ScheduleError::InvalidLength(line) => {
+                write!(f, "line {}: the loop length must be a whole number above 0", line)
+            }
+            ScheduleError::InvalidRule(line, error) => write!(f, "line {}: {}", line, error),
+            ScheduleError::UnknownColourMethod(line, text) => {
+                write!(f, "line {}: '{}' is not a known colour method", line, text)
+            }
+            ScheduleError::InvalidColour(line, text) => write!(
+                f,
+                "line {}: '{}' is not a valid colour, expected a hex code such as ff0000",
+                line, text
+            ),
+            ScheduleError::Io(error) => write!(f, "could not access file: {}", error),
+            ScheduleError::HasRegions(name) => write!(
+                f,
+                "'{}' has regions, which can not be saved in a schedule",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+#[cfg(test)]
+mod schedule {
+    use super::*;
+
+    fn example(name: &str, rule: &str) -> Example {
+        Example {
+            name: name.into(),
+            rule: rule.parse().unwrap(),
+            colour_method: ColourMethod::State,
+            colour1: Color::RED,
+            colour2: Color::GREEN,
+        }
+    }
+
+    #[test]
+    fn test_active() {
+        let mut schedule = Schedule::default();
+        schedule.add(50, example("445", "4/4/5/M"));
+        schedule.add(10, example("Crystals", "0-6/1,3/2/N"));
+
+        // Entries are kept in order
+        assert_eq!(schedule.entries[0].example.name, "Crystals");
+        assert_eq!(schedule.active(0), None);
+        assert_eq!(schedule.active(10), Some(0));
+        assert_eq!(schedule.active(49), Some(0));
+        assert_eq!(schedule.active(500), Some(1));
+
+        schedule.looping = true;
+        schedule.length = 100;
+        assert_eq!(schedule.active(105), None);
+        assert_eq!(schedule.active(110), Some(0));
+        assert_eq!(schedule.active(199), Some(1));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut schedule = Schedule {
+            looping: true,
+            length: 200,
+            ..Default::default()
+        };
+        schedule.add(0, example("Crystals", "0-6/1,3/2/N"));
+        schedule.add(120, example("Expanding Pyramid", "1-2/1/2/C(1,0,0;0,1,0)"));
+
+        let parsed: Schedule = schedule.to_string().parse().unwrap();
+        assert!(parsed.looping);
+        assert_eq!(parsed.length, 200);
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[1].generation, 120);
+        assert_eq!(parsed.entries[1].example.name, "Expanding Pyramid");
+        assert_eq!(parsed.entries[1].example.rule, schedule.entries[1].example.rule);
+        assert_eq!(parsed.entries[0].example.colour_method, ColourMethod::State);
+        assert_eq!(parsed.entries[0].example.colour1, Color::RED);
+
+        // Stochastic chances are kept in the rule
+        schedule.add(60, example("Flicker", "4/4/5/M/DP(7;0.002;0;4:0.5;)"));
+        let parsed: Schedule = schedule.to_string().parse().unwrap();
+        assert_eq!(parsed.entries[1].example.rule, schedule.entries[1].example.rule);
+        assert_eq!(parsed.entries[1].example.rule.stochastic.as_ref().unwrap().birth[4], 0.5);
+    }
+
+    #[test]
+    fn test_save_regions() {
+        use crate::regions::Region;
+
+        let mut schedule = Schedule::default();
+        let mut entry = example("445", "4/4/5/M");
+        entry.rule.regions.push(Region {
+            shape: Default::default(),
+            rule: entry.rule.clone(),
+            colour: Color::ORANGE,
+        });
+        schedule.add(0, entry);
+
+        let path = std::env::temp_dir().join("schedule_regions_test.txt");
+        assert_eq!(schedule.save(path), Err(ScheduleError::HasRegions("445".into())));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "0 | Crystals | 0-6/1,3/2/N".parse::<Schedule>().unwrap_err(),
+            ScheduleError::WrongFieldCount(1, 3)
+        );
+        assert_eq!(
+            "\nten | A | 4/4/5/M | State | ff0000 | 00ff00".parse::<Schedule>().unwrap_err(),
+            ScheduleError::InvalidGeneration(2)
+        );
+        assert_eq!(
+            "0 | A | 4/4/5/M | Rainbow | ff0000 | 00ff00".parse::<Schedule>().unwrap_err(),
+            ScheduleError::UnknownColourMethod(1, "Rainbow".into())
+        );
+        assert!(matches!(
+            "0 | A | 4/4/5/Q | State | ff0000 | 00ff00".parse::<Schedule>(),
+            Err(ScheduleError::InvalidRule(1, _))
+        ));
+        assert_eq!("loop 0".parse::<Schedule>().unwrap_err(), ScheduleError::InvalidLength(1));
+    }
+}
@@ -8,7 +8,6 @@ use bevy::{
 
 use bevy_flycam::prelude::*;
 
-// use crate::cells::multi_threaded;
 use crate::color_method::ColourMethod::{
     State,
     Neighbour,
@@ -16,10 +15,14 @@ use crate::color_method::ColourMethod::{
 };
 use crate::{
     cells::{
+        bit_packed,
+        hash_life,
         multi_dimensional,
+        multi_species,
         single_threaded,
+        sparse,
         Example,
-        // multi_threaded,
+        multi_threaded,
         Sims,
     },
     neighbours::Neighbourhood::{Moore, VonNeumann},
@@ -33,6 +36,49 @@ pub fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut sims: ResMut<Sims>,
 ) {
+    add_sims(&mut sims);
+    add_examples(&mut sims);
+
+    // todo! Use RNG to select a random example
+    sims.set_example(0);
+
+    // todo! Have a look into transparent cells for demo
+    commands.spawn((
+        meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        Transform::from_xyz(0.0, 0.0, 0.0),
+        GlobalTransform::default(),
+        InstanceMaterialData(
+            (1..=10)
+                .flat_map(|x| (1..=100).map(move |y| (x as f32 / 10.0, y as f32 / 10.0)))
+                .map(|(x, y)| InstanceData {
+                    position: Vec3::new(x * 10.0 - 5.0, y * 10.0 - 5.0, 0.0),
+                    scale: 0.9,
+                    color: Color::rgba(1.0, 0.5, 0.5, 0.2).into(),
+                })
+                .collect(),
+        ),
+        Visibility::default(),
+        ComputedVisibility::default(),
+        NoFrustumCulling,
+    ));
+
+    // commands.spawn(PbrBundle {
+    //     mesh: meshes.add(Mesh::from())
+    // })
+
+    // Spawn Camera
+    // https://bevy-cheatbook.github.io/window/clear-color.html
+    commands
+        .spawn(Camera3dBundle {
+            transform: Transform::from_xyz(50.0, 25.0, 50.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        // .insert(RotatingCamera::default());
+        .insert(FlyCam);
+}
+
+// Every engine which can be chosen, which the differential harness also checks against each other
+pub fn add_sims(sims: &mut Sims) {
     sims.add_sim(
         "Simple Cell".into(),
         Box::new(single_threaded::SingleThreaded::new()),
@@ -43,12 +89,33 @@ pub fn setup(
         Box::new(multi_dimensional::MultiDimensional::new()),
     );
 
-    // sims.add_sim(
-    //     "Multi-Threaded Cell".into(),
-    //     Box::new(multi_threaded::MultiThreaded::new()),
-    // );
+    sims.add_sim(
+        "Multi-Species Cell".into(),
+        Box::new(multi_species::MultiSpecies::new()),
+    );
+
+    sims.add_sim(
+        "Sparse Cell".into(),
+        Box::new(sparse::Sparse::new()),
+    );
+
+    sims.add_sim(
+        "Bit-Packed Cell".into(),
+        Box::new(bit_packed::BitPacked::new()),
+    );
+
+    sims.add_sim(
+        "HashLife Cell".into(),
+        Box::new(hash_life::HashLife::new()),
+    );
+
+    sims.add_sim(
+        "Multi-Threaded Cell".into(),
+        Box::new(multi_threaded::MultiThreaded::new()),
+    );
+}
 
-    // todo! Add to its own system
+pub fn add_examples(sims: &mut Sims) {
     sims.add_example(Example {
         name: "Chaos".into(),
         rule: Rule {
@@ -56,6 +123,7 @@ pub fn setup(
             birth: Value::new(&[4, 6, 8, 9, 10]),
             states: 10,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::RED,
@@ -69,6 +137,7 @@ pub fn setup(
             birth: Value::new(&[1, 3]),
             states: 2,
             neighbourhood: VonNeumann,
+            ..default()
         },
         colour_method: Neighbour,
         colour1: Color::BLACK,
@@ -82,6 +151,7 @@ pub fn setup(
             birth: Value::new(&[4]),
             states: 50,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::RED,
@@ -95,6 +165,7 @@ pub fn setup(
             birth: Value::new(&[4]),
             states: 25,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::LIME_GREEN,
@@ -108,6 +179,7 @@ pub fn setup(
             birth: Value::new(&[4]),
             states: 50,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: Index,
         colour1: Color::WHITE,
@@ -121,6 +193,7 @@ pub fn setup(
             birth: Value::new(&[1, 4]),
             states: 50,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::LIME_GREEN,
@@ -134,6 +207,7 @@ pub fn setup(
             birth: Value::new(&[4,13,17,20,21,22,23,24,26]),
             states: 4,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::RED,
@@ -147,6 +221,7 @@ pub fn setup(
             birth: Value::new(&[6,7,9]),
             states: 10,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::GREEN,
@@ -160,6 +235,7 @@ pub fn setup(
             birth: Value::new(&[4,8,10]),
             states: 20,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::PINK,
@@ -173,6 +249,7 @@ pub fn setup(
             birth: Value::new(&[4]),
             states: 5,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::BLUE,
@@ -186,46 +263,10 @@ pub fn setup(
             birth: Value::new(&[3]),
             states: 20,
             neighbourhood: Moore,
+            ..default()
         },
         colour_method: State,
         colour1: Color::ORANGE,
         colour2: Color::TEAL,
     });
-
-    // todo! Use RNG to select a random example
-    sims.set_example(0);
-
-    // todo! Have a look into transparent cells for demo
-    commands.spawn((
-        meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
-        Transform::from_xyz(0.0, 0.0, 0.0),
-        GlobalTransform::default(),
-        InstanceMaterialData(
-            (1..=10)
-                .flat_map(|x| (1..=100).map(move |y| (x as f32 / 10.0, y as f32 / 10.0)))
-                .map(|(x, y)| InstanceData {
-                    position: Vec3::new(x * 10.0 - 5.0, y * 10.0 - 5.0, 0.0),
-                    scale: 0.9,
-                    color: Color::rgba(1.0, 0.5, 0.5, 0.2).into(),
-                })
-                .collect(),
-        ),
-        Visibility::default(),
-        ComputedVisibility::default(),
-        NoFrustumCulling,
-    ));
-
-    // commands.spawn(PbrBundle {
-    //     mesh: meshes.add(Mesh::from())
-    // })
-
-    // Spawn Camera
-    // https://bevy-cheatbook.github.io/window/clear-color.html
-    commands
-        .spawn(Camera3dBundle {
-            transform: Transform::from_xyz(50.0, 25.0, 50.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..default()
-        })
-        // .insert(RotatingCamera::default());
-        .insert(FlyCam);
 }
@@ -5,50 +5,98 @@ use bevy::{
 use rand::Rng;
 
 // Adapted from TanTanDev
-pub fn idx_to_pos(index: i32, bounds: i32) -> IVec3 {
+pub fn idx_to_pos(index: i32, bounds: IVec3) -> IVec3 {
     // The index to position conversion was broken
 
     // Old Code:
     // ivec3(index % bounds,index / bounds & bounds,index / bounds / bounds)
 
     // (Modified) Source: https://stackoverflow.com/a/11712864
-    let rem = index % (bounds * bounds);
-    ivec3(rem % bounds, rem / bounds, index / (bounds * bounds))
+    let layer = bounds.x * bounds.y;
+    let rem = index % layer;
+    ivec3(rem % bounds.x, rem / bounds.x, index / layer)
 }
 
-pub fn generate_noise<F: FnMut(IVec3)>(centre: IVec3, radius: i32, amount: usize, mut f: F) {
-    let mut rand = rand::thread_rng();
-    (0..amount).for_each(|_| {
-        f(centre
-            + ivec3(
-            rand.gen_range(-radius..=radius),
-            rand.gen_range(-radius..=radius),
-            rand.gen_range(-radius..=radius),
-        ));
-    });
+// Random positions around the centre, which are the same for the same state of the generator
+pub fn generate_noise<R: Rng>(rand: &mut R, radius: i32, amount: usize) -> Vec<IVec3> {
+    (0..amount)
+        .map(|_| {
+            ivec3(
+                rand.gen_range(-radius..=radius),
+                rand.gen_range(-radius..=radius),
+                rand.gen_range(-radius..=radius),
+            )
+        })
+        .collect()
 }
 
-pub fn default_noise<F: FnMut(IVec3)>(centre: IVec3, f: F) {
-    generate_noise(centre, 10, 8 * 8 * 8, f)
+pub fn default_noise<R: Rng>(rand: &mut R) -> Vec<IVec3> {
+    generate_noise(rand, 10, 8 * 8 * 8)
 }
 
-pub fn pos_to_idx(position: IVec3, bounds: i32) -> usize {
-    (position.x + (position.y * bounds) + (position.z * bounds * bounds)) as usize
+pub fn pos_to_idx(position: IVec3, bounds: IVec3) -> usize {
+    (position.x + (position.y * bounds.x) + (position.z * bounds.x * bounds.y)) as usize
 }
 
-pub fn get_centre(bounds: i32) -> IVec3 {
-    let centre: i32 = bounds / 2;
-    ivec3(centre, centre, centre)
+// Number of cells in a grid of this size
+pub fn cell_count(bounds: IVec3) -> usize {
+    (bounds.x * bounds.y * bounds.z) as usize
 }
 
-pub fn wrap(position: IVec3, bounds: i32) -> IVec3 {
-    (position + bounds) % bounds
+pub fn get_centre(bounds: IVec3) -> IVec3 {
+    bounds / 2
 }
 
-pub fn get_dist_to_centre(position: IVec3, bounds: i32) -> f32 {
+// Check if a position is inside the box of the bounds
+pub fn in_bounds(position: IVec3, bounds: IVec3) -> bool {
+    position.cmpge(IVec3::ZERO).all() && position.cmplt(bounds).all()
+}
+
+pub fn wrap(position: IVec3, bounds: IVec3) -> IVec3 {
+    ivec3(
+        position.x.rem_euclid(bounds.x),
+        position.y.rem_euclid(bounds.y),
+        position.z.rem_euclid(bounds.z),
+    )
+}
+
+// Distance from the centre, where 1 is the middle of a face, along each axis
+pub fn get_dist_to_centre(position: IVec3, bounds: IVec3) -> f32 {
     let pos = position - get_centre(bounds);
-    let max = bounds as f32 / 2.0;
-    pos.as_vec3().length() / max
+    let max = bounds.as_vec3() / 2.0;
+    (pos.as_vec3() / max).length()
+}
+
+// Resize the sides which were not changed by the same factor as the one which was
+pub fn scale_bounds(previous: IVec3, bounds: IVec3, min: i32, max: i32) -> IVec3 {
+    let Some(axis) = (0..3).find(|axis| bounds[*axis] != previous[*axis]) else {
+        return bounds;
+    };
+    let factor = bounds[axis] as f32 / previous[axis] as f32;
+    let mut scaled = (previous.as_vec3() * factor).round().as_ivec3().clamp(IVec3::splat(min), IVec3::splat(max));
+    scaled[axis] = bounds[axis];
+    scaled
+}
+
+// Random number in [0, 1) for a cell, which is always the same for the same inputs
+// `stream` picks between independent numbers for the same cell and generation
+// Source: SplitMix64 - https://prng.di.unimi.it/splitmix64.c
+pub fn cell_random(seed: u64, generation: u64, position: IVec3, stream: u64) -> f32 {
+    let xy = (position.x as u32 as u64) | ((position.y as u32 as u64) << 32);
+    let z = (position.z as u32 as u64) | (stream << 32);
+    let mut hash = seed;
+    for value in [generation, xy, z] {
+        hash = splitmix64(hash ^ value);
+    }
+    // Use the top 24 bits, as that is all an f32 can hold exactly
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
 }
 
 pub fn state_colour(colour1: Color, colour2: Color, gradient: f32) -> Color {
@@ -62,12 +110,13 @@ pub fn state_colour(colour1: Color, colour2: Color, gradient: f32) -> Color {
 #[cfg(test)]
 mod utils {
     use approx::assert_relative_eq;
+    use rand::{rngs::StdRng, SeedableRng};
     use std::collections::HashSet;
     use super::*;
 
     #[test]
     fn test_pos_to_idx() {
-        let bounds = 32;
+        let bounds = IVec3::splat(32);
 
         // Test case 1
         let index = 0;
@@ -80,49 +129,82 @@ mod utils {
         // Test case 3
         let index = 32768;
         assert_eq!(ivec3(0, 0, 32), idx_to_pos(index, bounds));
+
+        // Test case 4
+        let index = 2 * 8 + 3 * 8 * 5 + 1;
+        assert_eq!(ivec3(1, 2, 3), idx_to_pos(index, ivec3(8, 5, 2)));
     }
 
     #[test]
     fn test_idx_to_pos() {
         // Test case 1
-        assert_eq!(55, pos_to_idx(ivec3(23, 1, 0), 32));
+        assert_eq!(55, pos_to_idx(ivec3(23, 1, 0), IVec3::splat(32)));
 
         // Test case 2
-        assert_eq!(0, pos_to_idx(ivec3(0, 0, 0), 32));
+        assert_eq!(0, pos_to_idx(ivec3(0, 0, 0), IVec3::splat(32)));
 
         // Test case 3
-        assert_eq!(33824, pos_to_idx(ivec3(32, 32, 32), 32));
+        assert_eq!(33824, pos_to_idx(ivec3(32, 32, 32), IVec3::splat(32)));
+
+        // Test case 4
+        assert_eq!(137, pos_to_idx(ivec3(1, 2, 3), ivec3(8, 5, 2)));
     }
 
     // Test generate_noise function
     #[test]
     fn test_generate_noise() {
         let mut result_set = HashSet::new();
-        generate_noise(ivec3(0, 0, 0), 1, 10, |p| {
+        for p in generate_noise(&mut StdRng::seed_from_u64(1), 1, 10) {
             result_set.insert(p);
-        });
+        }
         assert!(result_set.len() > 0);
+
+        // The same seed always gives the same positions
+        let noise = default_noise(&mut StdRng::seed_from_u64(5));
+        assert_eq!(noise, default_noise(&mut StdRng::seed_from_u64(5)));
+        assert_ne!(noise, default_noise(&mut StdRng::seed_from_u64(6)));
     }
 
     // Test get_centre function
     #[test]
     fn test_get_centre() {
-        assert_eq!(get_centre(3), ivec3(1, 1, 1));
-        assert_eq!(get_centre(4), ivec3(2, 2, 2));
+        assert_eq!(get_centre(IVec3::splat(3)), ivec3(1, 1, 1));
+        assert_eq!(get_centre(IVec3::splat(4)), ivec3(2, 2, 2));
+        assert_eq!(get_centre(ivec3(200, 200, 8)), ivec3(100, 100, 4));
     }
 
     // Test wrap function
     #[test]
     fn test_wrap() {
-        assert_eq!(wrap(ivec3(-1, -1, -1), 3), ivec3(2, 2, 2));
-        assert_eq!(wrap(ivec3(3, 3, 3), 3), ivec3(0, 0, 0));
+        assert_eq!(wrap(ivec3(-1, -1, -1), IVec3::splat(3)), ivec3(2, 2, 2));
+        assert_eq!(wrap(ivec3(3, 3, 3), IVec3::splat(3)), ivec3(0, 0, 0));
+        assert_eq!(wrap(ivec3(-1, 5, -9), ivec3(4, 5, 2)), ivec3(3, 0, 1));
+    }
+
+    // Test scale_bounds function
+    #[test]
+    fn test_scale_bounds() {
+        assert_eq!(scale_bounds(ivec3(50, 25, 10), ivec3(100, 25, 10), 1, 255), ivec3(100, 50, 20));
+        assert_eq!(scale_bounds(ivec3(50, 25, 10), ivec3(50, 25, 5), 1, 255), ivec3(25, 13, 5));
+        // The other sides stay within the limits
+        assert_eq!(scale_bounds(ivec3(50, 200, 2), ivec3(100, 200, 2), 1, 255), ivec3(100, 255, 4));
+        assert_eq!(scale_bounds(ivec3(8, 8, 8), ivec3(8, 8, 8), 1, 255), ivec3(8, 8, 8));
+    }
+
+    // Test in_bounds function
+    #[test]
+    fn test_in_bounds() {
+        assert!(in_bounds(ivec3(0, 0, 0), ivec3(2, 3, 4)));
+        assert!(in_bounds(ivec3(1, 2, 3), ivec3(2, 3, 4)));
+        assert!(!in_bounds(ivec3(2, 0, 0), ivec3(2, 3, 4)));
+        assert!(!in_bounds(ivec3(0, -1, 0), ivec3(2, 3, 4)));
     }
 
     // Test get_dist_to_centre function
     #[test]
     fn test_get_dist_to_centre() {
         let pos = ivec3(1, 1, 1);
-        let bounds = 4;
+        let bounds = IVec3::splat(4);
         let dist = get_dist_to_centre(pos, bounds);
         assert_relative_eq!(dist, 0.8660254, epsilon = 1e-6);
     }
@@ -138,4 +220,21 @@ mod utils {
         assert_relative_eq!(result.g(), 0.5, epsilon = 1e-6);
         assert_relative_eq!(result.b(), 0.0, epsilon = 1e-6);
     }
+
+    // Test cell_random function
+    #[test]
+    fn test_cell_random() {
+        let pos = ivec3(1, 2, 3);
+        let value = cell_random(1, 2, pos, 0);
+        assert!((0.0..1.0).contains(&value));
+
+        // The same inputs always give the same number
+        assert_eq!(value, cell_random(1, 2, pos, 0));
+
+        // Changing any input gives a different number
+        assert_ne!(value, cell_random(2, 2, pos, 0));
+        assert_ne!(value, cell_random(1, 3, pos, 0));
+        assert_ne!(value, cell_random(1, 2, ivec3(1, 2, 4), 0));
+        assert_ne!(value, cell_random(1, 2, pos, 1));
+    }
 }